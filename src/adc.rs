@@ -0,0 +1,490 @@
+//! Analog to Digital Converter (ADC)
+//!
+//! The 12-bit ADC has 16 channels. On the Due they are wired as follows:
+//!
+//! | Channel | Pin  | Due pin |
+//! |---------|------|---------|
+//! | AD0     | PA2  | A7      |
+//! | AD1     | PA3  | A6      |
+//! | AD2     | PA4  | A5      |
+//! | AD3     | PA6  | A4      |
+//! | AD4     | PA22 | A3      |
+//! | AD5     | PA23 | A2      |
+//! | AD6     | PA24 | A1      |
+//! | AD7     | PA16 | A0      |
+//! | AD8     | PB12 | SDA     |
+//! | AD9     | PB13 | SCL     |
+//! | AD10    | PB17 | A8      |
+//! | AD11    | PB18 | A9      |
+//! | AD12    | PB19 | A10     |
+//! | AD13    | PB20 | A11     |
+//! | AD14    | PB21 | D52     |
+//! | AD15    | -    | temperature sensor |
+//!
+//! The pins of AD0 to AD14 in `Analog` mode (`into_analog`) are the channels of the ADC, so
+//! a pin can't be driven as a digital output while it is sampled. AD15 has no pin and is
+//! selected with the `AD15` marker.
+//!
+//! Single conversions go through the `OneShot` implementation. For higher throughput the
+//! ADC can instead run free: the enabled channels are converted over and over, and the
+//! latest result of each channel is read from its `ADC_CDRx` register with `read_latest`.
+//! `OneShot::read` must not be used while the ADC is free-running.
+//!
+//! Conversions of the enabled channels can also be started by a hardware `Trigger`, e.g.
+//! TIOA of a TC channel for evenly spaced samples.
+//!
+//! # Differential inputs
+//!
+//! Channels pair up as (AD0, AD1), (AD2, AD3), ... (AD14, AD15), the even channel being the
+//! positive input. On the Due this gives A7/A6, A5/A4, A3/A2, A1/A0, SDA/SCL and A8/A9,
+//! A10/A11; AD14/AD15 can't be used as the temperature sensor occupies AD15. Reading a
+//! differential channel through `OneShot<Adc, i16, _>` returns a signed result.
+
+use core::cell::Cell;
+use core::convert::Infallible;
+
+use crate::gpio::pioa::{PA16, PA2, PA22, PA23, PA24, PA3, PA4, PA6};
+use crate::gpio::piob::{PB12, PB13, PB17, PB18, PB19, PB20, PB21};
+use crate::gpio::Analog;
+use crate::hal::adc::{Channel, OneShot};
+use crate::pmc::{Clocks, PeripheralId, Pmc};
+use sam3x8e::ADC;
+
+/// Highest ADC clock frequency
+const MAX_ADC_CLOCK_FREQ: u32 = 20_000_000;
+
+// Bits of ADC_ISR
+const ISR_DRDY: u32 = 1 << 24;
+const ISR_COMPE: u32 = 1 << 26;
+
+/// Channel of the temperature sensor
+const TEMP_SENSOR_CHANNEL: u8 = 15;
+
+/// Startup time of the temperature sensor, in microseconds
+const TEMP_SENSOR_STARTUP_US: u32 = 40;
+
+/// Output of the temperature sensor at 27 °C, in millivolts
+const TEMP_SENSOR_MV_AT_27C: f32 = 800.0;
+
+/// Slope of the temperature sensor, in millivolts per °C
+const TEMP_SENSOR_MV_PER_C: f32 = 2.65;
+
+/// ADC reference voltage on the Arduino Due, in millivolts
+const VREF_MV: f32 = 3300.0;
+
+/// Hardware sources that can start a conversion (`ADC_MR.TRGSEL`)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Trigger {
+    /// External trigger pin ADTRG (PA11)
+    Adtrg,
+    /// TIOA output of TC0
+    Tioa0,
+    /// TIOA output of TC1
+    Tioa1,
+    /// TIOA output of TC2
+    Tioa2,
+    /// PWM event line 0
+    PwmEvent0,
+    /// PWM event line 1
+    PwmEvent1,
+}
+
+impl Trigger {
+    fn trgsel(self) -> u8 {
+        match self {
+            Trigger::Adtrg => 0,
+            Trigger::Tioa0 => 1,
+            Trigger::Tioa1 => 2,
+            Trigger::Tioa2 => 3,
+            Trigger::PwmEvent0 => 4,
+            Trigger::PwmEvent1 => 5,
+        }
+    }
+}
+
+/// Programmable gain of a channel
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Gain {
+    /// 0.5, differential channels only
+    Half,
+    One,
+    Two,
+    /// 4, single-ended channels only
+    Four,
+}
+
+/// ADC configuration errors
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AdcError {
+    /// The gain isn't available in the channel's input mode, single-ended or differential
+    UnsupportedGain,
+    /// The channel is odd, a differential pair is selected by its even channel
+    NotAPairChannel,
+}
+
+/// Condition flagging a conversion result against the compare window (`ADC_EMR.CMPMODE`)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CompareMode {
+    /// The result is below the low threshold
+    Low,
+    /// The result is above the high threshold
+    High,
+    /// The result is within the thresholds
+    In,
+    /// The result is outside the thresholds
+    Out,
+}
+
+/// Analog to Digital Converter
+pub struct Adc {
+    adc: ADC,
+    clocks: Clocks,
+    /// Compare event read from `ADC_ISR` and not taken yet, reading `ADC_ISR` clears it
+    compare_event: Cell<bool>,
+}
+
+impl Adc {
+    /// Enables the ADC clock and configures the conversion timings
+    ///
+    /// The ADC clock is `MCK / ((PRESCAL + 1) * 2)`, with `PRESCAL` chosen so that it doesn't
+    /// exceed 20 MHz. The startup time is 768 ADC clock periods (about 40 us at 20 MHz).
+    pub fn new(adc: ADC, clocks: Clocks, pmc: &mut Pmc) -> Self {
+        pmc.enable_peripheral(PeripheralId::ADC);
+
+        adc.cr.write_with_zero(|w| w.swrst().set_bit());
+        adc.chdr.write_with_zero(|w| unsafe { w.bits(0xFFFF) });
+
+        let divider = (clocks.mck().0 + 2 * MAX_ADC_CLOCK_FREQ - 1) / (2 * MAX_ADC_CLOCK_FREQ);
+        let prescal = divider.max(1) - 1;
+        assert!(prescal <= 0xFF);
+
+        adc.mr.write_with_zero(|w| unsafe {
+            w.prescal()
+                .bits(prescal as u8)
+                .startup()
+                .sut768()
+                .tracktim()
+                .bits(15)
+                .settling()
+                .ast17()
+                .transfer()
+                .bits(1)
+        });
+
+        Adc {
+            adc,
+            clocks,
+            compare_event: Cell::new(false),
+        }
+    }
+
+    /// Includes a channel in the conversion sequence (`ADC_CHER`)
+    pub fn enable_channel<PIN>(&mut self, _pin: &PIN)
+    where
+        PIN: Channel<Adc, ID = u8>,
+    {
+        self.adc
+            .cher
+            .write_with_zero(|w| unsafe { w.bits(1 << PIN::channel()) });
+    }
+
+    /// Removes a channel from the conversion sequence (`ADC_CHDR`)
+    pub fn disable_channel<PIN>(&mut self, _pin: &PIN)
+    where
+        PIN: Channel<Adc, ID = u8>,
+    {
+        self.adc
+            .chdr
+            .write_with_zero(|w| unsafe { w.bits(1 << PIN::channel()) });
+    }
+
+    /// Starts converting the enabled channels continuously (`ADC_MR.FREERUN`)
+    pub fn start_free_running(&mut self) {
+        self.adc.mr.modify(|_, w| w.freerun().set_bit());
+        self.adc.cr.write_with_zero(|w| w.start().set_bit());
+    }
+
+    /// Stops free-running conversions
+    pub fn stop_free_running(&mut self) {
+        self.adc.mr.modify(|_, w| w.freerun().clear_bit());
+    }
+
+    /// Starts a conversion of the enabled channels on each rising edge of a hardware trigger
+    /// (`ADC_MR.TRGEN`/`TRGSEL`)
+    pub fn enable_trigger(&mut self, trigger: Trigger) {
+        self.adc
+            .mr
+            .modify(|_, w| unsafe { w.trgsel().bits(trigger.trgsel()).trgen().set_bit() });
+    }
+
+    /// Goes back to starting conversions by software only
+    pub fn disable_trigger(&mut self) {
+        self.adc.mr.modify(|_, w| w.trgen().clear_bit());
+    }
+
+    /// Returns the latest conversion result of a channel without blocking
+    ///
+    /// This reads `ADC_CDRx`, which clears the channel's end-of-conversion flag. The value
+    /// is 0 until the channel has been converted once.
+    pub fn read_latest<PIN>(&self, _pin: &PIN) -> u16
+    where
+        PIN: Channel<Adc, ID = u8>,
+    {
+        self.adc.cdr[PIN::channel() as usize].read().data().bits()
+    }
+
+    /// Returns whether a channel has been converted since its result was last read
+    pub fn is_conversion_done<PIN>(&self, _pin: &PIN) -> bool
+    where
+        PIN: Channel<Adc, ID = u8>,
+    {
+        self.read_isr() & (1 << PIN::channel()) != 0
+    }
+
+    /// Sets the gain of a channel (`ADC_CGR.GAINx`)
+    ///
+    /// Single-ended channels support gains of 1, 2 and 4, differential channels gains of
+    /// 0.5, 1 and 2, other gains fail with `AdcError::UnsupportedGain`. Set the gain after
+    /// `set_differential`.
+    pub fn set_gain<PIN>(&mut self, _pin: &PIN, gain: Gain) -> Result<(), AdcError>
+    where
+        PIN: Channel<Adc, ID = u8>,
+    {
+        let channel = PIN::channel();
+        let differential = self.adc.cor.read().bits() & (1 << (16 + channel)) != 0;
+
+        let bits = match (gain, differential) {
+            (Gain::Half, true) => 0,
+            (Gain::One, _) => 1,
+            (Gain::Two, _) => 2,
+            (Gain::Four, false) => 3,
+            _ => return Err(AdcError::UnsupportedGain),
+        };
+
+        let shift = 2 * u32::from(channel);
+        self.adc.mr.modify(|_, w| w.anach().set_bit());
+        self.adc
+            .cgr
+            .modify(|r, w| unsafe { w.bits((r.bits() & !(0b11 << shift)) | (bits << shift)) });
+        Ok(())
+    }
+
+    /// Centers the input range of a channel on VREF/2 (`ADC_COR.OFFx`)
+    pub fn set_offset<PIN>(&mut self, _pin: &PIN, offset: bool)
+    where
+        PIN: Channel<Adc, ID = u8>,
+    {
+        self.modify_cor(PIN::channel(), offset);
+    }
+
+    /// Switches a channel pair to differential input (`ADC_COR.DIFFx`)
+    ///
+    /// The pair is given by its even channel, which is the positive input; the odd channel
+    /// above it is the negative input and the result is read from the even channel. An odd
+    /// channel is rejected with `AdcError::NotAPairChannel`.
+    pub fn set_differential<PIN>(&mut self, _pin: &PIN, differential: bool) -> Result<(), AdcError>
+    where
+        PIN: Channel<Adc, ID = u8>,
+    {
+        let channel = PIN::channel();
+        if channel % 2 != 0 {
+            return Err(AdcError::NotAPairChannel);
+        }
+
+        self.modify_cor(16 + channel, differential);
+        Ok(())
+    }
+
+    fn modify_cor(&mut self, bit: u8, set: bool) {
+        self.adc.mr.modify(|_, w| w.anach().set_bit());
+        self.adc.cor.modify(|r, w| unsafe {
+            if set {
+                w.bits(r.bits() | (1 << bit))
+            } else {
+                w.bits(r.bits() & !(1 << bit))
+            }
+        });
+    }
+
+    /// Compares the conversion results of a channel against a window (`ADC_EMR`, `ADC_CWR`)
+    ///
+    /// A result matching `mode` raises a compare event, which `take_compare_event` reports
+    /// and `listen_compare` turns into an interrupt. The thresholds are 12-bit values; only
+    /// one channel can be compared at a time.
+    pub fn set_compare_window<PIN>(&mut self, _pin: &PIN, low: u16, high: u16, mode: CompareMode)
+    where
+        PIN: Channel<Adc, ID = u8>,
+    {
+        self.adc.cwr.write_with_zero(|w| unsafe {
+            w.lowthres()
+                .bits(low.min(0xFFF))
+                .highthres()
+                .bits(high.min(0xFFF))
+        });
+        self.adc.emr.modify(|_, w| {
+            let w = unsafe { w.cmpsel().bits(PIN::channel()) }
+                .cmpall()
+                .clear_bit();
+            match mode {
+                CompareMode::Low => w.cmpmode().low(),
+                CompareMode::High => w.cmpmode().high(),
+                CompareMode::In => w.cmpmode().in_(),
+                CompareMode::Out => w.cmpmode().out(),
+            }
+        });
+        self.compare_event.set(false);
+    }
+
+    /// Returns whether a result matched the compare window since the last call, and clears the
+    /// event (`ADC_ISR.COMPE`)
+    pub fn take_compare_event(&mut self) -> bool {
+        self.read_isr();
+        self.compare_event.replace(false)
+    }
+
+    /// Raises the ADC interrupt on compare events (`ADC_IER.COMPE`)
+    pub fn listen_compare(&mut self) {
+        self.adc.ier.write_with_zero(|w| w.compe().set_bit());
+    }
+
+    /// Stops raising the ADC interrupt on compare events
+    pub fn unlisten_compare(&mut self) {
+        self.adc.idr.write_with_zero(|w| w.compe().set_bit());
+    }
+
+    /// Reads `ADC_ISR`, keeping the compare event it clears for `take_compare_event`
+    fn read_isr(&self) -> u32 {
+        let isr = self.adc.isr.read().bits();
+        if isr & ISR_COMPE != 0 {
+            self.compare_event.set(true);
+        }
+        isr
+    }
+
+    /// Measures the chip temperature with the on-chip sensor, in °C
+    ///
+    /// The sensor is turned on (`ADC_ACR.TSON`) on the first call, waiting for its 40 us
+    /// startup time, and then left on. Its output (0.8 V at 27 °C, 2.65 mV/°C, typical
+    /// values) is converted on AD15 against a 3.3 V reference, so AD15 must keep a gain of 1
+    /// and no offset. The typical values are off by up to 15% on a given chip; calibrate at a
+    /// known temperature for better accuracy.
+    pub fn read_temperature(&mut self) -> f32 {
+        if self.adc.acr.read().tson().bit_is_clear() {
+            self.adc.acr.modify(|_, w| w.tson().set_bit());
+            cortex_m::asm::delay(self.clocks.hclk().0 / 1_000_000 * TEMP_SENSOR_STARTUP_US);
+        }
+
+        let mv = f32::from(self.convert(TEMP_SENSOR_CHANNEL)) * VREF_MV / 4095.0;
+        27.0 + (mv - TEMP_SENSOR_MV_AT_27C) / TEMP_SENSOR_MV_PER_C
+    }
+
+    /// Converts a single channel, blocking until the result is available
+    ///
+    /// The other channels are disabled during the conversion and the enabled channels
+    /// (`ADC_CHSR`) are restored afterwards.
+    fn convert(&mut self, channel: u8) -> u16 {
+        let mask = 1 << channel;
+        let enabled = self.adc.chsr.read().bits() & 0xFFFF;
+
+        self.adc
+            .chdr
+            .write_with_zero(|w| unsafe { w.bits(enabled & !mask) });
+        self.adc.cher.write_with_zero(|w| unsafe { w.bits(mask) });
+
+        self.adc.cr.write_with_zero(|w| w.start().set_bit());
+        while self.read_isr() & ISR_DRDY == 0 {}
+
+        // reading LCDR clears DRDY
+        let data = self.adc.lcdr.read().ldata().bits();
+
+        self.adc.chdr.write_with_zero(|w| unsafe { w.bits(mask) });
+        self.adc
+            .cher
+            .write_with_zero(|w| unsafe { w.bits(enabled) });
+
+        data
+    }
+
+    /// Releases the ADC peripheral
+    pub fn free(self) -> ADC {
+        self.adc
+    }
+}
+
+macro_rules! pin_channels {
+    ($($PXi:ident: $id:expr,)+) => {
+        $(
+            impl Channel<Adc> for $PXi<Analog> {
+                type ID = u8;
+
+                fn channel() -> u8 {
+                    $id
+                }
+            }
+        )+
+    }
+}
+
+pin_channels! {
+    PA2: 0,
+    PA3: 1,
+    PA4: 2,
+    PA6: 3,
+    PA22: 4,
+    PA23: 5,
+    PA24: 6,
+    PA16: 7,
+    PB12: 8,
+    PB13: 9,
+    PB17: 10,
+    PB18: 11,
+    PB19: 12,
+    PB20: 13,
+    PB21: 14,
+}
+
+/// ADC channel 15, wired to the internal temperature sensor
+pub struct AD15;
+
+impl Channel<Adc> for AD15 {
+    type ID = u8;
+
+    fn channel() -> u8 {
+        TEMP_SENSOR_CHANNEL
+    }
+}
+
+impl<PIN> OneShot<Adc, u16, PIN> for Adc
+where
+    PIN: Channel<Adc, ID = u8>,
+{
+    type Error = Infallible;
+
+    /// Converts a single channel, blocking until the result is available
+    fn read(&mut self, _pin: &mut PIN) -> nb::Result<u16, Infallible> {
+        Ok(self.convert(PIN::channel()))
+    }
+}
+
+impl<PIN> OneShot<Adc, i16, PIN> for Adc
+where
+    PIN: Channel<Adc, ID = u8>,
+{
+    type Error = Infallible;
+
+    /// Converts a single channel as a signed value
+    ///
+    /// Differential results are centered on 0 (-2048 to 2047); single-ended results are
+    /// returned unchanged.
+    fn read(&mut self, _pin: &mut PIN) -> nb::Result<i16, Infallible> {
+        let channel = PIN::channel();
+        let data = self.convert(channel) as i16;
+
+        if self.adc.cor.read().bits() & (1 << (16 + channel)) != 0 {
+            Ok(data - 2048)
+        } else {
+            Ok(data)
+        }
+    }
+}
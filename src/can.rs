@@ -0,0 +1,531 @@
+//! Controller Area Network (CAN)
+//!
+//! The SAM3X8E has two CAN 2.0B controllers: CAN0 (CANTX0 on PA0, CANRX0 on PA1) and CAN1
+//! (CANTX1 on PB14, CANRX1 on PB15). Both need an external transceiver; on the Due they are
+//! only brought out to the CANTX/CANRX header pins.
+//!
+//! Each controller has eight mailboxes, each holding one frame with its own mode, acceptance
+//! mask and identifier (`CAN_MMRx`, `CAN_MAMx`, `CAN_MIDx`) and data (`CAN_MDLx`/`CAN_MDHx`).
+//! This driver splits them up as follows:
+//!
+//! | Mailboxes | Use                                |
+//! |-----------|------------------------------------|
+//! | 0 - 2     | receive frames with a standard ID  |
+//! | 3 - 5     | receive frames with an extended ID |
+//! | 6 - 7     | transmit                           |
+//!
+//! All frames are accepted. When several receive mailboxes are full, `receive` returns the
+//! oldest frame according to the mailbox timestamps.
+//!
+//! # Bit timing
+//!
+//! A bit is made of 8 to 25 time quanta of `(BRP + 1) / MCK`, split into the
+//! synchronization, propagation, phase 1 and phase 2 segments (`CAN_BR`). The constructors
+//! look for the largest number of quanta that divides MCK exactly for the requested bitrate
+//! and place the sample point at about 75 %. At 84 MHz, 125 kbit/s to 1 Mbit/s are all
+//! reachable.
+
+use crate::gpio::pioa::{PA0, PA1};
+use crate::gpio::piob::{PB14, PB15};
+use crate::gpio::{Alternate, PeriphA};
+use crate::hal::can::{self, ExtendedId, Id, StandardId};
+use crate::pmc::{Clocks, PeripheralId, Pmc};
+use crate::time::Bps;
+use sam3x8e::{CAN0, CAN1};
+
+/// Expands `$body` for the register of a mailbox family (`mmr0` to `mmr7`, `mid0` to
+/// `mid7`...) picked by a mailbox index, bound to `$reg`
+///
+/// The PAC gives every mailbox its own register types instead of an array of register sets.
+macro_rules! per_mailbox {
+    (
+        $can:expr,
+        $mailbox:expr,
+        $r0:ident $r1:ident $r2:ident $r3:ident $r4:ident $r5:ident $r6:ident $r7:ident,
+        |$reg:ident| $body:expr
+    ) => {
+        match $mailbox {
+            0 => {
+                let $reg = &$can.$r0;
+                $body
+            }
+            1 => {
+                let $reg = &$can.$r1;
+                $body
+            }
+            2 => {
+                let $reg = &$can.$r2;
+                $body
+            }
+            3 => {
+                let $reg = &$can.$r3;
+                $body
+            }
+            4 => {
+                let $reg = &$can.$r4;
+                $body
+            }
+            5 => {
+                let $reg = &$can.$r5;
+                $body
+            }
+            6 => {
+                let $reg = &$can.$r6;
+                $body
+            }
+            _ => {
+                let $reg = &$can.$r7;
+                $body
+            }
+        }
+    };
+}
+
+macro_rules! mmr {
+    ($can:expr, $mailbox:expr, |$reg:ident| $body:expr) => {
+        per_mailbox!($can, $mailbox, mmr0 mmr1 mmr2 mmr3 mmr4 mmr5 mmr6 mmr7, |$reg| $body)
+    };
+}
+
+macro_rules! mam {
+    ($can:expr, $mailbox:expr, |$reg:ident| $body:expr) => {
+        per_mailbox!($can, $mailbox, mam0 mam1 mam2 mam3 mam4 mam5 mam6 mam7, |$reg| $body)
+    };
+}
+
+macro_rules! mid {
+    ($can:expr, $mailbox:expr, |$reg:ident| $body:expr) => {
+        per_mailbox!($can, $mailbox, mid0 mid1 mid2 mid3 mid4 mid5 mid6 mid7, |$reg| $body)
+    };
+}
+
+macro_rules! msr {
+    ($can:expr, $mailbox:expr, |$reg:ident| $body:expr) => {
+        per_mailbox!($can, $mailbox, msr0 msr1 msr2 msr3 msr4 msr5 msr6 msr7, |$reg| $body)
+    };
+}
+
+macro_rules! mdl {
+    ($can:expr, $mailbox:expr, |$reg:ident| $body:expr) => {
+        per_mailbox!($can, $mailbox, mdl0 mdl1 mdl2 mdl3 mdl4 mdl5 mdl6 mdl7, |$reg| $body)
+    };
+}
+
+macro_rules! mdh {
+    ($can:expr, $mailbox:expr, |$reg:ident| $body:expr) => {
+        per_mailbox!($can, $mailbox, mdh0 mdh1 mdh2 mdh3 mdh4 mdh5 mdh6 mdh7, |$reg| $body)
+    };
+}
+
+macro_rules! mcr {
+    ($can:expr, $mailbox:expr, |$reg:ident| $body:expr) => {
+        per_mailbox!($can, $mailbox, mcr0 mcr1 mcr2 mcr3 mcr4 mcr5 mcr6 mcr7, |$reg| $body)
+    };
+}
+
+/// Receive mailboxes for frames with a standard ID
+const RX_STANDARD_MAILBOXES: [usize; 3] = [0, 1, 2];
+
+/// Receive mailboxes for frames with an extended ID
+const RX_EXTENDED_MAILBOXES: [usize; 3] = [3, 4, 5];
+
+/// Transmit mailboxes
+const TX_MAILBOXES: [usize; 2] = [6, 7];
+
+// `CAN_MMRx.MOT` values
+const MOT_RX: u32 = 1 << 24;
+const MOT_TX: u32 = 3 << 24;
+
+// Bits of `CAN_MIDx` and `CAN_MAMx`
+const MIDE: u32 = 1 << 29;
+const MIDVA_SHIFT: u32 = 18;
+
+// Bits of `CAN_MSRx` and `CAN_MCRx`
+const MDLC_SHIFT: u32 = 16;
+const MRTR: u32 = 1 << 20;
+const MRDY: u32 = 1 << 23;
+const MMI: u32 = 1 << 24;
+const MTCR: u32 = 1 << 23;
+
+/// CAN error
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CanError {
+    /// A receive mailbox was full when another frame for it came in (`CAN_MSRx.MMI`)
+    Overrun,
+    /// A transmitted bit was read back with the other level (`CAN_SR.BERR`)
+    Bit,
+    /// Six consecutive bits of the same level were received (`CAN_SR.SERR`)
+    Stuff,
+    /// A received CRC didn't match (`CAN_SR.CERR`)
+    Crc,
+    /// A fixed-form field held an illegal level (`CAN_SR.FERR`)
+    Form,
+    /// A transmitted frame wasn't acknowledged (`CAN_SR.AERR`)
+    Acknowledge,
+    /// The controller went bus off after too many errors (`CAN_SR.BOFF`)
+    BusOff,
+    /// The requested bitrate is zero or can't be derived exactly from MCK
+    Bitrate,
+}
+
+impl can::Error for CanError {
+    fn kind(&self) -> can::ErrorKind {
+        match self {
+            CanError::Overrun => can::ErrorKind::Overrun,
+            CanError::Bit => can::ErrorKind::Bit,
+            CanError::Stuff => can::ErrorKind::Stuff,
+            CanError::Crc => can::ErrorKind::Crc,
+            CanError::Form => can::ErrorKind::Form,
+            CanError::Acknowledge => can::ErrorKind::Acknowledge,
+            CanError::BusOff | CanError::Bitrate => can::ErrorKind::Other,
+        }
+    }
+}
+
+/// CAN frame, with up to 8 data bytes
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Frame {
+    id: Id,
+    remote: bool,
+    dlc: u8,
+    data: [u8; 8],
+}
+
+impl can::Frame for Frame {
+    fn new(id: impl Into<Id>, data: &[u8]) -> Option<Self> {
+        if data.len() > 8 {
+            return None;
+        }
+
+        let mut frame = Frame {
+            id: id.into(),
+            remote: false,
+            dlc: data.len() as u8,
+            data: [0; 8],
+        };
+        frame.data[..data.len()].copy_from_slice(data);
+
+        Some(frame)
+    }
+
+    fn new_remote(id: impl Into<Id>, dlc: usize) -> Option<Self> {
+        if dlc > 8 {
+            return None;
+        }
+
+        Some(Frame {
+            id: id.into(),
+            remote: true,
+            dlc: dlc as u8,
+            data: [0; 8],
+        })
+    }
+
+    fn is_extended(&self) -> bool {
+        match self.id {
+            Id::Standard(_) => false,
+            Id::Extended(_) => true,
+        }
+    }
+
+    fn is_remote_frame(&self) -> bool {
+        self.remote
+    }
+
+    fn id(&self) -> Id {
+        self.id
+    }
+
+    fn dlc(&self) -> usize {
+        usize::from(self.dlc)
+    }
+
+    fn data(&self) -> &[u8] {
+        if self.remote {
+            &[]
+        } else {
+            &self.data[..usize::from(self.dlc)]
+        }
+    }
+}
+
+/// Pin usable as the CANTX line of a CAN controller
+pub trait TxPin<CAN> {}
+
+/// Pin usable as the CANRX line of a CAN controller
+pub trait RxPin<CAN> {}
+
+/// Pins a CAN controller can be constructed from, as `(tx, rx)`
+pub trait Pins<CAN> {}
+
+impl<CAN, TX, RX> Pins<CAN> for (TX, RX)
+where
+    TX: TxPin<CAN>,
+    RX: RxPin<CAN>,
+{
+}
+
+impl TxPin<CAN0> for PA0<Alternate<PeriphA>> {}
+impl RxPin<CAN0> for PA1<Alternate<PeriphA>> {}
+
+impl TxPin<CAN1> for PB14<Alternate<PeriphA>> {}
+impl RxPin<CAN1> for PB15<Alternate<PeriphA>> {}
+
+/// CAN controller
+pub struct Can<CAN, PINS> {
+    can: CAN,
+    pins: PINS,
+}
+
+/// Segments of a bit, as the values of the `CAN_BR` fields
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct BitTiming {
+    brp: u8,
+    sjw: u8,
+    propag: u8,
+    phase1: u8,
+    phase2: u8,
+}
+
+/// Computes `CAN_BR` for a non-zero bitrate, if MCK can be divided down to it exactly
+///
+/// Phase 2 takes about a quarter of the bit, at least the 2 quanta of information
+/// processing time; propagation and phase 1 share the rest.
+fn bit_timing(mck: u32, bitrate: u32) -> Option<BitTiming> {
+    (8..=25).rev().find_map(|quanta: u32| {
+        let quanta_rate = bitrate.checked_mul(quanta).filter(|&rate| rate != 0)?;
+        let prescaler = mck / quanta_rate;
+        if prescaler < 2 || prescaler > 128 || prescaler * quanta_rate != mck {
+            return None;
+        }
+
+        let phase2 = (quanta / 4).max(quanta.saturating_sub(17)).max(2).min(8);
+        let rest = quanta - 1 - phase2;
+        let phase1 = (rest / 2).min(8);
+        let propag = rest - phase1;
+        let sjw = phase1.min(phase2).min(4);
+
+        Some(BitTiming {
+            brp: (prescaler - 1) as u8,
+            sjw: (sjw - 1) as u8,
+            propag: (propag - 1) as u8,
+            phase1: (phase1 - 1) as u8,
+            phase2: (phase2 - 1) as u8,
+        })
+    })
+}
+
+macro_rules! hal {
+    ($($CANX:ident: ($canX:ident, $pid:ident),)+) => {
+        $(
+            impl<PINS> Can<$CANX, PINS> {
+                /// Configures a CAN controller for a bitrate and joins the bus
+                ///
+                /// The controller takes part in bus traffic once it has seen 11 recessive
+                /// bits in a row (`CAN_SR.WAKEUP`). Fails with `CanError::Bitrate` if the
+                /// bitrate is zero or can't be derived exactly from MCK.
+                pub fn $canX(
+                    can: $CANX,
+                    pins: PINS,
+                    bitrate: Bps,
+                    clocks: Clocks,
+                    pmc: &mut Pmc,
+                ) -> Result<Self, CanError>
+                where
+                    PINS: Pins<$CANX>,
+                {
+                    let timing = bit_timing(clocks.mck().0, bitrate.0).ok_or(CanError::Bitrate)?;
+
+                    pmc.enable_peripheral(PeripheralId::$pid);
+
+                    can.mr.write_with_zero(|w| w.canen().clear_bit());
+                    can.idr.write_with_zero(|w| unsafe { w.bits(0xFFFF_FFFF) });
+
+                    can.br.write_with_zero(|w| unsafe {
+                        w.brp()
+                            .bits(timing.brp)
+                            .sjw()
+                            .bits(timing.sjw)
+                            .propag()
+                            .bits(timing.propag)
+                            .phase1()
+                            .bits(timing.phase1)
+                            .phase2()
+                            .bits(timing.phase2)
+                    });
+
+                    let mut can = Can { can, pins };
+
+                    // compare the ID version only, so that every frame of that version is
+                    // accepted
+                    for &mailbox in RX_STANDARD_MAILBOXES.iter() {
+                        can.set_mailbox_mode(mailbox, MOT_RX, MIDE, 0);
+                    }
+                    for &mailbox in RX_EXTENDED_MAILBOXES.iter() {
+                        can.set_mailbox_mode(mailbox, MOT_RX, MIDE, MIDE);
+                    }
+                    for &mailbox in TX_MAILBOXES.iter() {
+                        can.set_mailbox_mode(mailbox, MOT_TX, 0, 0);
+                    }
+
+                    can.can.mr.write_with_zero(|w| w.canen().set_bit());
+
+                    Ok(can)
+                }
+
+                /// Returns the transmit and receive error counters (`CAN_ECR.TEC`/`REC`)
+                pub fn error_counters(&self) -> (u8, u8) {
+                    let ecr = self.can.ecr.read();
+
+                    (ecr.tec().bits(), ecr.rec().bits())
+                }
+
+                /// Disables the controller and releases the peripheral and pins
+                pub fn free(self) -> ($CANX, PINS) {
+                    self.can.mr.write_with_zero(|w| w.canen().clear_bit());
+
+                    (self.can, self.pins)
+                }
+
+                /// Sets the object type (`CAN_MMRx.MOT`) and acceptance filter of a mailbox,
+                /// disabling it meanwhile, and hands receive mailboxes to the controller
+                fn set_mailbox_mode(&mut self, mailbox: usize, mot: u32, mam: u32, mid: u32) {
+                    let can = &self.can;
+                    mmr!(can, mailbox, |reg| reg.write_with_zero(|w| unsafe { w.bits(0) }));
+                    mam!(can, mailbox, |reg| reg.write_with_zero(|w| unsafe { w.bits(mam) }));
+                    mid!(can, mailbox, |reg| reg.write_with_zero(|w| unsafe { w.bits(mid) }));
+                    mmr!(can, mailbox, |reg| reg.write_with_zero(|w| unsafe { w.bits(mot) }));
+                    if mot == MOT_RX {
+                        mcr!(can, mailbox, |reg| reg.write_with_zero(|w| unsafe { w.bits(MTCR) }));
+                    }
+                }
+            }
+
+            impl<PINS> can::Can for Can<$CANX, PINS> {
+                type Frame = Frame;
+                type Error = CanError;
+
+                /// Queues a frame in a free transmit mailbox
+                ///
+                /// Pending frames are never replaced, so this always returns `Ok(None)` or
+                /// `WouldBlock` when both transmit mailboxes are busy.
+                fn transmit(&mut self, frame: &Frame) -> nb::Result<Option<Frame>, CanError> {
+                    if self.can.sr.read().boff().bit_is_set() {
+                        return Err(nb::Error::Other(CanError::BusOff));
+                    }
+
+                    let mailbox = TX_MAILBOXES
+                        .iter()
+                        .cloned()
+                        .find(|&mailbox| {
+                            msr!(self.can, mailbox, |reg| reg.read().bits()) & MRDY != 0
+                        })
+                        .ok_or(nb::Error::WouldBlock)?;
+
+                    let mid = match frame.id {
+                        Id::Standard(id) => u32::from(id.as_raw()) << MIDVA_SHIFT,
+                        Id::Extended(id) => MIDE | id.as_raw(),
+                    };
+                    let data = frame.data;
+                    let mdl = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+                    let mdh = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
+
+                    mid!(self.can, mailbox, |reg| reg.write_with_zero(|w| unsafe { w.bits(mid) }));
+                    mdl!(self.can, mailbox, |reg| reg.write_with_zero(|w| unsafe { w.bits(mdl) }));
+                    mdh!(self.can, mailbox, |reg| reg.write_with_zero(|w| unsafe { w.bits(mdh) }));
+
+                    let mut mcr = MTCR | (u32::from(frame.dlc) << MDLC_SHIFT);
+                    if frame.remote {
+                        mcr |= MRTR;
+                    }
+                    mcr!(self.can, mailbox, |reg| reg.write_with_zero(|w| unsafe { w.bits(mcr) }));
+
+                    Ok(None)
+                }
+
+                /// Returns the oldest received frame
+                ///
+                /// Bus errors flagged in `CAN_SR` are reported first; reading `CAN_SR`
+                /// clears them.
+                fn receive(&mut self) -> nb::Result<Frame, CanError> {
+                    let sr = self.can.sr.read();
+                    let err = if sr.boff().bit_is_set() {
+                        Some(CanError::BusOff)
+                    } else if sr.berr().bit_is_set() {
+                        Some(CanError::Bit)
+                    } else if sr.serr().bit_is_set() {
+                        Some(CanError::Stuff)
+                    } else if sr.cerr().bit_is_set() {
+                        Some(CanError::Crc)
+                    } else if sr.ferr().bit_is_set() {
+                        Some(CanError::Form)
+                    } else if sr.aerr().bit_is_set() {
+                        Some(CanError::Acknowledge)
+                    } else {
+                        None
+                    };
+                    if let Some(err) = err {
+                        return Err(nb::Error::Other(err));
+                    }
+
+                    // the timer counts bit times; the frame received the longest ago is the
+                    // one whose timestamp lies furthest behind it
+                    let now = self.can.tim.read().timer().bits();
+                    let mut oldest = None;
+                    let rx_mailboxes = RX_STANDARD_MAILBOXES
+                        .iter()
+                        .chain(RX_EXTENDED_MAILBOXES.iter());
+                    for &mailbox in rx_mailboxes {
+                        if sr.bits() & (1 << mailbox) == 0 {
+                            continue;
+                        }
+
+                        let msr = msr!(self.can, mailbox, |reg| reg.read().bits());
+                        if msr & MMI != 0 {
+                            // reading MSR cleared MMI, the frame is still there for next time
+                            return Err(nb::Error::Other(CanError::Overrun));
+                        }
+
+                        let age = now.wrapping_sub(msr as u16);
+                        match oldest {
+                            Some((_, _, oldest_age)) if oldest_age >= age => {}
+                            _ => oldest = Some((mailbox, msr, age)),
+                        }
+                    }
+                    let (mailbox, msr, _) = oldest.ok_or(nb::Error::WouldBlock)?;
+
+                    let mid = mid!(self.can, mailbox, |reg| reg.read().bits());
+                    let id = if mid & MIDE != 0 {
+                        Id::Extended(ExtendedId::new(mid & 0x1FFF_FFFF).unwrap())
+                    } else {
+                        let id = (mid >> MIDVA_SHIFT) as u16 & 0x7FF;
+                        Id::Standard(StandardId::new(id).unwrap())
+                    };
+                    let dlc = ((msr >> MDLC_SHIFT) & 0xF).min(8) as u8;
+
+                    let mut data = [0; 8];
+                    let mdl = mdl!(self.can, mailbox, |reg| reg.read().bits());
+                    let mdh = mdh!(self.can, mailbox, |reg| reg.read().bits());
+                    data[..4].copy_from_slice(&mdl.to_le_bytes());
+                    data[4..].copy_from_slice(&mdh.to_le_bytes());
+
+                    // give the mailbox back to the controller
+                    mcr!(self.can, mailbox, |reg| reg.write_with_zero(|w| unsafe { w.bits(MTCR) }));
+
+                    Ok(Frame {
+                        id,
+                        remote: msr & MRTR != 0,
+                        dlc,
+                        data,
+                    })
+                }
+            }
+        )+
+    }
+}
+
+hal! {
+    CAN0: (can0, CAN0),
+    CAN1: (can1, CAN1),
+}
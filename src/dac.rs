@@ -0,0 +1,158 @@
+//! Digital to Analog Converter Controller (DACC)
+//!
+//! The two 12-bit outputs are DAC0 (PB15) and DAC1 (PB16). Conversions run free: each
+//! value written is converted as soon as possible. Tag mode is used, so every sample written
+//! to `DACC_CDR` carries the channel it is meant for.
+//!
+//! A buffer of samples can also be played out by the Peripheral DMA Controller (PDC) with
+//! `play`, without the CPU writing each sample. The samples go out at the DAC conversion
+//! rate.
+//!
+//! With a hardware `Trigger`, each conversion waits for a rising edge of the trigger
+//! instead, e.g. TIOA of a TC channel, so that samples go out at a fixed rate.
+
+use crate::pdc::Transfer;
+use crate::pmc::{PeripheralId, Pmc};
+use sam3x8e::DACC;
+
+/// Largest value of the 12-bit DAC
+const MAX_VALUE: u16 = 0xFFF;
+
+/// DAC output channels
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Channel {
+    /// DAC0 (PB15)
+    Channel0 = 0,
+    /// DAC1 (PB16)
+    Channel1 = 1,
+}
+
+/// Hardware sources that can start a conversion (`DACC_MR.TRGSEL`)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Trigger {
+    /// External trigger pin DATRG
+    Datrg,
+    /// TIOA output of TC0
+    Tioa0,
+    /// TIOA output of TC1
+    Tioa1,
+    /// TIOA output of TC2
+    Tioa2,
+    /// PWM event line 0
+    PwmEvent0,
+    /// PWM event line 1
+    PwmEvent1,
+}
+
+impl Trigger {
+    fn trgsel(self) -> u8 {
+        match self {
+            Trigger::Datrg => 0,
+            Trigger::Tioa0 => 1,
+            Trigger::Tioa1 => 2,
+            Trigger::Tioa2 => 3,
+            Trigger::PwmEvent0 => 4,
+            Trigger::PwmEvent1 => 5,
+        }
+    }
+}
+
+/// Digital to Analog Converter
+pub struct Dac {
+    dacc: DACC,
+}
+
+impl Dac {
+    /// Enables the DACC clock and configures it for free-running conversions
+    ///
+    /// The startup time is 1728 DAC clock periods (MCK / 2), which covers the 40 us needed
+    /// at up to 84 MHz. The outputs are refreshed every `1024 * 8` DAC clock periods.
+    pub fn new(dacc: DACC, pmc: &mut Pmc) -> Self {
+        pmc.enable_peripheral(PeripheralId::DACC);
+
+        dacc.cr.write_with_zero(|w| w.swrst().set_bit());
+
+        dacc.mr.write_with_zero(|w| unsafe {
+            w.trgen()
+                .dis()
+                .word()
+                .half()
+                .tag()
+                .en()
+                .refresh()
+                .bits(8)
+                .startup()
+                ._1728()
+        });
+
+        // analog current settings required for both channels to work
+        dacc.acr.write_with_zero(|w| unsafe {
+            w.ibctlch0()
+                .bits(0b10)
+                .ibctlch1()
+                .bits(0b10)
+                .ibctldaccore()
+                .bits(0b01)
+        });
+
+        Dac { dacc }
+    }
+
+    /// Enables an output channel (`DACC_CHER`)
+    pub fn enable(&mut self, channel: Channel) {
+        self.dacc
+            .cher
+            .write_with_zero(|w| unsafe { w.bits(1 << (channel as u8)) });
+    }
+
+    /// Disables an output channel (`DACC_CHDR`)
+    pub fn disable(&mut self, channel: Channel) {
+        self.dacc
+            .chdr
+            .write_with_zero(|w| unsafe { w.bits(1 << (channel as u8)) });
+    }
+
+    /// Returns whether an output channel is enabled
+    pub fn is_enabled(&self, channel: Channel) -> bool {
+        self.dacc.chsr.read().bits() & (1 << (channel as u8)) != 0
+    }
+
+    /// Converts each sample on a rising edge of a hardware trigger (`DACC_MR.TRGEN`/`TRGSEL`)
+    pub fn enable_trigger(&mut self, trigger: Trigger) {
+        self.dacc
+            .mr
+            .modify(|_, w| unsafe { w.trgsel().bits(trigger.trgsel()).trgen().en() });
+    }
+
+    /// Goes back to converting samples as soon as they are written
+    pub fn disable_trigger(&mut self) {
+        self.dacc.mr.modify(|_, w| w.trgen().dis());
+    }
+
+    /// Sets the output value of a channel, clamped to 12 bits
+    ///
+    /// Blocks until the DACC can accept a new sample.
+    pub fn set_value(&mut self, channel: Channel, value: u16) {
+        let value = u32::from(value.min(MAX_VALUE)) | ((channel as u32) << 12);
+
+        while self.dacc.isr.read().txrdy().bit_is_clear() {}
+        self.dacc
+            .cdr
+            .write_with_zero(|w| unsafe { w.data().bits(value) });
+    }
+
+    /// Starts playing a buffer of samples through the PDC (`DACC_TPR`/`DACC_TCR`)
+    ///
+    /// Samples for DAC0 are plain 12-bit values. Samples for DAC1 must have bit 12 set, as
+    /// the channel is taken from bits 12-13 of each sample in tag mode. The `Transfer` owns
+    /// the DAC and the buffer until it is done or stopped. Panics if there are more than
+    /// 65535 samples.
+    pub fn play(self, samples: &'static [u16]) -> Transfer<&'static [u16], Dac> {
+        Transfer::write(self, samples)
+    }
+
+    /// Releases the DACC peripheral
+    pub fn free(self) -> DACC {
+        self.dacc
+    }
+}
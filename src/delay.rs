@@ -1,12 +1,37 @@
 //! Delays
+//!
+//! The SysTick reload value is 24 bits wide, about 200 ms at 84 MHz. Longer delays are
+//! split into several SysTick periods.
+//!
+//! With the `eh1` feature, `Delay` also implements the embedded-hal 1.0 `DelayNs` trait.
 
-use cast::u32;
+use core::cmp;
 use cortex_m::peripheral::syst::SystClkSource;
 use cortex_m::peripheral::SYST;
 
 use crate::pmc::Clocks;
+use crate::time::Hertz;
 use hal::blocking::delay::{DelayMs, DelayUs};
 
+/// Largest SysTick reload value
+const MAX_RVR: u32 = 0x00FF_FFFF;
+
+/// Converts a delay in microseconds, of any width up to `u32`, to core clock cycles
+fn us_to_ticks<T: Into<u32>>(us: T, hclk: Hertz) -> u64 {
+    u64::from(us.into()) * u64::from(hclk.0) / 1_000_000
+}
+
+/// Converts a delay in milliseconds, of any width up to `u32`, to core clock cycles
+fn ms_to_ticks<T: Into<u32>>(ms: T, hclk: Hertz) -> u64 {
+    u64::from(ms.into()) * u64::from(hclk.0) / 1_000
+}
+
+/// Converts a delay in nanoseconds to core clock cycles, rounding up
+#[cfg_attr(not(feature = "eh1"), allow(dead_code))]
+fn ns_to_ticks(ns: u32, hclk: Hertz) -> u64 {
+    (u64::from(ns) * u64::from(hclk.0) + 999_999_999) / 1_000_000_000
+}
+
 /// System timer (SysTick) as a delay provider
 pub struct Delay {
     clocks: Clocks,
@@ -25,50 +50,132 @@ impl Delay {
     pub fn free(self) -> SYST {
         self.syst
     }
+
+    /// Waits for a number of core clock cycles
+    fn delay_ticks(&mut self, mut ticks: u64) {
+        while ticks != 0 {
+            let rvr = cmp::min(ticks, u64::from(MAX_RVR)) as u32;
+
+            self.syst.set_reload(rvr);
+            self.syst.clear_current();
+            self.syst.enable_counter();
+
+            while !self.syst.has_wrapped() {}
+
+            self.syst.disable_counter();
+
+            ticks -= u64::from(rvr);
+        }
+    }
 }
 
 impl DelayMs<u32> for Delay {
     fn delay_ms(&mut self, ms: u32) {
-        self.delay_us(ms * 1_000);
+        let ticks = ms_to_ticks(ms, self.clocks.hclk());
+        self.delay_ticks(ticks);
     }
 }
 
 impl DelayMs<u16> for Delay {
     fn delay_ms(&mut self, ms: u16) {
-        self.delay_ms(u32(ms));
+        let ticks = ms_to_ticks(ms, self.clocks.hclk());
+        self.delay_ticks(ticks);
     }
 }
 
 impl DelayMs<u8> for Delay {
     fn delay_ms(&mut self, ms: u8) {
-        self.delay_ms(u32(ms));
+        let ticks = ms_to_ticks(ms, self.clocks.hclk());
+        self.delay_ticks(ticks);
     }
 }
 
 impl DelayUs<u32> for Delay {
     fn delay_us(&mut self, us: u32) {
-        let rvr = us * (self.clocks.mck().0 / 1_000_000);
-
-        assert!(rvr < (1 << 24));
-
-        self.syst.set_reload(rvr);
-        self.syst.clear_current();
-        self.syst.enable_counter();
-
-        while !self.syst.has_wrapped() {}
-
-        self.syst.disable_counter();
+        let ticks = us_to_ticks(us, self.clocks.hclk());
+        self.delay_ticks(ticks);
     }
 }
 
 impl DelayUs<u16> for Delay {
     fn delay_us(&mut self, us: u16) {
-        self.delay_us(u32(us))
+        let ticks = us_to_ticks(us, self.clocks.hclk());
+        self.delay_ticks(ticks);
     }
 }
 
 impl DelayUs<u8> for Delay {
     fn delay_us(&mut self, us: u8) {
-        self.delay_us(u32(us))
+        let ticks = us_to_ticks(us, self.clocks.hclk());
+        self.delay_ticks(ticks);
+    }
+}
+
+#[cfg(feature = "eh1")]
+impl hal1::delay::DelayNs for Delay {
+    fn delay_ns(&mut self, ns: u32) {
+        let ticks = ns_to_ticks(ns, self.clocks.hclk());
+        self.delay_ticks(ticks);
+    }
+
+    fn delay_us(&mut self, us: u32) {
+        DelayUs::delay_us(self, us);
+    }
+
+    fn delay_ms(&mut self, ms: u32) {
+        DelayMs::delay_ms(self, ms);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ms_to_ticks, ns_to_ticks, us_to_ticks};
+    use crate::time::Hertz;
+
+    const HCLK_84MHZ: Hertz = Hertz(84_000_000);
+    const HCLK_12MHZ: Hertz = Hertz(12_000_000);
+
+    #[test]
+    fn us_ticks_u8() {
+        assert_eq!(us_to_ticks(200u8, HCLK_84MHZ), 16_800);
+        assert_eq!(us_to_ticks(200u8, HCLK_12MHZ), 2_400);
+    }
+
+    #[test]
+    fn us_ticks_u16() {
+        assert_eq!(us_to_ticks(60_000u16, HCLK_84MHZ), 5_040_000);
+        assert_eq!(us_to_ticks(60_000u16, HCLK_12MHZ), 720_000);
+    }
+
+    #[test]
+    fn us_ticks_u32() {
+        assert_eq!(us_to_ticks(4_000_000_000u32, HCLK_84MHZ), 336_000_000_000);
+        assert_eq!(us_to_ticks(4_000_000_000u32, HCLK_12MHZ), 48_000_000_000);
+    }
+
+    #[test]
+    fn ms_ticks_u8() {
+        assert_eq!(ms_to_ticks(250u8, HCLK_84MHZ), 21_000_000);
+        assert_eq!(ms_to_ticks(250u8, HCLK_12MHZ), 3_000_000);
+    }
+
+    #[test]
+    fn ms_ticks_u16() {
+        assert_eq!(ms_to_ticks(65_535u16, HCLK_84MHZ), 5_504_940_000);
+        assert_eq!(ms_to_ticks(65_535u16, HCLK_12MHZ), 786_420_000);
+    }
+
+    #[test]
+    fn ms_ticks_u32() {
+        assert_eq!(ms_to_ticks(100_000u32, HCLK_84MHZ), 8_400_000_000);
+        assert_eq!(ms_to_ticks(100_000u32, HCLK_12MHZ), 1_200_000_000);
+    }
+
+    #[test]
+    fn ns_ticks_round_up() {
+        assert_eq!(ns_to_ticks(1_000, HCLK_84MHZ), 84);
+        assert_eq!(ns_to_ticks(1, HCLK_84MHZ), 1);
+        assert_eq!(ns_to_ticks(100, HCLK_12MHZ), 2);
+        assert_eq!(ns_to_ticks(0, HCLK_12MHZ), 0);
     }
 }
@@ -0,0 +1,264 @@
+//! Enhanced Embedded Flash Controller (EEFC)
+//!
+//! The 512 KB of flash of the SAM3X8E are split into two banks of 256 KB, each with its own
+//! controller: EFC0 for bank 0 at `0x0008_0000` and EFC1 for bank 1 at `0x000C_0000`. A bank
+//! is made of 1024 pages of 256 bytes.
+//!
+//! A page is written by filling the controller's latch buffer, through 32-bit writes to any
+//! address within the page, and then issuing an "erase page and write page" command
+//! (`EFC_FCR`, `FCMD = EWP`), or "erase page, write page and lock" (`EWPL`) with
+//! `write_page_and_lock`. Flash can't be read while a command is running, so the routine
+//! issuing a command and polling for its completion is placed in RAM (in `.data`, copied
+//! there at startup) and runs with interrupts masked: an application can program the bank it
+//! runs from. The routine accesses the controller's registers in assembly so that it never
+//! calls into flash, whatever the optimization level.
+//!
+//! The SAM3X EEFC has no stand-alone erase page command (`EP`, found on later SAM parts):
+//! pages are always erased by `EWP`/`EWPL` as they are written. Writing without erasing
+//! (`WP`/`WPL`), erasing a whole bank (`EA`) and unlocking regions are not provided.
+//!
+//! Reads go straight through the flash address space.
+//!
+//! # GPNVM bits
+//!
+//! Three general-purpose non-volatile bits, handled by EFC0, control how the chip starts:
+//!
+//! | Bit | Cleared                    | Set                      |
+//! |-----|----------------------------|--------------------------|
+//! | 0   | flash readable by debugger | security bit, locks JTAG |
+//! | 1   | boot from ROM (SAM-BA)     | boot from flash          |
+//! | 2   | boot from bank 0           | boot from bank 1         |
+//!
+//! Erasing the chip through the ERASE pin (the Due's "erase" button) clears bit 1 so the
+//! board starts SAM-BA; an updater should set `Gpnvm::BootFromFlash` after programming. The
+//! security bit can only be cleared by an ERASE pin erase, so `clear_gpnvm` fails for it.
+
+use core::ptr;
+
+use sam3x8e::{EFC0, EFC1};
+
+/// Flash page size in bytes
+pub const PAGE_SIZE: usize = 256;
+
+/// Flash page size in 32-bit words
+pub const PAGE_WORDS: usize = PAGE_SIZE / 4;
+
+/// Number of pages of a bank
+pub const PAGES_PER_BANK: u16 = 1024;
+
+/// Size of a bank in bytes
+const BANK_SIZE: usize = PAGE_SIZE * PAGES_PER_BANK as usize;
+
+/// Password of `EFC_FCR.FKEY`
+const FKEY: u8 = 0x5A;
+
+// Bits of EFC_FSR
+const FSR_FCMDE: u32 = 1 << 1;
+const FSR_FLOCKE: u32 = 1 << 2;
+
+/// EEFC error
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum EefcError {
+    /// The controller rejected the command (`EFC_FSR.FCMDE`)
+    Command,
+    /// The page is in a locked region (`EFC_FSR.FLOCKE`)
+    Locked,
+    /// The page or offset is outside the bank
+    OutOfRange,
+}
+
+/// GPNVM bits of the SAM3X8E
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Gpnvm {
+    /// Security bit, disables debugger access to the flash
+    Security = 0,
+    /// Boot from flash instead of the SAM-BA ROM
+    BootFromFlash = 1,
+    /// Boot from flash bank 1 instead of bank 0
+    BootFromBank1 = 2,
+}
+
+/// EEFC commands (`EFC_FCR.FCMD`)
+#[derive(Clone, Copy)]
+enum Command {
+    /// Erase page and write page
+    Ewp = 0x03,
+    /// Erase page, write page and lock the region
+    Ewpl = 0x04,
+    /// Set GPNVM bit
+    Sgpb = 0x0B,
+    /// Clear GPNVM bit
+    Cgpb = 0x0C,
+    /// Get GPNVM bits
+    Ggpb = 0x0D,
+}
+
+/// Flash bank and its controller
+pub struct Eefc<EFC> {
+    efc: EFC,
+}
+
+macro_rules! hal {
+    ($($EFCX:ident: ($efcX:ident, $base:expr),)+) => {
+        $(
+            impl Eefc<$EFCX> {
+                /// Address of the first byte of the bank
+                pub const BASE: usize = $base;
+
+                /// Takes the controller of a flash bank
+                pub fn $efcX(efc: $EFCX) -> Self {
+                    Eefc { efc }
+                }
+
+                /// Returns the address of a page in the flash address space
+                pub fn page_address(page: u16) -> usize {
+                    Self::BASE + usize::from(page) * PAGE_SIZE
+                }
+
+                /// Copies bytes from the bank, starting `offset` bytes into it
+                pub fn read(&self, offset: usize, buffer: &mut [u8]) -> Result<(), EefcError> {
+                    match offset.checked_add(buffer.len()) {
+                        Some(end) if end <= BANK_SIZE => {}
+                        _ => return Err(EefcError::OutOfRange),
+                    }
+
+                    for (i, byte) in buffer.iter_mut().enumerate() {
+                        let address = (Self::BASE + offset + i) as *const u8;
+                        *byte = unsafe { ptr::read_volatile(address) };
+                    }
+
+                    Ok(())
+                }
+
+                /// Erases a page and writes new contents to it
+                ///
+                /// Blocks until the page has been programmed, about 4 ms, with interrupts
+                /// masked.
+                pub fn write_page(
+                    &mut self,
+                    page: u16,
+                    data: &[u32; PAGE_WORDS],
+                ) -> Result<(), EefcError> {
+                    self.program_page(Command::Ewp, page, data)
+                }
+
+                /// Erases a page, writes new contents to it and locks its region against
+                /// further writes
+                ///
+                /// Like `write_page`; later writes to the region fail with
+                /// `EefcError::Locked`.
+                pub fn write_page_and_lock(
+                    &mut self,
+                    page: u16,
+                    data: &[u32; PAGE_WORDS],
+                ) -> Result<(), EefcError> {
+                    self.program_page(Command::Ewpl, page, data)
+                }
+
+                fn program_page(
+                    &mut self,
+                    command: Command,
+                    page: u16,
+                    data: &[u32; PAGE_WORDS],
+                ) -> Result<(), EefcError> {
+                    if page >= PAGES_PER_BANK {
+                        return Err(EefcError::OutOfRange);
+                    }
+
+                    // fill the latch buffer
+                    let address = Self::page_address(page) as *mut u32;
+                    for (i, word) in data.iter().enumerate() {
+                        unsafe { ptr::write_volatile(address.add(i), *word) };
+                    }
+
+                    self.command(command, page)
+                }
+
+                /// Issues a command and waits for the controller to be ready again
+                fn command(&mut self, command: Command, farg: u16) -> Result<(), EefcError> {
+                    let fcr = u32::from(FKEY) << 24 | u32::from(farg) << 8 | command as u32;
+                    let efc = $EFCX::ptr() as usize;
+                    let fsr = cortex_m::interrupt::free(|_| unsafe { run_command(efc, fcr) });
+
+                    if fsr & FSR_FCMDE != 0 {
+                        Err(EefcError::Command)
+                    } else if fsr & FSR_FLOCKE != 0 {
+                        Err(EefcError::Locked)
+                    } else {
+                        Ok(())
+                    }
+                }
+
+                /// Releases the EFC peripheral
+                pub fn free(self) -> $EFCX {
+                    self.efc
+                }
+            }
+        )+
+    }
+}
+
+/// Writes `fcr` to `EFC_FCR` of the controller at `efc` and polls `EFC_FSR` until the command
+/// completes, returning `EFC_FSR`
+///
+/// The flash can't be read while the command runs, so this lives in RAM and must not call
+/// into flash. The register accesses are written in assembly, as even `ptr::read_volatile`
+/// is an out-of-line call into flash in an unoptimized build.
+#[cfg(target_arch = "arm")]
+#[inline(never)]
+#[link_section = ".data.eefc_run_command"]
+unsafe fn run_command(efc: usize, fcr: u32) -> u32 {
+    let status: u32;
+
+    // EFC_FCR is at offset 0x04 and EFC_FSR at offset 0x08, FRDY is bit 0 of EFC_FSR
+    core::arch::asm!(
+        "2:",
+        "ldr {status}, [{efc}, #8]",
+        "tst {status}, #1",
+        "beq 2b",
+        "str {fcr}, [{efc}, #4]",
+        "3:",
+        "ldr {status}, [{efc}, #8]",
+        "tst {status}, #1",
+        "beq 3b",
+        efc = in(reg) efc,
+        fcr = in(reg) fcr,
+        status = out(reg) status,
+        options(nostack),
+    );
+
+    status
+}
+
+/// Stand-in for host builds, where there is no flash controller to drive
+#[cfg(not(target_arch = "arm"))]
+unsafe fn run_command(_efc: usize, _fcr: u32) -> u32 {
+    unimplemented!("the EEFC can only be driven on the SAM3X")
+}
+
+impl Eefc<EFC0> {
+    /// Returns whether a GPNVM bit is set (`GGPB` command, result in `EFC_FRR`)
+    pub fn gpnvm(&mut self, bit: Gpnvm) -> Result<bool, EefcError> {
+        self.command(Command::Ggpb, 0)?;
+
+        Ok(self.efc.frr.read().fvalue().bits() & (1 << (bit as u32)) != 0)
+    }
+
+    /// Sets a GPNVM bit (`SGPB` command)
+    pub fn set_gpnvm(&mut self, bit: Gpnvm) -> Result<(), EefcError> {
+        self.command(Command::Sgpb, bit as u16)
+    }
+
+    /// Clears a GPNVM bit (`CGPB` command)
+    ///
+    /// Clearing the security bit is rejected with `EefcError::Command`.
+    pub fn clear_gpnvm(&mut self, bit: Gpnvm) -> Result<(), EefcError> {
+        self.command(Command::Cgpb, bit as u16)
+    }
+}
+
+hal! {
+    EFC0: (efc0, 0x0008_0000),
+    EFC1: (efc1, 0x000C_0000),
+}
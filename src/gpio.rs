@@ -1,3 +1,696 @@
 //! General Purpose Input / Output
+//!
+//! Each PIO controller is split into independent pins with `GpioExt::split`. The mode of a
+//! pin is tracked in its type, e.g. `PA9<Output<PushPull>>`, and changed by consuming
+//! `into_*` methods.
+//!
+//! Out of reset every pin is controlled by the PIO as an input with its pull-up enabled.
+//! The PIO clock is needed for inputs to be sampled and for input change interrupts to
+//! work, so `split` takes the `Pmc` and enables it.
+//!
+//! The PIO configures pins through pairs of enable/disable registers (`PIO_PER`/`PIO_PDR`,
+//! `PIO_OER`/`PIO_ODR`, ...) that only act on the bits written as 1, so pins of the same
+//! port can be used from different contexts without sharing a register token.
+//!
+//! In particular, outputs are driven through `PIO_SODR` and `PIO_CODR` and never by a
+//! read-modify-write of `PIO_ODSR`. Setting or clearing a pin is a single store touching
+//! only that pin, so if the main loop drives PB27 while an interrupt handler drives PB26,
+//! the handler can't preempt a half-done update and undo the main loop's change. The same
+//! holds for erased `Pin`s. Only the peripheral function selection (`PIO_ABSR`) needs a
+//! read-modify-write, which is done in a critical section.
+//!
+//! The input buffer of a pin stays enabled when it is an output, so output pins implement
+//! `InputPin` too and read back the actual line level from `PIO_PDSR` (this also needs the
+//! PIO clock). For an open-drain line this is the level other devices drive, while
+//! `StatefulOutputPin` reports the level the pin itself drives (`PIO_ODSR`).
+//!
+//! Several output pins of a port can be updated at once with the port's `write_port`, e.g.
+//! the data lines of a parallel bus. It writes `PIO_ODSR` through the output write enable
+//! mask (`PIO_OWER`), so all selected pins change on the same bus access.
+//!
+//! Open-drain outputs use the PIO multi-drive mode (`PIO_MDER`): the pin is only driven
+//! low, and an external pull-up (or the internal one) gives the high level. The SAM3X PIO
+//! has pull-ups only; there are no internal pull-downs, so an active-high button needs an
+//! external pull-down resistor with a floating input.
+//!
+//! To hand a pin over to a peripheral such as a USART or the SPI, switch it to one of its
+//! two peripheral functions with `into_peripheral_a` or `into_peripheral_b`. This disables
+//! PIO control of the pin (`PIO_PDR`) and selects the function in `PIO_ABSR`. The drivers
+//! only accept pins in the `Alternate` mode of the right function.
+//!
+//! ADC inputs are put in `Analog` mode with `into_analog`, which disables the output, the
+//! pull-up and PIO control of the pin so that nothing digital loads the analog signal. The
+//! ADC accepts the pins of its channels in this mode.
+//!
+//! Pins can be downgraded to a `Pin<MODE>`, which keeps its mode in the type but its port
+//! and number at runtime, e.g. to keep pins of several ports in one array.
+//!
+//! # Input filters
+//!
+//! Each input can filter out short pulses before they reach `PIO_PDSR` and the input
+//! change interrupt:
+//!
+//! - the glitch filter (`enable_glitch_filter`) rejects pulses shorter than half an MCK
+//!   period;
+//! - the debounce filter (`set_debounce`) rejects pulses shorter than half a period of the
+//!   divided slow clock, `2 * (DIV + 1) / SLCK` with `DIV` from `PIO_SCDR`. With the
+//!   32.768 kHz slow clock, pulses of up to 500 ms can be rejected.
+//!
+//! `PIO_SCDR` is shared by all pins of a port, so all debounced pins of a port use the
+//! period set last. The SAM3X PIO has no slew rate or drive strength settings.
+//!
+//! # Interrupts
+//!
+//! An input pin can interrupt on any edge (the default), on a single edge or on a level,
+//! selected with `make_interrupt_source` and enabled with `enable_interrupt`. All pins of a
+//! port share one interrupt line (e.g. `PIOA`). `PIO_ISR` reports the pins that triggered
+//! since it was last read, and **reading it clears it** for the whole port: read it once in
+//! the handler with `interrupt_status` and test each pin with `check_interrupt`.
 
-use crate::hal::digital::v2;
+use core::convert::Infallible;
+use core::marker::PhantomData;
+
+use crate::hal::digital::v2::{InputPin, OutputPin, StatefulOutputPin, ToggleableOutputPin};
+use crate::pmc::Pmc;
+use sam3x8e::{PIOA, PIOB, PIOC, PIOD};
+
+/// Largest slow clock divider of the debounce filter (`PIO_SCDR.DIV`)
+const MAX_DIV: u32 = 0x3FFF;
+
+/// Extension trait to split a PIO controller into independent pins
+pub trait GpioExt {
+    /// The pins of the port
+    type Parts;
+
+    /// Enables the clock of the PIO controller and splits it into independent pins
+    fn split(self, pmc: &mut Pmc) -> Self::Parts;
+}
+
+/// Input mode (type state)
+pub struct Input<MODE> {
+    _mode: PhantomData<MODE>,
+}
+
+/// Floating input (type state)
+pub struct Floating;
+
+/// Pulled up input (type state)
+pub struct PullUp;
+
+/// Output mode (type state)
+pub struct Output<MODE> {
+    _mode: PhantomData<MODE>,
+}
+
+/// Push pull output (type state)
+pub struct PushPull;
+
+/// Open drain output (type state)
+pub struct OpenDrain;
+
+/// Analog mode (type state)
+pub struct Analog;
+
+/// Peripheral function mode (type state)
+pub struct Alternate<AF> {
+    _af: PhantomData<AF>,
+}
+
+/// Peripheral function A (type state)
+pub struct PeriphA;
+
+/// Peripheral function B (type state)
+pub struct PeriphB;
+
+/// Input change interrupt trigger
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Trigger {
+    /// Any edge
+    BothEdges,
+    /// Rising edge
+    RisingEdge,
+    /// Falling edge
+    FallingEdge,
+    /// Low level
+    LowLevel,
+    /// High level
+    HighLevel,
+}
+
+/// PIO controllers
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Port {
+    PIOA,
+    PIOB,
+    PIOC,
+    PIOD,
+}
+
+impl Port {
+    fn pio(self) -> &'static sam3x8e::pioa::RegisterBlock {
+        // all PIO controllers share the same register layout
+        unsafe {
+            match self {
+                Port::PIOA => &*PIOA::ptr(),
+                Port::PIOB => &*(PIOB::ptr() as *const sam3x8e::pioa::RegisterBlock),
+                Port::PIOC => &*(PIOC::ptr() as *const sam3x8e::pioa::RegisterBlock),
+                Port::PIOD => &*(PIOD::ptr() as *const sam3x8e::pioa::RegisterBlock),
+            }
+        }
+    }
+}
+
+/// Pin whose port and number are known at runtime only
+pub struct Pin<MODE> {
+    port: Port,
+    i: u8,
+    _mode: PhantomData<MODE>,
+}
+
+impl<MODE> Pin<MODE> {
+    /// Returns the port of the pin
+    pub fn port(&self) -> Port {
+        self.port
+    }
+
+    /// Returns the number of the pin within its port
+    pub fn pin(&self) -> u8 {
+        self.i
+    }
+
+    fn mask(&self) -> u32 {
+        1 << self.i
+    }
+}
+
+impl<MODE> InputPin for Pin<Input<MODE>> {
+    type Error = Infallible;
+
+    fn is_high(&self) -> Result<bool, Infallible> {
+        Ok(self.port.pio().pdsr.read().bits() & self.mask() != 0)
+    }
+
+    fn is_low(&self) -> Result<bool, Infallible> {
+        self.is_high().map(|high| !high)
+    }
+}
+
+impl<MODE> OutputPin for Pin<Output<MODE>> {
+    type Error = Infallible;
+
+    fn set_high(&mut self) -> Result<(), Infallible> {
+        let mask = self.mask();
+        self.port
+            .pio()
+            .sodr
+            .write_with_zero(|w| unsafe { w.bits(mask) });
+        Ok(())
+    }
+
+    fn set_low(&mut self) -> Result<(), Infallible> {
+        let mask = self.mask();
+        self.port
+            .pio()
+            .codr
+            .write_with_zero(|w| unsafe { w.bits(mask) });
+        Ok(())
+    }
+}
+
+impl<MODE> StatefulOutputPin for Pin<Output<MODE>> {
+    fn is_set_high(&self) -> Result<bool, Infallible> {
+        Ok(self.port.pio().odsr.read().bits() & self.mask() != 0)
+    }
+
+    fn is_set_low(&self) -> Result<bool, Infallible> {
+        self.is_set_high().map(|high| !high)
+    }
+}
+
+impl<MODE> InputPin for Pin<Output<MODE>> {
+    type Error = Infallible;
+
+    /// Reads back the line level from `PIO_PDSR`
+    fn is_high(&self) -> Result<bool, Infallible> {
+        Ok(self.port.pio().pdsr.read().bits() & self.mask() != 0)
+    }
+
+    fn is_low(&self) -> Result<bool, Infallible> {
+        self.is_high().map(|high| !high)
+    }
+}
+
+impl<MODE> ToggleableOutputPin for Pin<Output<MODE>> {
+    type Error = Infallible;
+
+    /// Toggles the pin, reading its state from `PIO_ODSR`
+    fn toggle(&mut self) -> Result<(), Infallible> {
+        if self.is_set_high()? {
+            self.set_low()
+        } else {
+            self.set_high()
+        }
+    }
+}
+
+macro_rules! gpio {
+    ($PIOX:ident, $piox:ident, [
+        $($PXi:ident: ($pxi:ident, $i:expr),)+
+    ]) => {
+        /// PIO controller
+        pub mod $piox {
+            use core::convert::Infallible;
+            use core::marker::PhantomData;
+
+            use cortex_m::interrupt;
+
+            use super::{
+                Alternate, Analog, Floating, GpioExt, Input, OpenDrain, Output, PeriphA, PeriphB,
+                Pin, Port, PullUp, PushPull, Trigger, MAX_DIV,
+            };
+            use crate::hal::digital::v2::{
+                InputPin, OutputPin, StatefulOutputPin, ToggleableOutputPin,
+            };
+            use crate::pmc::{Clocks, PeripheralId, Pmc};
+            use sam3x8e::$PIOX;
+
+            /// Pins of the port, in their reset state
+            pub struct Parts {
+                $(
+                    /// Pin
+                    pub $pxi: $PXi<Input<PullUp>>,
+                )+
+            }
+
+            impl GpioExt for $PIOX {
+                type Parts = Parts;
+
+                fn split(self, pmc: &mut Pmc) -> Parts {
+                    pmc.enable_peripheral(PeripheralId::$PIOX);
+
+                    Parts {
+                        $(
+                            $pxi: $PXi { _mode: PhantomData },
+                        )+
+                    }
+                }
+            }
+
+            fn pio() -> &'static sam3x8e::$piox::RegisterBlock {
+                unsafe { &*$PIOX::ptr() }
+            }
+
+            /// Returns the pins of the port whose input changed since the last call (`PIO_ISR`)
+            ///
+            /// Reading `PIO_ISR` clears it, for all pins of the port.
+            pub fn interrupt_status() -> u32 {
+                pio().isr.read().bits()
+            }
+
+            /// Drives the output pins selected by `mask` to the levels of the matching bits
+            /// of `value`, all on the same bus access
+            ///
+            /// Only the pins in `mask` are affected. They should be output pins owned by the
+            /// caller: this bypasses the pin types.
+            pub fn write_port(mask: u32, value: u32) {
+                interrupt::free(|_| {
+                    pio().ower.write_with_zero(|w| unsafe { w.bits(mask) });
+                    pio().odsr.write_with_zero(|w| unsafe { w.bits(value) });
+                    pio().owdr.write_with_zero(|w| unsafe { w.bits(mask) });
+                });
+            }
+
+            $(
+                /// Pin
+                pub struct $PXi<MODE> {
+                    _mode: PhantomData<MODE>,
+                }
+
+                impl<MODE> $PXi<MODE> {
+                    const MASK: u32 = 1 << $i;
+
+                    /// Erases the port and pin number from the type
+                    pub fn downgrade(self) -> Pin<MODE> {
+                        Pin {
+                            port: Port::$PIOX,
+                            i: $i,
+                            _mode: PhantomData,
+                        }
+                    }
+
+                    /// Configures the pin to operate as a floating input pin
+                    pub fn into_floating_input(self) -> $PXi<Input<Floating>> {
+                        pio().odr.write_with_zero(|w| unsafe { w.bits(Self::MASK) });
+                        pio().pudr.write_with_zero(|w| unsafe { w.bits(Self::MASK) });
+                        pio().per.write_with_zero(|w| unsafe { w.bits(Self::MASK) });
+
+                        $PXi { _mode: PhantomData }
+                    }
+
+                    /// Configures the pin to operate as a pulled up input pin
+                    pub fn into_pull_up_input(self) -> $PXi<Input<PullUp>> {
+                        pio().odr.write_with_zero(|w| unsafe { w.bits(Self::MASK) });
+                        pio().puer.write_with_zero(|w| unsafe { w.bits(Self::MASK) });
+                        pio().per.write_with_zero(|w| unsafe { w.bits(Self::MASK) });
+
+                        $PXi { _mode: PhantomData }
+                    }
+
+                    /// Configures the pin to operate as a push pull output pin
+                    pub fn into_push_pull_output(self) -> $PXi<Output<PushPull>> {
+                        pio().mddr.write_with_zero(|w| unsafe { w.bits(Self::MASK) });
+                        pio().oer.write_with_zero(|w| unsafe { w.bits(Self::MASK) });
+                        pio().per.write_with_zero(|w| unsafe { w.bits(Self::MASK) });
+
+                        $PXi { _mode: PhantomData }
+                    }
+
+                    /// Configures the pin to operate as an open drain output pin
+                    ///
+                    /// The internal pull-up is disabled; use `into_open_drain_output_pull_up`
+                    /// to keep it.
+                    pub fn into_open_drain_output(self) -> $PXi<Output<OpenDrain>> {
+                        pio().pudr.write_with_zero(|w| unsafe { w.bits(Self::MASK) });
+                        pio().mder.write_with_zero(|w| unsafe { w.bits(Self::MASK) });
+                        pio().oer.write_with_zero(|w| unsafe { w.bits(Self::MASK) });
+                        pio().per.write_with_zero(|w| unsafe { w.bits(Self::MASK) });
+
+                        $PXi { _mode: PhantomData }
+                    }
+
+                    /// Configures the pin to operate as an open drain output pin, pulled up
+                    /// by the internal pull-up
+                    pub fn into_open_drain_output_pull_up(self) -> $PXi<Output<OpenDrain>> {
+                        pio().puer.write_with_zero(|w| unsafe { w.bits(Self::MASK) });
+                        pio().mder.write_with_zero(|w| unsafe { w.bits(Self::MASK) });
+                        pio().oer.write_with_zero(|w| unsafe { w.bits(Self::MASK) });
+                        pio().per.write_with_zero(|w| unsafe { w.bits(Self::MASK) });
+
+                        $PXi { _mode: PhantomData }
+                    }
+
+                    /// Configures the pin as an analog input, e.g. for the ADC
+                    ///
+                    /// The output driver and the pull-up are turned off and PIO control is
+                    /// disabled (`PIO_PDR`), leaving the line to the analog function.
+                    pub fn into_analog(self) -> $PXi<Analog> {
+                        pio().odr.write_with_zero(|w| unsafe { w.bits(Self::MASK) });
+                        pio().pudr.write_with_zero(|w| unsafe { w.bits(Self::MASK) });
+                        pio().pdr.write_with_zero(|w| unsafe { w.bits(Self::MASK) });
+
+                        $PXi { _mode: PhantomData }
+                    }
+
+                    /// Hands the pin over to its peripheral function A
+                    pub fn into_peripheral_a(self) -> $PXi<Alternate<PeriphA>> {
+                        // PIO_ABSR has no set/clear pair, so it is changed in a critical section
+                        interrupt::free(|_| {
+                            pio()
+                                .absr
+                                .modify(|r, w| unsafe { w.bits(r.bits() & !Self::MASK) })
+                        });
+                        pio().pdr.write_with_zero(|w| unsafe { w.bits(Self::MASK) });
+
+                        $PXi { _mode: PhantomData }
+                    }
+
+                    /// Hands the pin over to its peripheral function B
+                    pub fn into_peripheral_b(self) -> $PXi<Alternate<PeriphB>> {
+                        interrupt::free(|_| {
+                            pio()
+                                .absr
+                                .modify(|r, w| unsafe { w.bits(r.bits() | Self::MASK) })
+                        });
+                        pio().pdr.write_with_zero(|w| unsafe { w.bits(Self::MASK) });
+
+                        $PXi { _mode: PhantomData }
+                    }
+                }
+
+                impl<MODE> $PXi<Input<MODE>> {
+                    /// Selects what triggers the input change interrupt of the pin
+                    ///
+                    /// `BothEdges` is the PIO default; the others select the additional
+                    /// interrupt modes (`PIO_AIMER`, `PIO_ESR`/`PIO_LSR`, `PIO_REHLSR`/`PIO_FELLSR`).
+                    pub fn make_interrupt_source(&mut self, trigger: Trigger) {
+                        let pio = pio();
+
+                        match trigger {
+                            Trigger::BothEdges => {
+                                pio.aimdr.write_with_zero(|w| unsafe { w.bits(Self::MASK) });
+                                return;
+                            }
+                            Trigger::RisingEdge | Trigger::FallingEdge => {
+                                pio.esr.write_with_zero(|w| unsafe { w.bits(Self::MASK) });
+                            }
+                            Trigger::LowLevel | Trigger::HighLevel => {
+                                pio.lsr.write_with_zero(|w| unsafe { w.bits(Self::MASK) });
+                            }
+                        }
+
+                        match trigger {
+                            Trigger::RisingEdge | Trigger::HighLevel => {
+                                pio.rehlsr.write_with_zero(|w| unsafe { w.bits(Self::MASK) });
+                            }
+                            _ => {
+                                pio.fellsr.write_with_zero(|w| unsafe { w.bits(Self::MASK) });
+                            }
+                        }
+
+                        pio.aimer.write_with_zero(|w| unsafe { w.bits(Self::MASK) });
+                    }
+
+                    /// Enables the input change interrupt of the pin (`PIO_IER`)
+                    pub fn enable_interrupt(&mut self) {
+                        pio().ier.write_with_zero(|w| unsafe { w.bits(Self::MASK) });
+                    }
+
+                    /// Disables the input change interrupt of the pin (`PIO_IDR`)
+                    pub fn disable_interrupt(&mut self) {
+                        pio().idr.write_with_zero(|w| unsafe { w.bits(Self::MASK) });
+                    }
+
+                    /// Enables the glitch filter of the pin (`PIO_IFER`, `PIO_SCIFSR`)
+                    pub fn enable_glitch_filter(&mut self) {
+                        pio().scifsr.write_with_zero(|w| unsafe { w.bits(Self::MASK) });
+                        pio().ifer.write_with_zero(|w| unsafe { w.bits(Self::MASK) });
+                    }
+
+                    /// Enables the debounce filter of the pin, rejecting pulses shorter than
+                    /// `period_us` (`PIO_IFER`, `PIO_DIFSR`)
+                    ///
+                    /// The divided slow clock is set in `PIO_SCDR`, which applies to the whole
+                    /// port. The period is rounded down to a multiple of the slow clock period
+                    /// and clamped to 500 ms.
+                    pub fn set_debounce(&mut self, period_us: u32, clocks: &Clocks) {
+                        let slck_cycles = u64::from(period_us) * u64::from(clocks.slck().0) / 1_000_000;
+                        let div = slck_cycles.max(1).min(u64::from(MAX_DIV) + 1) - 1;
+
+                        pio().scdr.write_with_zero(|w| unsafe { w.div().bits(div as u16) });
+                        pio().difsr.write_with_zero(|w| unsafe { w.bits(Self::MASK) });
+                        pio().ifer.write_with_zero(|w| unsafe { w.bits(Self::MASK) });
+                    }
+
+                    /// Disables the input filter of the pin (`PIO_IFDR`)
+                    pub fn disable_input_filter(&mut self) {
+                        pio().ifdr.write_with_zero(|w| unsafe { w.bits(Self::MASK) });
+                    }
+
+                    /// Clears the pending interrupt by reading `PIO_ISR`
+                    ///
+                    /// This clears the pending interrupts of all pins of the port. With
+                    /// several interrupt pins on a port, use `interrupt_status` and
+                    /// `check_interrupt` instead.
+                    pub fn clear_interrupt_pending_bit(&mut self) {
+                        pio().isr.read();
+                    }
+
+                    /// Returns whether the pin is set in a value read from `interrupt_status`
+                    pub fn check_interrupt(&self, status: u32) -> bool {
+                        status & Self::MASK != 0
+                    }
+                }
+
+                impl<MODE> InputPin for $PXi<Input<MODE>> {
+                    type Error = Infallible;
+
+                    fn is_high(&self) -> Result<bool, Infallible> {
+                        Ok(pio().pdsr.read().bits() & Self::MASK != 0)
+                    }
+
+                    fn is_low(&self) -> Result<bool, Infallible> {
+                        self.is_high().map(|high| !high)
+                    }
+                }
+
+                impl<MODE> OutputPin for $PXi<Output<MODE>> {
+                    type Error = Infallible;
+
+                    /// Sets the pin through `PIO_SODR`, without affecting other pins
+                    fn set_high(&mut self) -> Result<(), Infallible> {
+                        pio().sodr.write_with_zero(|w| unsafe { w.bits(Self::MASK) });
+                        Ok(())
+                    }
+
+                    /// Clears the pin through `PIO_CODR`, without affecting other pins
+                    fn set_low(&mut self) -> Result<(), Infallible> {
+                        pio().codr.write_with_zero(|w| unsafe { w.bits(Self::MASK) });
+                        Ok(())
+                    }
+                }
+
+                impl<MODE> StatefulOutputPin for $PXi<Output<MODE>> {
+                    fn is_set_high(&self) -> Result<bool, Infallible> {
+                        Ok(pio().odsr.read().bits() & Self::MASK != 0)
+                    }
+
+                    fn is_set_low(&self) -> Result<bool, Infallible> {
+                        self.is_set_high().map(|high| !high)
+                    }
+                }
+
+                impl<MODE> InputPin for $PXi<Output<MODE>> {
+                    type Error = Infallible;
+
+                    /// Reads back the line level from `PIO_PDSR`
+                    fn is_high(&self) -> Result<bool, Infallible> {
+                        Ok(pio().pdsr.read().bits() & Self::MASK != 0)
+                    }
+
+                    fn is_low(&self) -> Result<bool, Infallible> {
+                        self.is_high().map(|high| !high)
+                    }
+                }
+
+                impl<MODE> ToggleableOutputPin for $PXi<Output<MODE>> {
+                    type Error = Infallible;
+
+                    /// Toggles the pin, reading its state from `PIO_ODSR`
+                    fn toggle(&mut self) -> Result<(), Infallible> {
+                        if self.is_set_high()? {
+                            self.set_low()
+                        } else {
+                            self.set_high()
+                        }
+                    }
+                }
+            )+
+        }
+    }
+}
+
+gpio!(PIOA, pioa, [
+    PA0: (pa0, 0),
+    PA1: (pa1, 1),
+    PA2: (pa2, 2),
+    PA3: (pa3, 3),
+    PA4: (pa4, 4),
+    PA5: (pa5, 5),
+    PA6: (pa6, 6),
+    PA7: (pa7, 7),
+    PA8: (pa8, 8),
+    PA9: (pa9, 9),
+    PA10: (pa10, 10),
+    PA11: (pa11, 11),
+    PA12: (pa12, 12),
+    PA13: (pa13, 13),
+    PA14: (pa14, 14),
+    PA15: (pa15, 15),
+    PA16: (pa16, 16),
+    PA17: (pa17, 17),
+    PA18: (pa18, 18),
+    PA19: (pa19, 19),
+    PA20: (pa20, 20),
+    PA21: (pa21, 21),
+    PA22: (pa22, 22),
+    PA23: (pa23, 23),
+    PA24: (pa24, 24),
+    PA25: (pa25, 25),
+    PA26: (pa26, 26),
+    PA27: (pa27, 27),
+    PA28: (pa28, 28),
+    PA29: (pa29, 29),
+]);
+
+gpio!(PIOB, piob, [
+    PB0: (pb0, 0),
+    PB1: (pb1, 1),
+    PB2: (pb2, 2),
+    PB3: (pb3, 3),
+    PB4: (pb4, 4),
+    PB5: (pb5, 5),
+    PB6: (pb6, 6),
+    PB7: (pb7, 7),
+    PB8: (pb8, 8),
+    PB9: (pb9, 9),
+    PB10: (pb10, 10),
+    PB11: (pb11, 11),
+    PB12: (pb12, 12),
+    PB13: (pb13, 13),
+    PB14: (pb14, 14),
+    PB15: (pb15, 15),
+    PB16: (pb16, 16),
+    PB17: (pb17, 17),
+    PB18: (pb18, 18),
+    PB19: (pb19, 19),
+    PB20: (pb20, 20),
+    PB21: (pb21, 21),
+    PB22: (pb22, 22),
+    PB23: (pb23, 23),
+    PB24: (pb24, 24),
+    PB25: (pb25, 25),
+    PB26: (pb26, 26),
+    PB27: (pb27, 27),
+    PB28: (pb28, 28),
+    PB29: (pb29, 29),
+    PB30: (pb30, 30),
+    PB31: (pb31, 31),
+]);
+
+gpio!(PIOC, pioc, [
+    PC0: (pc0, 0),
+    PC1: (pc1, 1),
+    PC2: (pc2, 2),
+    PC3: (pc3, 3),
+    PC4: (pc4, 4),
+    PC5: (pc5, 5),
+    PC6: (pc6, 6),
+    PC7: (pc7, 7),
+    PC8: (pc8, 8),
+    PC9: (pc9, 9),
+    PC10: (pc10, 10),
+    PC11: (pc11, 11),
+    PC12: (pc12, 12),
+    PC13: (pc13, 13),
+    PC14: (pc14, 14),
+    PC15: (pc15, 15),
+    PC16: (pc16, 16),
+    PC17: (pc17, 17),
+    PC18: (pc18, 18),
+    PC19: (pc19, 19),
+    PC20: (pc20, 20),
+    PC21: (pc21, 21),
+    PC22: (pc22, 22),
+    PC23: (pc23, 23),
+    PC24: (pc24, 24),
+    PC25: (pc25, 25),
+    PC26: (pc26, 26),
+    PC27: (pc27, 27),
+    PC28: (pc28, 28),
+    PC29: (pc29, 29),
+    PC30: (pc30, 30),
+]);
+
+gpio!(PIOD, piod, [
+    PD0: (pd0, 0),
+    PD1: (pd1, 1),
+    PD2: (pd2, 2),
+    PD3: (pd3, 3),
+    PD4: (pd4, 4),
+    PD5: (pd5, 5),
+    PD6: (pd6, 6),
+    PD7: (pd7, 7),
+    PD8: (pd8, 8),
+    PD9: (pd9, 9),
+    PD10: (pd10, 10),
+]);
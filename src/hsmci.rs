@@ -0,0 +1,490 @@
+//! High Speed Multimedia Card Interface (HSMCI)
+//!
+//! The HSMCI talks to an SD card over its native bus: a clock (MCCK), a bidirectional command
+//! line (MCCDA) and four data lines (MCDA0 to MCDA3), all on PIOA peripheral A:
+//!
+//! | Signal | Pin  |
+//! |--------|------|
+//! | MCCK   | PA19 |
+//! | MCCDA  | PA20 |
+//! | MCDA0  | PA21 |
+//! | MCDA1  | PA22 |
+//! | MCDA2  | PA23 |
+//! | MCDA3  | PA24 |
+//!
+//! `init` brings a card from idle to the transfer state (CMD0, CMD8, ACMD41, CMD2, CMD3, CMD7),
+//! switches it to the 4-bit bus and raises the card clock to at most 25 MHz. Standard
+//! capacity (SDSC) cards are addressed in bytes and high capacity (SDHC/SDXC) cards in blocks;
+//! `read_block` and `write_block` take a block number in both cases.
+//!
+//! The HSMCI of the SAM3X has no PDC channel, it is served by the DMA Controller (DMAC).
+//! Blocks are moved by the CPU through `HSMCI_RDR` and `HSMCI_TDR`, with `HSMCI_MR.RDPROOF`
+//! and `WRPROOF` set so that the card clock stops instead of overrunning the FIFO.
+
+use crate::gpio::pioa::{PA19, PA20, PA21, PA22, PA23, PA24};
+use crate::gpio::{Alternate, PeriphA};
+use crate::pmc::{Clocks, PeripheralId, Pmc};
+use crate::time::Hertz;
+use sam3x8e::HSMCI;
+
+/// Size of a block, in bytes
+pub const BLOCK_SIZE: usize = 512;
+
+/// Card clock during identification
+const INIT_CLOCK: Hertz = Hertz(400_000);
+
+/// Highest card clock in default speed mode
+const TRANSFER_CLOCK: Hertz = Hertz(25_000_000);
+
+/// Number of ACMD41 tries before giving up on a card
+const OP_COND_TRIES: u32 = 10_000;
+
+/// Voltage window 2.7-3.6 V of the OCR
+const OCR_VOLTAGE_WINDOW: u32 = 0x00FF_8000;
+
+/// `OCR.CCS`/`HCS`, high capacity card
+const OCR_CCS: u32 = 1 << 30;
+
+/// `OCR.BUSY`, cleared while the card is initializing
+const OCR_READY: u32 = 1 << 31;
+
+/// Argument of CMD8: 2.7-3.6 V and check pattern `0xAA`
+const IF_COND: u32 = 0x1AA;
+
+// Bits of HSMCI_SR
+const SR_CMDRDY: u32 = 1 << 0;
+const SR_RXRDY: u32 = 1 << 1;
+const SR_TXRDY: u32 = 1 << 2;
+const SR_NOTBUSY: u32 = 1 << 5;
+const SR_RINDE: u32 = 1 << 16;
+const SR_RDIRE: u32 = 1 << 17;
+const SR_RCRCE: u32 = 1 << 18;
+const SR_RENDE: u32 = 1 << 19;
+const SR_RTOE: u32 = 1 << 20;
+const SR_DCRCE: u32 = 1 << 21;
+const SR_DTOE: u32 = 1 << 22;
+const SR_XFRDONE: u32 = 1 << 27;
+const SR_OVRE: u32 = 1 << 30;
+const SR_UNRE: u32 = 1 << 31;
+
+/// HSMCI errors
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum HsmciError {
+    /// The card didn't answer a command (`HSMCI_SR.RTOE`)
+    CommandTimeout,
+    /// The CRC of a response was wrong (`HSMCI_SR.RCRCE`)
+    CommandCrc,
+    /// A response had a wrong index, direction or end bit
+    Response,
+    /// The card didn't send or accept data in time (`HSMCI_SR.DTOE`)
+    DataTimeout,
+    /// The CRC of a data block was wrong (`HSMCI_SR.DCRCE`)
+    DataCrc,
+    /// Received data was lost (`HSMCI_SR.OVRE`)
+    Overrun,
+    /// Data to transmit wasn't ready in time (`HSMCI_SR.UNRE`)
+    Underrun,
+    /// The card doesn't support the 2.7-3.6 V range or never left its idle state
+    UnsupportedCard,
+    /// The byte address of the block doesn't fit the 32-bit argument of a standard capacity card
+    AddressOutOfRange,
+}
+
+/// Pins of the HSMCI, as `(mcck, mccda, mcda0, mcda1, mcda2, mcda3)`
+pub trait Pins<HSMCI> {}
+
+impl Pins<HSMCI>
+    for (
+        PA19<Alternate<PeriphA>>,
+        PA20<Alternate<PeriphA>>,
+        PA21<Alternate<PeriphA>>,
+        PA22<Alternate<PeriphA>>,
+        PA23<Alternate<PeriphA>>,
+        PA24<Alternate<PeriphA>>,
+    )
+{
+}
+
+/// Response expected to a command (`HSMCI_CMDR.RSPTYP`)
+#[derive(Clone, Copy, PartialEq)]
+enum Response {
+    None,
+    /// 48-bit response with a CRC (R1, R6, R7)
+    Short,
+    /// 48-bit response without a CRC (R3)
+    ShortNoCrc,
+    /// 48-bit response followed by busy (R1b)
+    ShortBusy,
+    /// 136-bit response (R2)
+    Long,
+}
+
+/// Data transfer started by a command (`HSMCI_CMDR.TRCMD` and `TRDIR`)
+#[derive(Clone, Copy, PartialEq)]
+enum Transfer {
+    None,
+    Read,
+    Write,
+}
+
+/// SD card on the HSMCI
+pub struct Hsmci<PINS> {
+    hsmci: HSMCI,
+    pins: PINS,
+    clocks: Clocks,
+    rca: u16,
+    high_capacity: bool,
+    block_count: u32,
+}
+
+impl<PINS> Hsmci<PINS>
+where
+    PINS: Pins<HSMCI>,
+{
+    /// Enables the HSMCI with a 400 kHz card clock on a 1-bit bus
+    ///
+    /// The card must then be brought up with `init`.
+    pub fn new(hsmci: HSMCI, pins: PINS, clocks: Clocks, pmc: &mut Pmc) -> Self {
+        pmc.enable_peripheral(PeripheralId::HSMCI);
+
+        hsmci.cr.write_with_zero(|w| w.swrst().set_bit());
+        hsmci
+            .cr
+            .write_with_zero(|w| w.mcidis().set_bit().pwsdis().set_bit());
+
+        hsmci
+            .dtor
+            .write_with_zero(|w| unsafe { w.dtocyc().bits(0xF).dtomul()._1048576() });
+        hsmci
+            .cstor
+            .write_with_zero(|w| unsafe { w.cstocyc().bits(0xF).cstomul()._1048576() });
+        hsmci
+            .cfg
+            .write_with_zero(|w| w.fifomode().set_bit().ferrctrl().set_bit());
+        hsmci
+            .sdcr
+            .write_with_zero(|w| w.sdcsel().slota().sdcbus()._1());
+
+        let mut hsmci = Hsmci {
+            hsmci,
+            pins,
+            clocks,
+            rca: 0,
+            high_capacity: false,
+            block_count: 0,
+        };
+        hsmci.set_clock(INIT_CLOCK);
+
+        hsmci
+            .hsmci
+            .cr
+            .write_with_zero(|w| w.mcien().set_bit().pwsdis().set_bit());
+
+        hsmci
+    }
+
+    /// Identifies the card and selects it for data transfers
+    pub fn init(&mut self) -> Result<(), HsmciError> {
+        self.hsmci
+            .sdcr
+            .write_with_zero(|w| w.sdcsel().slota().sdcbus()._1());
+        self.set_clock(INIT_CLOCK);
+
+        // 74 clock cycles for the card to power up
+        self.hsmci
+            .argr
+            .write_with_zero(|w| unsafe { w.arg().bits(0) });
+        self.hsmci
+            .cmdr
+            .write_with_zero(|w| unsafe { w.cmdnb().bits(0).spcmd().init().opdcmd().set_bit() });
+        while self.hsmci.sr.read().cmdrdy().bit_is_clear() {}
+
+        // GO_IDLE_STATE
+        self.command(0, 0, Response::None, Transfer::None)?;
+
+        // SEND_IF_COND, only answered by version 2.00 cards
+        let v2 = match self.command(8, IF_COND, Response::Short, Transfer::None) {
+            Ok(response) if response & 0xFFF == IF_COND => true,
+            Ok(_) => return Err(HsmciError::UnsupportedCard),
+            Err(HsmciError::CommandTimeout) => false,
+            Err(e) => return Err(e),
+        };
+
+        // SD_SEND_OP_COND, until the card is ready
+        let hcs = if v2 { OCR_CCS } else { 0 };
+        let mut ocr = 0;
+        for _ in 0..OP_COND_TRIES {
+            self.command(55, 0, Response::Short, Transfer::None)?;
+            ocr = self.command(
+                41,
+                OCR_VOLTAGE_WINDOW | hcs,
+                Response::ShortNoCrc,
+                Transfer::None,
+            )?;
+
+            if ocr & OCR_READY != 0 {
+                break;
+            }
+        }
+        if ocr & OCR_READY == 0 {
+            return Err(HsmciError::UnsupportedCard);
+        }
+        self.high_capacity = ocr & OCR_CCS != 0;
+
+        // ALL_SEND_CID
+        self.command(2, 0, Response::Long, Transfer::None)?;
+
+        // SEND_RELATIVE_ADDR
+        self.rca = (self.command(3, 0, Response::Short, Transfer::None)? >> 16) as u16;
+        let rca = u32::from(self.rca) << 16;
+
+        // SEND_CSD
+        self.command(9, rca, Response::Long, Transfer::None)?;
+        self.block_count = self.read_capacity();
+
+        // SELECT_CARD
+        self.command(7, rca, Response::ShortBusy, Transfer::None)?;
+
+        // SET_BUS_WIDTH to 4 bits
+        self.command(55, rca, Response::Short, Transfer::None)?;
+        self.command(6, 2, Response::Short, Transfer::None)?;
+        self.hsmci
+            .sdcr
+            .write_with_zero(|w| w.sdcsel().slota().sdcbus()._4());
+
+        // SET_BLOCKLEN, fixed to 512 bytes on high capacity cards
+        if !self.high_capacity {
+            self.command(16, BLOCK_SIZE as u32, Response::Short, Transfer::None)?;
+        }
+
+        self.set_clock(TRANSFER_CLOCK);
+
+        Ok(())
+    }
+
+    /// Returns the number of 512-byte blocks of the card, as read by `init`
+    pub fn block_count(&self) -> u32 {
+        self.block_count
+    }
+
+    /// Reads a block (READ_SINGLE_BLOCK)
+    pub fn read_block(
+        &mut self,
+        block: u32,
+        buffer: &mut [u8; BLOCK_SIZE],
+    ) -> Result<(), HsmciError> {
+        self.set_block_length();
+        self.command(
+            17,
+            self.block_address(block)?,
+            Response::Short,
+            Transfer::Read,
+        )?;
+
+        for chunk in buffer.chunks_mut(4) {
+            loop {
+                let sr = self.hsmci.sr.read().bits();
+                data_error(sr)?;
+
+                if sr & SR_RXRDY != 0 {
+                    break;
+                }
+            }
+
+            chunk.copy_from_slice(&self.hsmci.rdr.read().data().bits().to_le_bytes());
+        }
+
+        self.wait_transfer_done()
+    }
+
+    /// Writes a block (WRITE_BLOCK) and waits until the card has programmed it
+    pub fn write_block(&mut self, block: u32, buffer: &[u8; BLOCK_SIZE]) -> Result<(), HsmciError> {
+        self.set_block_length();
+        self.command(
+            24,
+            self.block_address(block)?,
+            Response::Short,
+            Transfer::Write,
+        )?;
+
+        for chunk in buffer.chunks(4) {
+            loop {
+                let sr = self.hsmci.sr.read().bits();
+                data_error(sr)?;
+
+                if sr & SR_TXRDY != 0 {
+                    break;
+                }
+            }
+
+            let word = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+            self.hsmci
+                .tdr
+                .write_with_zero(|w| unsafe { w.data().bits(word) });
+        }
+
+        loop {
+            let sr = self.hsmci.sr.read().bits();
+            data_error(sr)?;
+
+            if sr & SR_NOTBUSY != 0 {
+                break;
+            }
+        }
+
+        self.wait_transfer_done()
+    }
+
+    /// Disables the HSMCI and releases the peripheral and pins
+    pub fn free(self) -> (HSMCI, PINS) {
+        self.hsmci.cr.write_with_zero(|w| w.mcidis().set_bit());
+
+        (self.hsmci, self.pins)
+    }
+
+    /// Sets the card clock to the highest frequency not above `freq`
+    ///
+    /// MCCK is `MCK / (2 * (CLKDIV + 1))`.
+    fn set_clock(&mut self, freq: Hertz) {
+        let mck = self.clocks.mck().0;
+        let clkdiv = ((mck + 2 * freq.0 - 1) / (2 * freq.0)).max(1) - 1;
+        assert!(clkdiv <= 0xFF);
+
+        self.hsmci.mr.write_with_zero(|w| unsafe {
+            w.clkdiv()
+                .bits(clkdiv as u8)
+                .pwsdiv()
+                .bits(7)
+                .rdproof()
+                .set_bit()
+                .wrproof()
+                .set_bit()
+        });
+    }
+
+    fn set_block_length(&mut self) {
+        self.hsmci
+            .blkr
+            .write_with_zero(|w| unsafe { w.bcnt().bits(1).blklen().bits(BLOCK_SIZE as u16) });
+    }
+
+    /// Converts a block number to the address argument of the card
+    ///
+    /// Standard capacity cards are addressed in bytes, high capacity cards in blocks.
+    fn block_address(&self, block: u32) -> Result<u32, HsmciError> {
+        if self.high_capacity {
+            Ok(block)
+        } else {
+            block
+                .checked_mul(BLOCK_SIZE as u32)
+                .ok_or(HsmciError::AddressOutOfRange)
+        }
+    }
+
+    /// Sends a command and returns the first word of its response
+    fn command(
+        &mut self,
+        index: u8,
+        argument: u32,
+        response: Response,
+        transfer: Transfer,
+    ) -> Result<u32, HsmciError> {
+        self.hsmci
+            .argr
+            .write_with_zero(|w| unsafe { w.arg().bits(argument) });
+        self.hsmci.cmdr.write_with_zero(|w| {
+            let w = unsafe { w.cmdnb().bits(index) }.maxlat()._64();
+            let w = match response {
+                Response::None => w.rsptyp().noresp(),
+                Response::Short | Response::ShortNoCrc => w.rsptyp()._48_bit(),
+                Response::ShortBusy => w.rsptyp().r1b(),
+                Response::Long => w.rsptyp()._136_bit(),
+            };
+            match transfer {
+                Transfer::None => w.trcmd().no_data(),
+                Transfer::Read => w.trcmd().start_data().trdir().read().trtyp().single(),
+                Transfer::Write => w.trcmd().start_data().trdir().write().trtyp().single(),
+            }
+        });
+
+        let sr = loop {
+            let sr = self.hsmci.sr.read().bits();
+            if sr & SR_CMDRDY != 0 {
+                break sr;
+            }
+        };
+
+        if sr & SR_RTOE != 0 {
+            return Err(HsmciError::CommandTimeout);
+        }
+        if sr & SR_RCRCE != 0 && response != Response::ShortNoCrc {
+            return Err(HsmciError::CommandCrc);
+        }
+        if sr & (SR_RINDE | SR_RDIRE | SR_RENDE) != 0 {
+            return Err(HsmciError::Response);
+        }
+
+        if response == Response::ShortBusy {
+            while self.hsmci.sr.read().notbusy().bit_is_clear() {}
+        }
+
+        Ok(self.hsmci.rspr[0].read().rsp().bits())
+    }
+
+    /// Computes the number of 512-byte blocks from the CSD of a 136-bit response
+    fn read_capacity(&self) -> u32 {
+        // successive reads of HSMCI_RSPR return the response from its most significant word
+        let csd = (0..4).fold(0u128, |csd, _| {
+            (csd << 32) | u128::from(self.hsmci.rspr[0].read().rsp().bits())
+        });
+        let field = |msb: u32, lsb: u32| ((csd >> lsb) as u32) & ((1 << (msb - lsb + 1)) - 1);
+
+        match field(127, 126) {
+            // CSD version 1.0: (C_SIZE + 1) * 2^(C_SIZE_MULT + 2) blocks of 2^READ_BL_LEN bytes
+            0 => {
+                let blocks = (field(73, 62) + 1) << (field(49, 47) + 2);
+                // READ_BL_LEN is 9 to 11, scale straight to 512-byte blocks so the byte
+                // count of a 2 GB card doesn't overflow
+                let read_bl_len = field(83, 80);
+                if read_bl_len >= 9 {
+                    blocks << (read_bl_len - 9)
+                } else {
+                    blocks >> (9 - read_bl_len)
+                }
+            }
+            // CSD version 2.0: (C_SIZE + 1) * 512 KB
+            _ => (field(69, 48) + 1) * 1024,
+        }
+    }
+
+    /// Waits for the end of a data transfer
+    fn wait_transfer_done(&mut self) -> Result<(), HsmciError> {
+        loop {
+            let sr = self.hsmci.sr.read().bits();
+            data_error(sr)?;
+
+            if sr & SR_XFRDONE != 0 {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Returns the data error flagged in `HSMCI_SR`, if any
+///
+/// The error flags clear when `HSMCI_SR` is read, so the status is read once and checked for
+/// both errors and progress.
+fn data_error(sr: u32) -> Result<(), HsmciError> {
+    if sr & SR_DCRCE != 0 {
+        Err(HsmciError::DataCrc)
+    } else if sr & SR_DTOE != 0 {
+        Err(HsmciError::DataTimeout)
+    } else if sr & SR_OVRE != 0 {
+        Err(HsmciError::Overrun)
+    } else if sr & SR_UNRE != 0 {
+        Err(HsmciError::Underrun)
+    } else {
+        Ok(())
+    }
+}
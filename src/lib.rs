@@ -1,12 +1,33 @@
 #![no_std]
 
 extern crate embedded_hal as hal;
+#[cfg(feature = "eh1")]
+extern crate embedded_hal_1 as hal1;
 
+pub mod adc;
+pub mod can;
+pub mod dac;
 pub mod delay;
+pub mod eefc;
 pub mod gpio;
+pub mod hsmci;
+pub mod pdc;
 pub mod pmc;
+pub mod prelude;
 pub mod time;
+pub mod timer;
 pub mod pwm;
+pub mod reset;
+pub mod rtc;
+pub mod rtt;
+pub mod serial;
+pub mod smc;
+pub mod spi;
+pub mod trng;
+pub mod twi;
+pub mod usart_spi;
+pub mod usb;
+pub mod watchdog;
 
 #[cfg(test)]
 mod tests {
@@ -0,0 +1,236 @@
+//! Peripheral DMA Controller (PDC)
+//!
+//! The UART, the USARTs, the TWIs, the ADC, the DACC and the PWM each have a PDC channel
+//! moving data between memory and the peripheral without the CPU. Its registers follow the
+//! peripheral's own, at offset `0x100`: a pointer and a counter per direction (`PERIPH_TPR`
+//! and `PERIPH_TCR` to transmit, `PERIPH_RPR` and `PERIPH_RCR` to receive) and a transfer
+//! control register, `PERIPH_PTCR`. The ADC can only receive, the DACC and the PWM can
+//! only transmit.
+//!
+//! The counter counts transfers, not bytes: the size of each transfer is set by the
+//! peripheral, e.g. bytes for the USART and half-words for the ADC. A transfer is done when
+//! its counter has reached 0.
+//!
+//! SPI0 has no PDC channel on the SAM3X; it is served by the DMA Controller (DMAC) instead.
+//!
+//! `Transfer` owns a `'static` buffer together with the driver, so the buffer can't be
+//! touched or freed while the PDC still accesses it. `wait` gives both back once the
+//! transfer is done. The `PdcTx` and `PdcRx` traits are implemented by the drivers, not the
+//! raw peripherals, and their `Word` is the transfer size: a `Transfer` only takes a buffer
+//! of that type, so the PDC can't move past its end.
+
+use core::sync::atomic::{compiler_fence, Ordering};
+
+use crate::adc::Adc;
+use crate::dac::Dac;
+use crate::serial::{Rx, Tx};
+use sam3x8e::{ADC, DACC, UART, USART0, USART1, USART2, USART3};
+
+/// Driver whose PDC channel can transmit from memory
+pub trait PdcTx {
+    /// Size of one transfer
+    type Word;
+
+    /// Points the transmit channel to `count` transfers at `address` and enables it
+    ///
+    /// # Safety
+    ///
+    /// `address` must point to `count` readable `Word`s that stay valid until the channel
+    /// is done or stopped.
+    unsafe fn start_tx(&self, address: u32, count: u16);
+
+    /// Disables the transmit channel (`PERIPH_PTCR.TXTDIS`)
+    fn stop_tx(&self);
+
+    /// Returns the number of transfers left to transmit (`PERIPH_TCR`)
+    fn tx_remaining(&self) -> u16;
+}
+
+/// Driver whose PDC channel can receive to memory
+pub trait PdcRx {
+    /// Size of one transfer
+    type Word;
+
+    /// Points the receive channel to `count` transfers at `address` and enables it
+    ///
+    /// # Safety
+    ///
+    /// `address` must point to `count` writable `Word`s that stay valid and aren't
+    /// accessed otherwise until the channel is done or stopped.
+    unsafe fn start_rx(&self, address: u32, count: u16);
+
+    /// Disables the receive channel (`PERIPH_PTCR.RXTDIS`)
+    fn stop_rx(&self);
+
+    /// Returns the number of transfers left to receive (`PERIPH_RCR`)
+    fn rx_remaining(&self) -> u16;
+}
+
+macro_rules! tx {
+    ($($Driver:ty => ($PERIPH:ident, $Word:ty),)+) => {
+        $(
+            impl PdcTx for $Driver {
+                type Word = $Word;
+
+                unsafe fn start_tx(&self, address: u32, count: u16) {
+                    let periph = &*$PERIPH::ptr();
+
+                    periph.ptcr.write_with_zero(|w| w.txtdis().set_bit());
+                    periph.tpr.write_with_zero(|w| w.txptr().bits(address));
+                    periph.tcr.write_with_zero(|w| w.txctr().bits(count));
+                    periph.ptcr.write_with_zero(|w| w.txten().set_bit());
+                }
+
+                fn stop_tx(&self) {
+                    let periph = unsafe { &*$PERIPH::ptr() };
+
+                    periph.ptcr.write_with_zero(|w| w.txtdis().set_bit());
+                }
+
+                fn tx_remaining(&self) -> u16 {
+                    let periph = unsafe { &*$PERIPH::ptr() };
+
+                    periph.tcr.read().txctr().bits()
+                }
+            }
+        )+
+    }
+}
+
+macro_rules! rx {
+    ($($Driver:ty => ($PERIPH:ident, $Word:ty),)+) => {
+        $(
+            impl PdcRx for $Driver {
+                type Word = $Word;
+
+                unsafe fn start_rx(&self, address: u32, count: u16) {
+                    let periph = &*$PERIPH::ptr();
+
+                    periph.ptcr.write_with_zero(|w| w.rxtdis().set_bit());
+                    periph.rpr.write_with_zero(|w| w.rxptr().bits(address));
+                    periph.rcr.write_with_zero(|w| w.rxctr().bits(count));
+                    periph.ptcr.write_with_zero(|w| w.rxten().set_bit());
+                }
+
+                fn stop_rx(&self) {
+                    let periph = unsafe { &*$PERIPH::ptr() };
+
+                    periph.ptcr.write_with_zero(|w| w.rxtdis().set_bit());
+                }
+
+                fn rx_remaining(&self) -> u16 {
+                    let periph = unsafe { &*$PERIPH::ptr() };
+
+                    periph.rcr.read().rxctr().bits()
+                }
+            }
+        )+
+    }
+}
+
+tx! {
+    Dac => (DACC, u16),
+    Tx<UART> => (UART, u8),
+    Tx<USART0> => (USART0, u8),
+    Tx<USART1> => (USART1, u8),
+    Tx<USART2> => (USART2, u8),
+    Tx<USART3> => (USART3, u8),
+}
+
+rx! {
+    Adc => (ADC, u16),
+    Rx<UART> => (UART, u8),
+    Rx<USART0> => (USART0, u8),
+    Rx<USART1> => (USART1, u8),
+    Rx<USART2> => (USART2, u8),
+    Rx<USART3> => (USART3, u8),
+}
+
+/// PDC transfer in progress
+///
+/// Dropping a `Transfer` leaves the PDC running until the counter reaches 0. This is
+/// harmless since the buffer is `'static` and can't be reached any more.
+pub struct Transfer<BUF, PERIPH> {
+    buffer: BUF,
+    periph: PERIPH,
+}
+
+impl<PERIPH> Transfer<&'static [PERIPH::Word], PERIPH>
+where
+    PERIPH: PdcTx,
+{
+    /// Starts transmitting a buffer to the peripheral
+    ///
+    /// Panics if the buffer holds more than 65535 transfers.
+    pub fn write(periph: PERIPH, buffer: &'static [PERIPH::Word]) -> Self {
+        assert!(buffer.len() <= 0xFFFF);
+
+        // The buffer must be written out before the PDC starts reading it
+        compiler_fence(Ordering::SeqCst);
+        // Safety: the buffer is `'static` and held by the `Transfer` until it is stopped
+        unsafe { periph.start_tx(buffer.as_ptr() as u32, buffer.len() as u16) };
+
+        Transfer { buffer, periph }
+    }
+
+    /// Returns whether the whole buffer has been transmitted
+    pub fn is_done(&self) -> bool {
+        self.periph.tx_remaining() == 0
+    }
+
+    /// Blocks until the whole buffer has been transmitted, then releases it
+    pub fn wait(self) -> (&'static [PERIPH::Word], PERIPH) {
+        while !self.is_done() {}
+
+        self.stop()
+    }
+
+    /// Stops the transfer, returning the buffer and the peripheral
+    pub fn stop(self) -> (&'static [PERIPH::Word], PERIPH) {
+        self.periph.stop_tx();
+        compiler_fence(Ordering::SeqCst);
+
+        (self.buffer, self.periph)
+    }
+}
+
+impl<PERIPH> Transfer<&'static mut [PERIPH::Word], PERIPH>
+where
+    PERIPH: PdcRx,
+{
+    /// Starts receiving from the peripheral into a buffer
+    ///
+    /// Panics if the buffer holds more than 65535 transfers.
+    pub fn read(periph: PERIPH, buffer: &'static mut [PERIPH::Word]) -> Self {
+        assert!(buffer.len() <= 0xFFFF);
+
+        compiler_fence(Ordering::SeqCst);
+        // Safety: the buffer is `'static` and held by the `Transfer` until it is stopped
+        unsafe { periph.start_rx(buffer.as_mut_ptr() as u32, buffer.len() as u16) };
+
+        Transfer { buffer, periph }
+    }
+
+    /// Returns whether the whole buffer has been filled
+    pub fn is_done(&self) -> bool {
+        self.periph.rx_remaining() == 0
+    }
+
+    /// Blocks until the whole buffer has been filled, then releases it
+    pub fn wait(self) -> (&'static mut [PERIPH::Word], PERIPH) {
+        while !self.is_done() {}
+
+        self.stop()
+    }
+
+    /// Stops the transfer, returning the buffer and the peripheral
+    ///
+    /// The transfers received so far are at the start of the buffer.
+    pub fn stop(self) -> (&'static mut [PERIPH::Word], PERIPH) {
+        self.periph.stop_rx();
+        // The buffer must not be read before the PDC is done writing it
+        compiler_fence(Ordering::SeqCst);
+
+        (self.buffer, self.periph)
+    }
+}
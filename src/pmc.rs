@@ -1,8 +1,7 @@
 //! Power Mode Controller (Manages clocks)
 
-use core::cmp;
-
 use crate::time::{Hertz, U32Ext};
+use cortex_m::peripheral::SCB;
 use sam3x8e::{pmc, PMC};
 
 /// Extension trait that constraints the 'pmc' peripheral
@@ -21,19 +20,301 @@ pub struct Pmc {
     pub cfgr: CFGR,
 }
 
-impl PmcExt for Pmc {
+impl PmcExt for PMC {
     fn constraint(self) -> Pmc {
         Pmc {
             pclk0: Pclk0 { _0: () },
             pclk1: Pclk1 { _0: () },
-            cfgr: CFGR {
-                master_clock: None,
-                clock_source: ClockSource::SlowClock,
-            },
+            cfgr: CFGR::new(),
         }
     }
 }
 
+impl Pmc {
+    /// Enables the clock of a peripheral
+    pub fn enable_peripheral(&mut self, id: PeripheralId) {
+        let id = id as u32;
+        if id < 32 {
+            self.pclk0.er().write_with_zero(|w| unsafe { w.bits(1 << id) });
+        } else {
+            self.pclk1.er().write_with_zero(|w| unsafe { w.bits(1 << (id - 32)) });
+        }
+    }
+
+    /// Disables the clock of a peripheral
+    pub fn disable_peripheral(&mut self, id: PeripheralId) {
+        let id = id as u32;
+        if id < 32 {
+            self.pclk0.dr().write_with_zero(|w| unsafe { w.bits(1 << id) });
+        } else {
+            self.pclk1.dr().write_with_zero(|w| unsafe { w.bits(1 << (id - 32)) });
+        }
+    }
+
+    /// Returns whether the clock of a peripheral is enabled
+    ///
+    /// Registers of a peripheral whose clock is disabled read back garbage.
+    pub fn is_peripheral_enabled(&self, id: PeripheralId) -> bool {
+        let id = id as u32;
+        if id < 32 {
+            self.pclk0.sr().read().bits() & (1 << id) != 0
+        } else {
+            self.pclk1.sr().read().bits() & (1 << (id - 32)) != 0
+        }
+    }
+
+    /// Lets an event wake the chip up from Wait Mode (`PMC_FSMR`)
+    pub fn enable_wakeup_source(&mut self, source: WakeupSource) {
+        let pmc = unsafe { &(*PMC::ptr()) };
+        pmc.pmc_fsmr
+            .modify(|r, w| unsafe { w.bits(r.bits() | source.mask()) });
+    }
+
+    /// Stops an event from waking the chip up from Wait Mode
+    pub fn disable_wakeup_source(&mut self, source: WakeupSource) {
+        let pmc = unsafe { &(*PMC::ptr()) };
+        pmc.pmc_fsmr
+            .modify(|r, w| unsafe { w.bits(r.bits() & !source.mask()) });
+    }
+
+    /// Clocks the USB controller from the UPLL (`PMC_USB`, `PMC_SCER.UOTGCLK`)
+    ///
+    /// The UPLL must be running, see `CFGR::enable_upll`.
+    pub fn enable_usb_clock(&mut self) {
+        let pmc = unsafe { &(*PMC::ptr()) };
+        pmc.pmc_usb
+            .write(|w| unsafe { w.usbs().set_bit().usbdiv().bits(0) });
+        pmc.pmc_scer.write_with_zero(|w| w.uotgclk().set_bit());
+    }
+
+    /// Starts monitoring the main crystal oscillator (`CKGR_MOR.CFDEN`)
+    ///
+    /// If the crystal stops, the PMC switches the main clock back to the fast RC oscillator and
+    /// latches the failure, see `clock_failure_detected`. The frequencies in `Clocks` are then
+    /// wrong until the clocks are configured again.
+    pub fn enable_clock_failure_detection(&mut self) {
+        let pmc = unsafe { &(*PMC::ptr()) };
+        pmc.ckgr_mor
+            .modify(|_, w| w.key().passwd().cfden().set_bit());
+    }
+
+    /// Stops monitoring the main crystal oscillator
+    pub fn disable_clock_failure_detection(&mut self) {
+        let pmc = unsafe { &(*PMC::ptr()) };
+        pmc.ckgr_mor
+            .modify(|_, w| w.key().passwd().cfden().clear_bit());
+    }
+
+    /// Raises the PMC interrupt when a clock failure is detected (`PMC_IER.CFDEV`)
+    pub fn listen_clock_failure(&mut self) {
+        let pmc = unsafe { &(*PMC::ptr()) };
+        pmc.pmc_ier.write_with_zero(|w| w.cfdev().set_bit());
+    }
+
+    /// Stops raising the PMC interrupt on clock failures
+    pub fn unlisten_clock_failure(&mut self) {
+        let pmc = unsafe { &(*PMC::ptr()) };
+        pmc.pmc_idr.write_with_zero(|w| w.cfdev().set_bit());
+    }
+
+    /// Returns whether a failure of the main crystal oscillator has been detected since the last
+    /// `clear_clock_failure` (`PMC_SR.FOS`)
+    pub fn clock_failure_detected(&self) -> bool {
+        let pmc = unsafe { &(*PMC::ptr()) };
+        pmc.pmc_sr.read().fos().bit_is_set()
+    }
+
+    /// Clears a detected clock failure (`PMC_FOCR.FOCLR`)
+    pub fn clear_clock_failure(&mut self) {
+        let pmc = unsafe { &(*PMC::ptr()) };
+        pmc.pmc_focr.write_with_zero(|w| w.foclr().set_bit());
+    }
+
+    /// Enters Sleep Mode until an interrupt occurs
+    ///
+    /// The clocks of all peripherals but the ones in `keep` are disabled while sleeping, and
+    /// re-enabled on wake-up.
+    pub fn sleep(&mut self, scb: &mut SCB, keep: &[PeripheralId]) {
+        let pmc = unsafe { &(*PMC::ptr()) };
+        let gated = self.gate_peripherals(keep);
+
+        scb.clear_sleepdeep();
+        pmc.pmc_fsmr.modify(|_, w| w.lpm().clear_bit());
+        cortex_m::asm::wfi();
+
+        self.ungate_peripherals(gated);
+    }
+
+    /// Enters Wait Mode until a wake-up source enabled with `enable_wakeup_source` fires
+    ///
+    /// The main clock is switched to the fast RC oscillator beforehand, as required by the
+    /// datasheet, and back to the crystal oscillator (if it was used) on wake-up. The Master
+    /// Clock runs from the main clock while it is switched, so the PLL isn't fed from a
+    /// changing input. Like `sleep`, the clocks of all peripherals but the ones in `keep` are
+    /// gated meanwhile.
+    ///
+    /// Fails with `ClockError::Timeout` if an oscillator or the Master Clock doesn't report
+    /// ready in time, leaving the clocks as they are at that point.
+    pub fn wait(&mut self, scb: &mut SCB, keep: &[PeripheralId]) -> Result<(), ClockError> {
+        let pmc = unsafe { &(*PMC::ptr()) };
+        let mck_ready = || pmc.pmc_sr.read().mckrdy().bit_is_set();
+        let moscsel_ready = || pmc.pmc_sr.read().moscsels().bit_is_set();
+
+        let crystal = pmc.ckgr_mor.read().moscsel().bit_is_set();
+        let css = pmc.pmc_mckr.read().css().bits();
+        if crystal {
+            pmc.ckgr_mor
+                .modify(|_, w| w.key().passwd().moscrcen().set_bit());
+            wait_ready(|| pmc.pmc_sr.read().moscrcs().bit_is_set())?;
+            if css != CSS_MAIN_CLK {
+                pmc.pmc_mckr.modify(|_, w| w.css().main_clk());
+                wait_ready(mck_ready)?;
+            }
+            pmc.ckgr_mor
+                .modify(|_, w| w.key().passwd().moscsel().clear_bit());
+            wait_ready(moscsel_ready)?;
+        }
+
+        let gated = self.gate_peripherals(keep);
+        scb.clear_sleepdeep();
+        pmc.pmc_fsmr.modify(|_, w| w.lpm().set_bit());
+        cortex_m::asm::wfe();
+        self.ungate_peripherals(gated);
+
+        if crystal {
+            pmc.ckgr_mor
+                .modify(|_, w| w.key().passwd().moscsel().set_bit());
+            wait_ready(moscsel_ready)?;
+            if css != CSS_MAIN_CLK {
+                if css == CSS_PLLA_CLK {
+                    wait_ready(|| pmc.pmc_sr.read().locka().bit_is_set())?;
+                }
+                pmc.pmc_mckr.modify(|_, w| w.css().bits(css));
+                wait_ready(mck_ready)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Disables the clocks of the enabled peripherals but the ones in `keep`, returning the
+    /// `PMC_PCSR0`/`PMC_PCSR1` bits that were disabled
+    fn gate_peripherals(&mut self, keep: &[PeripheralId]) -> (u32, u32) {
+        let (mut keep0, mut keep1) = (0, 0);
+        for &id in keep {
+            let id = id as u32;
+            if id < 32 {
+                keep0 |= 1 << id;
+            } else {
+                keep1 |= 1 << (id - 32);
+            }
+        }
+        let gated0 = self.pclk0.sr().read().bits() & !keep0;
+        let gated1 = self.pclk1.sr().read().bits() & !keep1;
+        self.pclk0.dr().write_with_zero(|w| unsafe { w.bits(gated0) });
+        self.pclk1.dr().write_with_zero(|w| unsafe { w.bits(gated1) });
+        (gated0, gated1)
+    }
+
+    /// Re-enables the peripheral clocks disabled by `gate_peripherals`
+    fn ungate_peripherals(&mut self, (gated0, gated1): (u32, u32)) {
+        self.pclk0.er().write_with_zero(|w| unsafe { w.bits(gated0) });
+        self.pclk1.er().write_with_zero(|w| unsafe { w.bits(gated1) });
+    }
+}
+
+/// Events able to wake the chip up from Wait Mode
+#[derive(Copy, Clone, PartialEq)]
+pub enum WakeupSource {
+    /// One of the WKUP0 to WKUP15 pins
+    WakeupPin(WakeupPin),
+    /// The Real-Time Timer alarm
+    RttAlarm,
+    /// The Real-Time Clock alarm
+    RtcAlarm,
+    /// A USB resume
+    UsbResume,
+}
+
+impl WakeupSource {
+    /// Bit of the source in `PMC_FSMR`
+    fn mask(self) -> u32 {
+        match self {
+            WakeupSource::WakeupPin(pin) => 1 << (pin as u32),
+            WakeupSource::RttAlarm => 1 << 16,
+            WakeupSource::RtcAlarm => 1 << 17,
+            WakeupSource::UsbResume => 1 << 18,
+        }
+    }
+}
+
+/// Wake-up inputs of the Supply Controller
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum WakeupPin {
+    WKUP0 = 0,
+    WKUP1 = 1,
+    WKUP2 = 2,
+    WKUP3 = 3,
+    WKUP4 = 4,
+    WKUP5 = 5,
+    WKUP6 = 6,
+    WKUP7 = 7,
+    WKUP8 = 8,
+    WKUP9 = 9,
+    WKUP10 = 10,
+    WKUP11 = 11,
+    WKUP12 = 12,
+    WKUP13 = 13,
+    WKUP14 = 14,
+    WKUP15 = 15,
+}
+
+/// Identifiers of the peripherals whose clock is controlled by the PMC
+///
+/// The TC identifiers are per channel: `TC0` to `TC2` are the channels of the TC0 block,
+/// `TC3` to `TC5` those of TC1 and `TC6` to `TC8` those of TC2.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PeripheralId {
+    UART = 8,
+    SMC = 9,
+    SDRAMC = 10,
+    PIOA = 11,
+    PIOB = 12,
+    PIOC = 13,
+    PIOD = 14,
+    PIOE = 15,
+    PIOF = 16,
+    USART0 = 17,
+    USART1 = 18,
+    USART2 = 19,
+    USART3 = 20,
+    HSMCI = 21,
+    TWI0 = 22,
+    TWI1 = 23,
+    SPI0 = 24,
+    SPI1 = 25,
+    SSC = 26,
+    TC0 = 27,
+    TC1 = 28,
+    TC2 = 29,
+    TC3 = 30,
+    TC4 = 31,
+    TC5 = 32,
+    TC6 = 33,
+    TC7 = 34,
+    TC8 = 35,
+    PWM = 36,
+    ADC = 37,
+    DACC = 38,
+    DMAC = 39,
+    UOTGHS = 40,
+    TRNG = 41,
+    EMAC = 42,
+    CAN0 = 43,
+    CAN1 = 44,
+}
+
 /// Peripheral clocks controlling pins from 8 to 31
 pub struct Pclk0 {
     _0: (),
@@ -50,7 +331,7 @@ impl Pclk0 {
         unsafe { &(*PMC::ptr()).pmc_pcdr0 }
     }
 
-    pub(crate) fn sr(&mut self) -> &pmc::PMC_PCSR0 {
+    pub(crate) fn sr(&self) -> &pmc::PMC_PCSR0 {
         // NOTE(unsafe) this proxy grants exclusive access to this register
         unsafe { &(*PMC::ptr()).pmc_pcsr0 }
     }
@@ -72,166 +353,468 @@ impl Pclk1 {
         unsafe { &(*PMC::ptr()).pmc_pcdr1 }
     }
 
-    pub(crate) fn sr(&mut self) -> &pmc::PMC_PCSR1 {
+    pub(crate) fn sr(&self) -> &pmc::PMC_PCSR1 {
         // NOTE(unsafe) this proxy grants exclusive access to this register
         unsafe { &(*PMC::ptr()).pmc_pcsr1 }
     }
 }
 
 const SLOW_CLOCK_FREQ: u32 = 32_768; //Hz
+const UPLL_CLOCK_FREQ: u32 = 480_000_000; //Hz
+// PLLA input (after DIVA) and output frequency ranges
+const PLLA_INPUT_MIN: u32 = 8_000_000; //Hz
+const PLLA_INPUT_MAX: u32 = 32_000_000; //Hz
+const PLLA_OUTPUT_MIN: u32 = 84_000_000; //Hz
+const PLLA_OUTPUT_MAX: u32 = 192_000_000; //Hz
+// PLLA lock time, in slow clock cycles
+const PLLA_COUNT: u8 = 0x3F;
+// UPLL start-up time, in multiples of 8 slow clock cycles
+const UPLL_COUNT: u8 = 3;
+// Peripheral clock limits of the datasheet
+const ADC_CLOCK_MIN: u32 = 1_000_000; //Hz
+const ADC_CLOCK_MAX: u32 = 20_000_000; //Hz
+const DACC_CLOCK_MAX: u32 = 50_000_000; //Hz
+const HSMCI_INIT_CLOCK: u32 = 400_000; //Hz
+// Polls of PMC_SR before a clock is deemed dead, over a second at the 4 MHz reset clock
+const READY_TIMEOUT: u32 = 1_000_000;
+// Values of PMC_MCKR.CSS
+const CSS_MAIN_CLK: u8 = 1;
+const CSS_PLLA_CLK: u8 = 2;
 
 /// Possible sources for Master clock
-#[derive(Copy, Clone)]
+///
+/// Unlike the SAM3S/SAM4S, the SAM3X has no PLLB (`CKGR_PLLBR`): its second PLL is the UPLL,
+/// which also clocks the USB controller.
+#[derive(Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum ClockSource {
     MainClock,
     SlowClock,
     PllClock,
-    //TODO: Support UPLLCK
+    /// The 480 MHz USB UTMI PLL, halved before the Master Clock prescaler
+    UpllClock,
+}
+
+/// Programmable clock outputs
+#[derive(Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Pck {
+    PCK0 = 0,
+    PCK1 = 1,
+    PCK2 = 2,
+}
+
+/// Possible sources for the programmable clocks
+#[derive(Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PckSource {
+    SlowClock,
+    MainClock,
+    PllClock,
+    UpllClock,
+    MasterClock,
+}
+
+/// Errors preventing a clock configuration from being frozen
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ClockError {
+    /// The requested master clock or main crystal frequency is zero
+    ZeroFrequency,
+    /// The requested master clock is faster than the selected source clock
+    MasterClockTooFast,
+    /// `CKGR_MOR` reports a fast RC oscillator frequency the datasheet doesn't define
+    UnknownMainClock,
+    /// No PLLA setting within the PLL input and output ranges reaches the requested master clock
+    PllUnreachable,
+    /// The UPLL can only multiply a 12 MHz main clock
+    UpllNeeds12MHz,
+    /// Programmable clocks can only be divided by a power of two up to 64
+    InvalidPckPrescaler,
+    /// An oscillator, PLL or the Master Clock didn't report ready in time
+    Timeout,
+    /// The clocks are too slow for a peripheral to reach its required frequency
+    PeripheralClockTooSlow(PeripheralId),
+    /// The clocks are too fast for a peripheral to be brought within its frequency limit
+    PeripheralClockTooFast(PeripheralId),
 }
 
 /// Clock configuration
+#[derive(Copy, Clone)]
 pub struct CFGR {
     /// Master Clock frequency
     master_clock: Option<u32>,
-    //TODO: Add support for programmable clocks
+    /// Frequency and start-up count of the main crystal oscillator, `None` keeps the fast RC
+    /// oscillator as main clock
+    main_crystal: Option<(u32, u8)>,
+    /// Source and prescaler of each programmable clock, `None` leaves it disabled
+    pcks: [Option<(PckSource, u8)>; 3],
     /// Master Clock's source clock
     clock_source: ClockSource,
+    /// Keep the UPLL running, even if it doesn't source the Master Clock
+    upll: bool,
 }
 
 impl CFGR {
     pub fn new() -> CFGR {
-        return CFGR{master_clock: None, clock_source: ClockSource::SlowClock}
+        return CFGR{master_clock: None, main_crystal: None, pcks: [None; 3], clock_source: ClockSource::SlowClock, upll: false}
     }
     ///Assign desired Master clock frequency
     pub fn master_clock(mut self, freq: impl Into<Hertz>) -> Self {
         self.master_clock = Some(freq.into().0);
         self
     }
+    ///Use an external crystal (12 MHz on the Arduino Due) as main clock instead of the fast
+    ///RC oscillator
+    ///
+    ///`startup` is the crystal start-up time in multiples of 8 slow clock cycles.
+    pub fn main_crystal(mut self, freq: impl Into<Hertz>, startup: u8) -> Self {
+        self.main_crystal = Some((freq.into().0, startup));
+        self
+    }
     ///Change clock source
     pub fn clock_source(mut self, src: ClockSource) -> Self {
         self.clock_source = src;
         self
     }
+    ///Enable a programmable clock output, dividing `src` by `div` (1, 2, 4, 8, 16, 32 or 64)
+    pub fn pck(mut self, pck: Pck, src: PckSource, div: u8) -> Self {
+        self.pcks[pck as usize] = Some((src, div));
+        self
+    }
+    ///Enable the UPLL (needed by the USB controller) whatever the Master clock source
+    pub fn enable_upll(mut self) -> Self {
+        self.upll = true;
+        self
+    }
 
     ///Freezes the clock frequencies making it effective
-    pub fn freeze(self) -> Clocks {
+    ///
+    /// Returns an error instead of touching the clock tree if the configuration can't be
+    /// achieved. For a quick start, `pmc.cfgr.freeze().unwrap()` panics on such errors.
+    ///
+    /// An oscillator, PLL or clock switch that doesn't get ready, e.g. because the crystal is
+    /// missing, fails with `ClockError::Timeout` rather than hanging. The clock tree is then
+    /// left half configured.
+    pub fn freeze(self) -> Result<Clocks, ClockError> {
         use sam3x8e::generic::Variant::Val;
 
         let pmc = unsafe { &(*PMC::ptr()) };
+        let mck_ready = || pmc.pmc_sr.read().mckrdy().bit_is_set();
         let mut mck = self.master_clock.unwrap_or(SLOW_CLOCK_FREQ);
-        let mut pres = 1u16;
-        let main_clock_freq = match pmc.ckgr_mor.read().moscrcf().variant() {
-            Val(pmc::ckgr_mor::MOSCRCF_A::_4_MHZ) => 4_000_000, //Hz
-            Val(pmc::ckgr_mor::MOSCRCF_A::_8_MHZ) => 8_000_000, //Hz
-            Val(pmc::ckgr_mor::MOSCRCF_A::_12_MHZ) => 12_000_000, //Hz
-            _ => unreachable!(),
+        let mut pllack = 0;
+        let main_clock_freq = match self.main_crystal {
+            Some((freq, _)) => freq,
+            None => match pmc.ckgr_mor.read().moscrcf().variant() {
+                Val(pmc::ckgr_mor::MOSCRCF_A::_4_MHZ) => 4_000_000, //Hz
+                Val(pmc::ckgr_mor::MOSCRCF_A::_8_MHZ) => 8_000_000, //Hz
+                Val(pmc::ckgr_mor::MOSCRCF_A::_12_MHZ) => 12_000_000, //Hz
+                _ => return Err(ClockError::UnknownMainClock),
+            },
         };
+        if mck == 0 || main_clock_freq == 0 {
+            return Err(ClockError::ZeroFrequency);
+        }
 
-        match self.clock_source {
+        let upll = self.upll
+            || self.clock_source == ClockSource::UpllClock
+            || self
+                .pcks
+                .iter()
+                .any(|pck| matches!(pck, Some((PckSource::UpllClock, _))));
+        for &(_, div) in self.pcks.iter().flatten() {
+            if !div.is_power_of_two() || div > 64 {
+                return Err(ClockError::InvalidPckPrescaler);
+            }
+        }
+        // UPLLCK is 40 times a 12 MHz main clock
+        if upll && main_clock_freq != 12_000_000 {
+            return Err(ClockError::UpllNeeds12MHz);
+        }
+
+        // Every setting is worked out before the first register write, so that only a
+        // timeout can leave the clock tree half configured
+        let (pll, pres_bits, pres) = match self.clock_source {
             ClockSource::PllClock => {
-                let pllmul: u16 =
-                    2 * (self.master_clock.unwrap_or(main_clock_freq) / main_clock_freq) as u16;
-                let pllmul = cmp::min(cmp::max(pllmul, 2), 2048);
-
-                //Actually safe as max value is guaranteed to be 2048
-                pmc.ckgr_pllar
-                    .write(|w| unsafe { w.diva().bits(2).mula().bits(pllmul - 1) });
-                while pmc.pmc_sr.read().locka().bit_is_clear() {}
-
-                pmc.pmc_mckr.write(|w| {
-                    //TODO: Think of something that utilizes the pre-scaler
-                    w.pres().clk_1();
-                    while pmc.pmc_sr.read().mckrdy().bit_is_clear() {}
-                    w.css().plla_clk();
-                    while pmc.pmc_sr.read().mckrdy().bit_is_clear() {}
-                    w
-                });
-                mck = main_clock_freq * u32::from(pllmul)
+                let requested = self.master_clock.unwrap_or(main_clock_freq);
+                let config =
+                    pll_config(main_clock_freq, requested).ok_or(ClockError::PllUnreachable)?;
+                (Some(config), config.pres_bits, config.pres)
             }
             ClockSource::SlowClock => {
-                let div = SLOW_CLOCK_FREQ / mck;
-
-                assert!(mck <= SLOW_CLOCK_FREQ);
-
-                let pres_bits = match div {
-                    0 => unreachable!(),
-                    1 => 0,
-                    2 => 1,
-                    3 => 7,
-                    4 => 2,
-                    5..=8 => 3,
-                    9..=16 => 4,
-                    17..=32 => 5,
-                    _ => 6,
-                };
+                let (pres_bits, div) = mck_prescaler(SLOW_CLOCK_FREQ, mck)?;
+                (None, pres_bits, div)
+            }
+            ClockSource::MainClock => {
+                let (pres_bits, div) = mck_prescaler(main_clock_freq, mck)?;
+                (None, pres_bits, div)
+            }
+            ClockSource::UpllClock => {
+                // UPLLDIV2 halves UPLLCK ahead of the prescaler
+                let (pres_bits, div) = mck_prescaler(UPLL_CLOCK_FREQ / 2, mck)?;
+                (None, pres_bits, div)
+            }
+        };
+
+        if let Some((_, startup)) = self.main_crystal {
+            // Start the crystal oscillator, then switch the main clock over once it's stable
+            pmc.ckgr_mor.modify(|_, w| unsafe {
+                w.key().passwd().moscxtst().bits(startup).moscxten().set_bit()
+            });
+            wait_ready(|| pmc.pmc_sr.read().moscxts().bit_is_set())?;
+            pmc.ckgr_mor
+                .modify(|_, w| w.key().passwd().moscsel().set_bit());
+            wait_ready(|| pmc.pmc_sr.read().moscsels().bit_is_set())?;
+        }
+
+        if upll {
+            pmc.ckgr_uckr
+                .write(|w| unsafe { w.upllcount().bits(UPLL_COUNT).upllen().set_bit() });
+            wait_ready(|| pmc.pmc_sr.read().locku().bit_is_set())?;
+        }
 
-                pmc.pmc_mckr.write(|w| {
-                    w.css().slow_clk();
-                    while pmc.pmc_sr.read().mckrdy().bit_is_clear() {}
-                    w.pres().bits(pres_bits);
-                    while pmc.pmc_sr.read().mckrdy().bit_is_clear() {}
-                    w
+        match self.clock_source {
+            ClockSource::PllClock => {
+                let config = pll.unwrap();
+
+                //Actually safe as mula is guaranteed to be at most 2047
+                //ONE must always be written as 1
+                pmc.ckgr_pllar.write(|w| unsafe {
+                    w.one()
+                        .set_bit()
+                        .pllacount()
+                        .bits(PLLA_COUNT)
+                        .diva()
+                        .bits(config.diva)
+                        .mula()
+                        .bits(config.mula)
                 });
-                pres = if div != 3 {
-                    2u16.pow(pres_bits.into())
-                } else {
-                    3
-                };
-                mck /= u32::from(pres)
+                wait_ready(|| pmc.pmc_sr.read().locka().bit_is_set())?;
+
+                pmc.pmc_mckr.modify(|_, w| w.pres().bits(pres_bits));
+                wait_ready(mck_ready)?;
+                pmc.pmc_mckr.modify(|_, w| w.css().plla_clk());
+                wait_ready(mck_ready)?;
+                pllack = config.pllack;
+                mck = config.mck
+            }
+            ClockSource::SlowClock => {
+                pmc.pmc_mckr.modify(|_, w| w.css().slow_clk());
+                wait_ready(mck_ready)?;
+                pmc.pmc_mckr.modify(|_, w| w.pres().bits(pres_bits));
+                wait_ready(mck_ready)?;
+                mck = SLOW_CLOCK_FREQ / u32::from(pres)
             }
             ClockSource::MainClock => {
-                let div = main_clock_freq / mck;
-
-                assert!(mck <= main_clock_freq);
-
-                let pres_bits = match div {
-                    0 => unreachable!(),
-                    1 => 0,
-                    2 => 1,
-                    3 => 7,
-                    4 => 2,
-                    5..=8 => 3,
-                    9..=16 => 4,
-                    17..=32 => 5,
-                    _ => 6,
-                };
-
-                pmc.pmc_mckr.write(|w| {
-                    w.css().main_clk();
-                    while pmc.pmc_sr.read().mckrdy().bit_is_clear() {}
-                    w.pres().bits(pres_bits);
-                    while pmc.pmc_sr.read().mckrdy().bit_is_clear() {}
-                    w
+                pmc.pmc_mckr.modify(|_, w| w.css().main_clk());
+                wait_ready(mck_ready)?;
+                pmc.pmc_mckr.modify(|_, w| w.pres().bits(pres_bits));
+                wait_ready(mck_ready)?;
+                mck = main_clock_freq / u32::from(pres);
+            }
+            ClockSource::UpllClock => {
+                pmc.pmc_mckr
+                    .modify(|_, w| w.uplldiv2().set_bit().pres().bits(pres_bits));
+                wait_ready(mck_ready)?;
+                pmc.pmc_mckr.modify(|_, w| w.css().upll_clk());
+                wait_ready(mck_ready)?;
+                mck = UPLL_CLOCK_FREQ / 2 / u32::from(pres);
+            }
+        };
+        let upllck = if upll { UPLL_CLOCK_FREQ } else { 0 };
+
+        let mut pcks = [0.hz(); 3];
+        for (i, pck) in self.pcks.iter().enumerate() {
+            if let Some((src, div)) = *pck {
+                let pres_bits = div.trailing_zeros() as u8;
+                pmc.pmc_pck[i].write(|w| {
+                    match src {
+                        PckSource::SlowClock => w.css().slow_clk(),
+                        PckSource::MainClock => w.css().main_clk(),
+                        PckSource::PllClock => w.css().plla_clk(),
+                        PckSource::UpllClock => w.css().upll_clk(),
+                        PckSource::MasterClock => w.css().mck(),
+                    };
+                    unsafe { w.pres().bits(pres_bits) }
+                });
+                pmc.pmc_scer.write_with_zero(|w| match i {
+                    0 => w.pck0().set_bit(),
+                    1 => w.pck1().set_bit(),
+                    _ => w.pck2().set_bit(),
                 });
-                pres = if div != 3 {
-                    2u16.pow(pres_bits.into())
-                } else {
-                    3
+                let pck_ready = || match i {
+                    0 => pmc.pmc_sr.read().pckrdy0().bit_is_set(),
+                    1 => pmc.pmc_sr.read().pckrdy1().bit_is_set(),
+                    _ => pmc.pmc_sr.read().pckrdy2().bit_is_set(),
                 };
-                mck /= u32::from(pres);
+                wait_ready(pck_ready)?;
+
+                let src_freq = match src {
+                    PckSource::SlowClock => SLOW_CLOCK_FREQ,
+                    PckSource::MainClock => main_clock_freq,
+                    PckSource::PllClock => pllack,
+                    PckSource::UpllClock => upllck,
+                    PckSource::MasterClock => mck,
+                };
+                pcks[i] = (src_freq / u32::from(div)).hz();
             }
-        };
-        Clocks {
+        }
+
+        Ok(Clocks {
             clock_source: self.clock_source,
+            requested_master_clock_freq: match self.clock_source {
+                ClockSource::PllClock => self.master_clock.unwrap_or(main_clock_freq),
+                _ => self.master_clock.unwrap_or(SLOW_CLOCK_FREQ),
+            }
+            .hz(),
             slck: SLOW_CLOCK_FREQ.hz(),
             main_clock_freq: main_clock_freq.hz(),
-            pllack: (mck / main_clock_freq).hz(),
+            pllack: pllack.hz(),
             master_clock_freq: mck.hz(),
+            hclk: mck.hz(),
+            upllck: upllck.hz(),
+            pcks,
             pres,
+        })
+    }
+}
+
+/// Master Clock prescalers as (`PMC_MCKR.pres` bits, division factor)
+const MCK_PRESCALERS: [(u8, u16); 8] = [
+    (0, 1),
+    (1, 2),
+    (7, 3),
+    (2, 4),
+    (3, 8),
+    (4, 16),
+    (5, 32),
+    (6, 64),
+];
+
+/// PLLA settings along with the Master Clock they produce
+#[derive(Copy, Clone)]
+struct PllConfig {
+    /// `CKGR_PLLAR.diva`, dividing the main clock at the PLL input
+    diva: u8,
+    /// `CKGR_PLLAR.mula`, the PLL multiplies its input by `mula + 1`
+    mula: u16,
+    /// `PMC_MCKR.pres` bits
+    pres_bits: u8,
+    /// Division factor of the Master Clock prescaler
+    pres: u16,
+    /// PLLA output frequency
+    pllack: u32,
+    /// Master Clock frequency
+    mck: u32,
+}
+
+/// Searches the PLLA divider, multiplier and Master Clock prescaler giving the Master Clock
+/// closest to `requested`, within the PLLA input and output frequency ranges
+fn pll_config(main_clock_freq: u32, requested: u32) -> Option<PllConfig> {
+    let mut best: Option<PllConfig> = None;
+    for diva in 1..=255u8 {
+        let pll_input = main_clock_freq / u32::from(diva);
+        if pll_input > PLLA_INPUT_MAX {
+            continue;
+        }
+        if pll_input < PLLA_INPUT_MIN {
+            break;
+        }
+        for &(pres_bits, pres) in MCK_PRESCALERS.iter() {
+            // Only the multipliers on either side of the ideal one can be the closest
+            let ideal = u64::from(requested) * u64::from(pres) * u64::from(diva)
+                / u64::from(main_clock_freq);
+            for pllmul in ideal..=ideal + 1 {
+                if pllmul < 2 || pllmul > 2048 {
+                    continue;
+                }
+                let pllack = u64::from(main_clock_freq) * pllmul / u64::from(diva);
+                if pllack < u64::from(PLLA_OUTPUT_MIN) || pllack > u64::from(PLLA_OUTPUT_MAX) {
+                    continue;
+                }
+                let mck = (pllack / u64::from(pres)) as u32;
+                let closer = match best {
+                    Some(best) => abs_diff(mck, requested) < abs_diff(best.mck, requested),
+                    None => true,
+                };
+                if closer {
+                    best = Some(PllConfig {
+                        diva,
+                        mula: pllmul as u16 - 1,
+                        pres_bits,
+                        pres,
+                        pllack: pllack as u32,
+                        mck,
+                    });
+                }
+            }
         }
     }
+    best
+}
+
+/// Polls `ready` until it returns true, failing with `ClockError::Timeout` after
+/// `READY_TIMEOUT` polls
+pub(crate) fn wait_ready(ready: impl Fn() -> bool) -> Result<(), ClockError> {
+    for _ in 0..READY_TIMEOUT {
+        if ready() {
+            return Ok(());
+        }
+    }
+    Err(ClockError::Timeout)
+}
+
+fn abs_diff(a: u32, b: u32) -> u32 {
+    if a > b {
+        a - b
+    } else {
+        b - a
+    }
+}
+
+/// Picks the Master Clock prescaler bringing `source` down to `mck`, or just below it
+///
+/// Returns the `PMC_MCKR.pres` bits along with the division factor.
+fn mck_prescaler(source: u32, mck: u32) -> Result<(u8, u16), ClockError> {
+    if mck > source {
+        return Err(ClockError::MasterClockTooFast);
+    }
+    let div = source / mck;
+
+    let pres_bits = match div {
+        0 => unreachable!(),
+        1 => 0,
+        2 => 1,
+        3 => 7,
+        4 => 2,
+        5..=8 => 3,
+        9..=16 => 4,
+        17..=32 => 5,
+        _ => 6,
+    };
+    let pres = if div != 3 {
+        2u16.pow(pres_bits.into())
+    } else {
+        3
+    };
+    Ok((pres_bits, pres))
 }
 
 ///Frozen clock frequencies
 ///
 /// Existence of this value indicates that the clock configuration cannot be changed
 #[derive(Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct Clocks {
     clock_source: ClockSource,
+    requested_master_clock_freq: Hertz,
     slck: Hertz,
     main_clock_freq: Hertz,
     pllack: Hertz,
     master_clock_freq: Hertz,
+    hclk: Hertz,
+    upllck: Hertz,
+    pcks: [Hertz; 3],
     pres: u16,
 }
 
@@ -249,7 +832,7 @@ impl Clocks {
         self.main_clock_freq
     }
 
-    /// Returns the frequency of PLLA clock
+    /// Returns the frequency of PLLA clock, or 0 Hz if the PLLA isn't used
     pub fn pllack(&self) -> Hertz {
         self.pllack
     }
@@ -262,8 +845,66 @@ impl Clocks {
         self.master_clock_freq
     }
 
+    /// Returns the Master Clock frequency that was asked for along with the one achieved
+    ///
+    /// The clock tree can only produce some frequencies, the closest one is picked.
+    pub fn requested_vs_actual(&self) -> (Hertz, Hertz) {
+        (self.requested_master_clock_freq, self.master_clock_freq)
+    }
+
+    /// Returns the frequency of the processor clock (HCLK)
+    ///
+    /// On the SAM3X the processor clock is the Master Clock itself, so this is always equal to
+    /// `master_clock_freq`. Code clocked by the core, like SysTick or the DWT cycle counter,
+    /// should use this rather than assume it runs from MCK.
+    pub fn hclk(&self) -> Hertz {
+        self.hclk
+    }
+
+    /// Returns the frequency of the USB UTMI PLL clock, or 0 Hz if the UPLL isn't running
+    pub fn upllck(&self) -> Hertz {
+        self.upllck
+    }
+
+    /// Returns the frequency of a programmable clock output, or 0 Hz if it is disabled
+    pub fn pck(&self, pck: Pck) -> Hertz {
+        self.pcks[pck as usize]
+    }
+
     /// Returns the value of prescaler in Master Clock controller
     pub fn pres(&self) -> u16 {
         self.pres
     }
+
+    /// Checks that the clocks let a peripheral run within its datasheet limits
+    ///
+    /// - ADC: the ADC clock, `MCK / 2` to `MCK / 512`, must reach 1 to 20 MHz
+    /// - DACC: the DAC clock, `MCK / 2`, mustn't exceed 50 MHz
+    /// - HSMCI: the card clock, `MCK / 2` to `MCK / 512`, must reach 400 kHz for card
+    ///   identification
+    /// - UOTGHS: the UPLL must run at 480 MHz
+    ///
+    /// Other peripherals have no constraint beyond the Master Clock.
+    pub fn validate(&self, peripheral: PeripheralId) -> Result<(), ClockError> {
+        let mck = self.master_clock_freq.0;
+        // Range the peripheral can divide its clock to, and the range it must reach
+        let ((lowest, highest), (min, max)) = match peripheral {
+            PeripheralId::ADC => ((mck / 512, mck / 2), (ADC_CLOCK_MIN, ADC_CLOCK_MAX)),
+            PeripheralId::DACC => ((mck / 2, mck / 2), (0, DACC_CLOCK_MAX)),
+            PeripheralId::HSMCI => ((mck / 512, mck / 2), (HSMCI_INIT_CLOCK, HSMCI_INIT_CLOCK)),
+            PeripheralId::UOTGHS => (
+                (self.upllck.0, self.upllck.0),
+                (UPLL_CLOCK_FREQ, UPLL_CLOCK_FREQ),
+            ),
+            _ => return Ok(()),
+        };
+
+        if highest < min {
+            Err(ClockError::PeripheralClockTooSlow(peripheral))
+        } else if lowest > max {
+            Err(ClockError::PeripheralClockTooFast(peripheral))
+        } else {
+            Ok(())
+        }
+    }
 }
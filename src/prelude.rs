@@ -0,0 +1,15 @@
+//! Prelude
+//!
+//! `use sam3x8e_hal::prelude::*;` brings the extension traits of this crate and the
+//! embedded-hal traits into scope, so their methods (`.split()`, `.hz()`, `.set_high()`,
+//! `.delay_ms()`, ...) can be called without importing each trait.
+
+pub use crate::gpio::GpioExt as _sam3x8e_hal_gpio_GpioExt;
+pub use crate::hal::digital::v2::InputPin as _embedded_hal_digital_v2_InputPin;
+pub use crate::hal::digital::v2::OutputPin as _embedded_hal_digital_v2_OutputPin;
+pub use crate::hal::digital::v2::StatefulOutputPin as _embedded_hal_digital_v2_StatefulOutputPin;
+pub use crate::hal::digital::v2::ToggleableOutputPin as _embedded_hal_digital_v2_ToggleableOutputPin;
+pub use crate::hal::prelude::*;
+pub use crate::pmc::PmcExt as _sam3x8e_hal_pmc_PmcExt;
+pub use crate::pwm::PwmExt as _sam3x8e_hal_pwm_PwmExt;
+pub use crate::time::U32Ext as _sam3x8e_hal_time_U32Ext;
@@ -1,23 +1,636 @@
 extern crate embedded_hal as hal;
 
+use core::cmp;
+
+use crate::gpio::pioa::{PA0, PA12, PA13, PA19, PA20, PA21, PA8, PA9};
+use crate::gpio::piob::{PB12, PB13, PB14, PB15, PB16, PB17, PB18, PB19};
+use crate::gpio::pioc::{
+    PC18, PC19, PC2, PC20, PC21, PC22, PC23, PC24, PC3, PC4, PC5, PC6, PC7, PC8, PC9,
+};
+use crate::gpio::{Alternate, PeriphB};
 use crate::pmc::Clocks;
-use sam3x8e::Peripherals;
+use crate::time::Hertz;
+
+/// Expands `$body` for the register of an eight-register family (`cmr0` to `cmr7`, `cmpv0` to
+/// `cmpv7`...) picked by a `Channel` or `Comparison`, bound to `$reg`
+///
+/// The registers of a family have distinct types in the PAC, so they can't be indexed; this
+/// writes the match over the eight of them in one place.
+macro_rules! per_channel {
+    ($pwm:expr, $index:expr, $r0:ident $r1:ident $r2:ident $r3:ident $r4:ident $r5:ident $r6:ident $r7:ident, |$reg:ident| $body:expr) => {
+        match $index as u8 {
+            0 => { let $reg = &$pwm.$r0; $body }
+            1 => { let $reg = &$pwm.$r1; $body }
+            2 => { let $reg = &$pwm.$r2; $body }
+            3 => { let $reg = &$pwm.$r3; $body }
+            4 => { let $reg = &$pwm.$r4; $body }
+            5 => { let $reg = &$pwm.$r5; $body }
+            6 => { let $reg = &$pwm.$r6; $body }
+            _ => { let $reg = &$pwm.$r7; $body }
+        }
+    };
+}
+
+macro_rules! cmr {
+    ($pwm:expr, $channel:expr, |$reg:ident| $body:expr) => {
+        per_channel!($pwm, $channel, cmr0 cmr1 cmr2 cmr3 cmr4 cmr5 cmr6 cmr7, |$reg| $body)
+    };
+}
+
+macro_rules! cprd {
+    ($pwm:expr, $channel:expr, |$reg:ident| $body:expr) => {
+        per_channel!($pwm, $channel, cprd0 cprd1 cprd2 cprd3 cprd4 cprd5 cprd6 cprd7, |$reg| $body)
+    };
+}
+
+macro_rules! cprdupd {
+    ($pwm:expr, $channel:expr, |$reg:ident| $body:expr) => {
+        per_channel!($pwm, $channel, cprdupd0 cprdupd1 cprdupd2 cprdupd3 cprdupd4 cprdupd5 cprdupd6 cprdupd7, |$reg| $body)
+    };
+}
+
+macro_rules! cdty {
+    ($pwm:expr, $channel:expr, |$reg:ident| $body:expr) => {
+        per_channel!($pwm, $channel, cdty0 cdty1 cdty2 cdty3 cdty4 cdty5 cdty6 cdty7, |$reg| $body)
+    };
+}
+
+macro_rules! cdtyupd {
+    ($pwm:expr, $channel:expr, |$reg:ident| $body:expr) => {
+        per_channel!($pwm, $channel, cdtyupd0 cdtyupd1 cdtyupd2 cdtyupd3 cdtyupd4 cdtyupd5 cdtyupd6 cdtyupd7, |$reg| $body)
+    };
+}
+
+macro_rules! dt {
+    ($pwm:expr, $channel:expr, |$reg:ident| $body:expr) => {
+        per_channel!($pwm, $channel, dt0 dt1 dt2 dt3 dt4 dt5 dt6 dt7, |$reg| $body)
+    };
+}
+
+macro_rules! cmpv {
+    ($pwm:expr, $comparison:expr, |$reg:ident| $body:expr) => {
+        per_channel!($pwm, $comparison, cmpv0 cmpv1 cmpv2 cmpv3 cmpv4 cmpv5 cmpv6 cmpv7, |$reg| $body)
+    };
+}
+
+macro_rules! cmpvupd {
+    ($pwm:expr, $comparison:expr, |$reg:ident| $body:expr) => {
+        per_channel!($pwm, $comparison, cmpvupd0 cmpvupd1 cmpvupd2 cmpvupd3 cmpvupd4 cmpvupd5 cmpvupd6 cmpvupd7, |$reg| $body)
+    };
+}
+
+macro_rules! cmpm {
+    ($pwm:expr, $comparison:expr, |$reg:ident| $body:expr) => {
+        per_channel!($pwm, $comparison, cmpm0 cmpm1 cmpm2 cmpm3 cmpm4 cmpm5 cmpm6 cmpm7, |$reg| $body)
+    };
+}
 
-struct PWM {
-    peripherals: Peripherals,
+/// Extension trait that constrains the PWM peripheral
+pub trait PwmExt {
+    /// Constrains the PWM peripheral so it plays nicely with the other abstractions
+    fn constrain_pwm(self, clocks: Clocks) -> PWM;
+}
+
+impl PwmExt for sam3x8e::PWM {
+    fn constrain_pwm(self, clocks: Clocks) -> PWM {
+        PWM::new(self, clocks)
+    }
+}
+
+/// Pulse Width Modulation Controller
+///
+/// A channel's waveform only shows up on pins muxed to the PWM, i.e. `ChannelPin`s in
+/// `Alternate<PeriphB>` mode. Channels are enabled through such a pin: `enable_output`, the
+/// `Output` of the `hal::Pwm` implementation and the `PwmChannel`s of `channel` all take one.
+pub struct PWM {
+    pwm: sam3x8e::PWM,
     clocks: Clocks,
+    /// Clock prescaler of each channel, indexed by `Channel`
+    prescalers: [Prescaler; 8],
+    /// Waveform alignment of each channel, indexed by `Channel`
+    alignments: [Alignment; 8],
+    /// Events read from `ISR1` and not cleared yet, reading `ISR1` clears it
+    pending_events: u32,
+    /// Division of MCK by CLKA and CLKB, 0 while the clock is turned off
+    linear_dividers: [u32; 2],
 }
 
 impl PWM {
-    fn new(peripherals: Peripherals, clocks: Clocks) -> Self {
+    /// Creates a new PWM abstraction owning the PWM peripheral and a copy of the frozen clocks
+    pub fn new(pwm: sam3x8e::PWM, clocks: Clocks) -> Self {
+        // CPOL is set on every channel, output waveforms start high unless told otherwise
+        pwm.wpcr.write_with_zero(|w| unsafe {
+            w.wpkey().bits(WPKEY).wpcmd().bits(0).wprg2().set_bit()
+        });
+        for channel in Channel::all() {
+            cmr!(pwm, channel, |cmr| cmr.write_with_zero(|w| w.cpol().set_bit()));
+        }
+
         PWM {
-            peripherals: peripherals,
-            clocks: clocks,
+            pwm,
+            clocks,
+            prescalers: [Prescaler::Div1; 8],
+            alignments: [Alignment::Left; 8],
+            pending_events: 0,
+            linear_dividers: [0; 2],
+        }
+    }
+
+    /// Disables all channels and releases the PWM peripheral and the clocks
+    pub fn free(self) -> (sam3x8e::PWM, Clocks) {
+        self.pwm.wpcr.write_with_zero(|w| unsafe {
+            w.wpkey().bits(WPKEY).wpcmd().bits(0).wprg1().set_bit()
+        });
+        self.pwm.dis.write_with_zero(|w| unsafe { w.bits(0xFF) });
+
+        (self.pwm, self.clocks)
+    }
+
+    /// Enables the channel driving a pin, which must be muxed to the PWM
+    ///
+    /// E.g. `pc21.into_peripheral_b()` gives PWML4, which enables CHID4.
+    pub fn enable_output<PIN: ChannelPin>(&mut self, _pin: &PIN) {
+        self.enable_channel(PIN::CHANNEL);
+    }
+
+    /// Returns whether a channel is currently enabled (`SR.chidx`)
+    pub fn is_enabled(&self, channel: Channel) -> bool {
+        self.pwm.sr.read().bits() & channel_mask(channel) != 0
+    }
+
+    /// Selects the clock prescaler of a channel
+    ///
+    /// The prescaler is written to `CMRx.cpre` when the channel is next enabled, since the
+    /// channel mode register can't be changed while the channel is running.
+    pub fn set_prescaler(&mut self, channel: Channel, prescaler: Prescaler) {
+        self.prescalers[channel as usize] = prescaler;
+    }
+
+    /// Returns the clock prescaler of a channel
+    pub fn prescaler(&self, channel: Channel) -> Prescaler {
+        self.prescalers[channel as usize]
+    }
+
+    /// Selects the waveform alignment of a channel
+    ///
+    /// Like the prescaler, the alignment is written to `CMRx.calg` when the channel is next
+    /// enabled. A center-aligned channel counts up and down, so its period is twice as long as a
+    /// left-aligned channel with the same `cprd`; the period setters account for that.
+    pub fn set_alignment(&mut self, channel: Channel, alignment: Alignment) {
+        self.alignments[channel as usize] = alignment;
+    }
+
+    /// Returns the waveform alignment of a channel
+    pub fn alignment(&self, channel: Channel) -> Alignment {
+        self.alignments[channel as usize]
+    }
+
+    /// Sets the output polarity of a channel by writing `CMRx.cpol`
+    ///
+    /// The channel mode register is only writable while the channel is disabled, so call this
+    /// before enabling the channel. Channels default to `Polarity::High`.
+    pub fn set_polarity(&mut self, channel: Channel, polarity: Polarity) {
+        let cpol = polarity == Polarity::High;
+        self.pwm.wpcr.write_with_zero(|w| unsafe {
+            w.wpkey().bits(WPKEY).wpcmd().bits(0).wprg2().set_bit()
+        });
+        cmr!(self.pwm, channel, |cmr| cmr.modify(|_, w| w.cpol().bit(cpol)));
+    }
+
+    /// Inserts dead-times (in nanoseconds) on the complementary PWMHx/PWMLx outputs of a channel
+    ///
+    /// Enables the dead-time generator (`CMRx.dte`) and programs `DTx`, counting in the channel's
+    /// clock, which must be turned on if it is CLKA/CLKB. Dead-times are clamped to the 12 bits of
+    /// the dead-time counters; dead-times longer than the high (PWMHx) or low (PWMLx) part of
+    /// the period are rejected. Call this while the channel is disabled and after its period and
+    /// duty cycle have been set.
+    pub fn set_dead_time(&mut self, channel: Channel, high_ns: u32, low_ns: u32) -> Result<(), PwmError> {
+        let divider = self.clock_divider(self.prescalers[channel as usize]);
+        if divider == 0 {
+            return Err(PwmError::ClockDisabled);
+        }
+        let channel_clock = u64::from(self.clocks.master_clock_freq().0 / divider);
+        let dth = cmp::min(u64::from(high_ns) * channel_clock / 1_000_000_000, u64::from(MAX_DT));
+        let dtl = cmp::min(u64::from(low_ns) * channel_clock / 1_000_000_000, u64::from(MAX_DT));
+
+        // The dead-time delays the rising edge of each output, so it must fit within the part
+        // of the period that output is active
+        let cprd = self.period_counts(channel);
+        let cdty = cmp::min(self.duty_counts(channel), cprd);
+        if dth > u64::from(cprd - cdty) || dtl > u64::from(cdty) {
+            return Err(PwmError::DeadTimeExceedsPeriod);
+        }
+        let (dth, dtl) = (dth as u16, dtl as u16);
+
+        self.pwm.wpcr.write_with_zero(|w| unsafe {
+            w.wpkey().bits(WPKEY).wpcmd().bits(0).wprg2().set_bit().wprg4().set_bit()
+        });
+        cmr!(self.pwm, channel, |cmr| cmr.modify(|_, w| w.dte().set_bit()));
+        dt!(self.pwm, channel, |dt| dt.write_with_zero(|w| unsafe { w.dth().bits(dth).dtl().bits(dtl) }));
+        Ok(())
+    }
+
+    /// Marks channels as synchronous (`SCM.syncx`), the other channels become asynchronous
+    ///
+    /// Synchronous channels share the counter of CHID0, which is therefore always part of the
+    /// group, and are started together by `start_sync`. Duty cycle and period changes of
+    /// running synchronous channels are applied together at the end of the period.
+    pub fn set_sync_channels(&mut self, channels: &[Channel]) {
+        let mask = channels.iter().fold(1, |mask, &channel| mask | 1 << (channel as u32));
+        self.pwm.wpcr.write_with_zero(|w| unsafe {
+            w.wpkey().bits(WPKEY).wpcmd().bits(0).wprg2().set_bit()
+        });
+        self.pwm.scm.modify(|r, w| unsafe { w.bits((r.bits() & !0xFF) | mask) });
+    }
+
+    /// Starts all synchronous channels in phase with a single write to `ENA`
+    pub fn start_sync(&mut self) {
+        let mask = self.pwm.scm.read().bits() & 0xFF;
+        self.pwm.wpcr.write_with_zero(|w| unsafe {
+            w.wpkey().bits(WPKEY).wpcmd().bits(0).wprg1().set_bit().wprg2().set_bit()
+        });
+        self.pwm.dis.write_with_zero(|w| unsafe { w.bits(mask) });
+        for channel in Channel::all() {
+            if mask & (1 << (channel as u32)) != 0 {
+                self.write_channel_mode(channel);
+            }
+        }
+        self.pwm.ena.write_with_zero(|w| unsafe { w.bits(mask) });
+    }
+
+    /// Returns whether a channel is synchronous (`SCM.syncx`)
+    pub fn is_sync(&self, channel: Channel) -> bool {
+        self.pwm.scm.read().bits() & channel_mask(channel) != 0
+    }
+
+    /// Lets the synchronous channels pick up their update registers (`SCUC.UPDULOCK`)
+    ///
+    /// In the manual update mode (`SCM.UPDM` = 0, the reset value) the `CDTYUPDx`/`CPRDUPDx`
+    /// of synchronous channels are only applied, all together at the end of the period, once
+    /// UPDULOCK is set. Does nothing for an asynchronous channel, whose update registers
+    /// apply on their own.
+    fn unlock_sync_update(&self, channel: Channel) {
+        if self.is_sync(channel) {
+            self.pwm.scuc.write_with_zero(|w| w.updulock().set_bit());
+        }
+    }
+
+    /// Enables a channel, first writing its prescaler and alignment to its mode register
+    fn enable_channel(&self, channel: Channel) {
+        self.pwm.wpcr.write_with_zero(|w| unsafe {
+            w.wpkey().bits(WPKEY).wpcmd().bits(0).wprg1().set_bit()
+        });
+
+        if self.is_enabled(channel) {
+            self.pwm.dis.write_with_zero(|w| unsafe { w.bits(channel_mask(channel)) });
+        }
+
+        self.write_channel_mode(channel);
+
+        self.pwm.ena.write_with_zero(|w| unsafe { w.bits(channel_mask(channel)) });
+    }
+
+    /// Disables a channel
+    fn disable_channel(&self, channel: Channel) {
+        self.pwm.wpcr.write_with_zero(|w| unsafe {
+            w.wpkey().bits(WPKEY).wpcmd().bits(0).wprg1().set_bit()
+        });
+        self.pwm.dis.write_with_zero(|w| unsafe { w.bits(channel_mask(channel)) });
+    }
+
+    /// Returns the period of a channel in channel clock counts (`CPRDx`)
+    fn period_counts(&self, channel: Channel) -> u32 {
+        cprd!(self.pwm, channel, |cprd| cprd.read().cprd().bits())
+    }
+
+    /// Returns the duty cycle of a channel in channel clock counts (`CDTYx`)
+    fn duty_counts(&self, channel: Channel) -> u32 {
+        cdty!(self.pwm, channel, |cdty| cdty.read().cdty().bits())
+    }
+
+    /// Writes the duty cycle of a channel in channel clock counts, clamped to the period
+    ///
+    /// While the channel is running, the duty goes through `CDTYUPDx` and takes effect at the end
+    /// of the current period, avoiding glitches.
+    fn write_duty_counts(&self, channel: Channel, cdty: u32) {
+        // A duty beyond CPRDx would leave the output stuck instead of giving a 100% duty cycle
+        let cdty = cmp::min(cdty, self.period_counts(channel));
+        // A running channel picks up the update register at the end of its current period,
+        // writing CDTYx directly would glitch the ongoing period
+        if self.is_enabled(channel) {
+            cdtyupd!(self.pwm, channel, |upd| upd.write_with_zero(|w| unsafe { w.cdtyupd().bits(cdty) }));
+            self.unlock_sync_update(channel);
+            return;
         }
+        cdty!(self.pwm, channel, |reg| reg.write_with_zero(|w| unsafe { w.cdty().bits(cdty) }));
+    }
+
+    /// Takes a pin muxed to the PWM as a `PwmChannel` driving the pin's channel, implementing
+    /// `embedded_hal::PwmPin`
+    ///
+    /// With the `eh1` feature, the channels also implement the embedded-hal 1.0 `SetDutyCycle`.
+    ///
+    /// The channels borrow the PWM, so it can't be reconfigured while they are in use. Periods,
+    /// prescalers and alignments should be set before taking channels.
+    pub fn channel<PIN: ChannelPin>(&self, pin: PIN) -> PwmChannel<'_, PIN> {
+        PwmChannel { pwm: self, pin }
+    }
+
+    /// Writes the prescaler and alignment of a channel to its mode register
+    fn write_channel_mode(&self, channel: Channel) {
+        // CALG is set for center-aligned channels, cleared for left-aligned ones
+        // CPOL is left as configured by `set_polarity`
+        let cpre = self.prescalers[channel as usize].cpre();
+        let calg = self.alignments[channel as usize] == Alignment::Center;
+        cmr!(self.pwm, channel, |cmr| cmr.modify(|_, w| unsafe { w.cpre().bits(cpre).calg().bit(calg) }));
+    }
+
+    /// Starts listening for an event of a channel, enabling its interrupt in `IER1`
+    pub fn listen(&mut self, channel: Channel, event: Event) {
+        self.pwm.ier1.write_with_zero(|w| unsafe { w.bits(event.mask(channel)) });
+    }
+
+    /// Stops listening for an event of a channel, disabling its interrupt in `IDR1`
+    pub fn unlisten(&mut self, channel: Channel, event: Event) {
+        self.pwm.idr1.write_with_zero(|w| unsafe { w.bits(event.mask(channel)) });
+    }
+
+    /// Returns whether an event of a channel has occurred since it was last cleared
+    ///
+    /// Reading `ISR1` clears all of its flags at once, so the flags read are kept until
+    /// `clear_event` is called for them. This way checking one event doesn't lose the others.
+    pub fn is_event_pending(&mut self, channel: Channel, event: Event) -> bool {
+        self.pending_events |= self.pwm.isr1.read().bits();
+        self.pending_events & event.mask(channel) != 0
+    }
+
+    /// Clears a pending event of a channel
+    pub fn clear_event(&mut self, channel: Channel, event: Event) {
+        self.pending_events |= self.pwm.isr1.read().bits();
+        self.pending_events &= !event.mask(channel);
+    }
+
+    /// Selects the active level of a fault input (`FMR.fpol`)
+    pub fn set_fault_polarity(&mut self, fault: Fault, active: Level) {
+        let mask = 1 << (fault as u8);
+        self.pwm.wpcr.write_with_zero(|w| unsafe {
+            w.wpkey().bits(WPKEY).wpcmd().bits(0).wprg5().set_bit()
+        });
+        self.pwm.fmr.modify(|r, w| unsafe {
+            match active {
+                Level::High => w.fpol().bits(r.fpol().bits() | mask),
+                Level::Low => w.fpol().bits(r.fpol().bits() & !mask),
+            }
+        });
+    }
+
+    /// Protects a channel with a fault input
+    ///
+    /// When the fault input becomes active, both outputs of the channel are forced to
+    /// `forced_value` by the hardware until the fault is cleared.
+    pub fn enable_fault(&mut self, channel: Channel, fault: Fault, forced_value: Level) {
+        let mask = 1 << (fault as u8);
+        let forced = forced_value == Level::High;
+        self.pwm.wpcr.write_with_zero(|w| unsafe {
+            w.wpkey().bits(WPKEY).wpcmd().bits(0).wprg5().set_bit()
+        });
+        self.pwm.fpv.modify(|r, w| unsafe {
+            let mask = output_mask(channel);
+            w.bits(if forced { r.bits() | mask } else { r.bits() & !mask })
+        });
+        let fpe = mask << fpe_shift(channel);
+        if (channel as u8) < 4 {
+            self.pwm.fpe1.modify(|r, w| unsafe { w.bits(r.bits() | fpe) });
+        } else {
+            self.pwm.fpe2.modify(|r, w| unsafe { w.bits(r.bits() | fpe) });
+        }
+    }
+
+    /// Stops protecting a channel with a fault input
+    pub fn disable_fault(&mut self, channel: Channel, fault: Fault) {
+        let mask = 1 << (fault as u8);
+        self.pwm.wpcr.write_with_zero(|w| unsafe {
+            w.wpkey().bits(WPKEY).wpcmd().bits(0).wprg5().set_bit()
+        });
+        let fpe = mask << fpe_shift(channel);
+        if (channel as u8) < 4 {
+            self.pwm.fpe1.modify(|r, w| unsafe { w.bits(r.bits() & !fpe) });
+        } else {
+            self.pwm.fpe2.modify(|r, w| unsafe { w.bits(r.bits() & !fpe) });
+        }
+    }
+
+    /// Clears a fault, releasing the channels it protects once the fault input is inactive
+    pub fn clear_fault(&mut self, fault: Fault) {
+        self.pwm.fcr.write_with_zero(|w| unsafe { w.fclr().bits(1 << (fault as u8)) });
+    }
+
+    /// Returns whether a fault is currently active (`FSR.fs`), i.e. it tripped and hasn't been
+    /// cleared yet
+    pub fn is_fault_active(&self, fault: Fault) -> bool {
+        self.pwm.fsr.read().fs().bits() & (1 << (fault as u8)) != 0
+    }
+
+    /// Forces both outputs of a channel to a level, regardless of its duty cycle
+    ///
+    /// The override value is written to `OOV` and the override is applied right away through
+    /// `OSS`, not at the end of the period. The channel keeps counting, so its waveform comes
+    /// back in phase on `release_output`.
+    pub fn force_output(&mut self, channel: Channel, level: Level) {
+        let mask = output_mask(channel);
+        self.pwm.oov.modify(|r, w| unsafe {
+            match level {
+                Level::High => w.bits(r.bits() | mask),
+                Level::Low => w.bits(r.bits() & !mask),
+            }
+        });
+        self.pwm.oss.write_with_zero(|w| unsafe { w.bits(mask) });
+    }
+
+    /// Stops overriding the outputs of a channel (`OSC`), giving them back to the waveform
+    pub fn release_output(&mut self, channel: Channel) {
+        self.pwm.osc.write_with_zero(|w| unsafe { w.bits(output_mask(channel)) });
+    }
+
+    /// Returns the level the outputs of a channel are forced to, or `None` if they follow the
+    /// waveform (`OS` and `OOV`)
+    pub fn forced_output(&self, channel: Channel) -> Option<Level> {
+        let mask = output_mask(channel);
+        if self.pwm.os.read().bits() & mask == 0 {
+            None
+        } else if self.pwm.oov.read().bits() & mask != 0 {
+            Some(Level::High)
+        } else {
+            Some(Level::Low)
+        }
+    }
+
+    /// Enables a comparison unit, matching when the counter of CHID0 reaches `value`
+    ///
+    /// A match pulses the event lines the comparison is routed to with `route_comparison`, e.g.
+    /// to trigger the ADC at a precise point of the period. On a center-aligned CHID0 the
+    /// counter reaches most values twice per period, `direction` picks the one that matches
+    /// (`CMPVx.cvm`): to sample in the middle of the low side of a waveform starting high, compare
+    /// with `CPRD0` while counting up. While CHID0 is running, the value goes through `CMPVUPDx`
+    /// and takes effect at the end of the current period.
+    pub fn set_comparison(&mut self, comparison: Comparison, value: u32, direction: CountDirection) {
+        let value = cmp::min(value, MAX_CV);
+        let cvm = direction == CountDirection::Down;
+        if self.is_enabled(Channel::CHID0) {
+            cmpvupd!(self.pwm, comparison, |upd| upd.write_with_zero(|w| unsafe { w.cvupd().bits(value).cvmupd().bit(cvm) }));
+        } else {
+            cmpv!(self.pwm, comparison, |cmpv| cmpv.write_with_zero(|w| unsafe { w.cv().bits(value).cvm().bit(cvm) }));
+        }
+        cmpm!(self.pwm, comparison, |cmpm| cmpm.write_with_zero(|w| w.cen().set_bit()));
+    }
+
+    /// Disables a comparison unit (`CMPMx.cen`)
+    pub fn disable_comparison(&mut self, comparison: Comparison) {
+        cmpm!(self.pwm, comparison, |cmpm| cmpm.write_with_zero(|w| w.cen().clear_bit()));
+    }
+
+    /// Makes the matches of a comparison unit pulse an event line (`ELMRx.cselx`)
+    ///
+    /// Several comparisons can be routed to the same line, and a comparison to both lines.
+    pub fn route_comparison(&mut self, comparison: Comparison, line: EventLine) {
+        self.pwm.elmr[line as usize].modify(|r, w| unsafe { w.bits(r.bits() | 1 << (comparison as u32)) });
+    }
+
+    /// Stops a comparison unit from pulsing an event line
+    pub fn unroute_comparison(&mut self, comparison: Comparison, line: EventLine) {
+        self.pwm.elmr[line as usize].modify(|r, w| unsafe { w.bits(r.bits() & !(1 << (comparison as u32))) });
+    }
+
+    /// Turns on CLKA at the frequency closest to `freq`, returning the frequency reached
+    ///
+    /// CLKA is MCK divided by a power-of-two prescaler (`CLK.prea`) and a linear divider from 1
+    /// to 255 (`CLK.diva`), reaching frequencies down to MCK / 261120. Channels use it through
+    /// `Prescaler::ClkA`; changing it affects all of them.
+    pub fn set_clock_a(&mut self, freq: Hertz) -> Result<Hertz, PwmError> {
+        let (pre, div) = self.linear_divider(freq)?;
+        self.pwm.wpcr.write_with_zero(|w| unsafe {
+            w.wpkey().bits(WPKEY).wpcmd().bits(0).wprg0().set_bit()
+        });
+        self.pwm.clk.modify(|_, w| unsafe { w.prea().bits(pre).diva().bits(div) });
+        self.linear_dividers[0] = u32::from(div) << pre;
+        Ok(Hertz(self.clocks.master_clock_freq().0 / self.linear_dividers[0]))
+    }
+
+    /// Turns on CLKB at the frequency closest to `freq`, returning the frequency reached
+    ///
+    /// See `set_clock_a`.
+    pub fn set_clock_b(&mut self, freq: Hertz) -> Result<Hertz, PwmError> {
+        let (pre, div) = self.linear_divider(freq)?;
+        self.pwm.wpcr.write_with_zero(|w| unsafe {
+            w.wpkey().bits(WPKEY).wpcmd().bits(0).wprg0().set_bit()
+        });
+        self.pwm.clk.modify(|_, w| unsafe { w.preb().bits(pre).divb().bits(div) });
+        self.linear_dividers[1] = u32::from(div) << pre;
+        Ok(Hertz(self.clocks.master_clock_freq().0 / self.linear_dividers[1]))
+    }
+
+    /// Returns the factor by which a prescaler divides MCK, or 0 for CLKA/CLKB while turned off
+    pub fn clock_divider(&self, prescaler: Prescaler) -> u32 {
+        match prescaler {
+            Prescaler::ClkA => self.linear_dividers[0],
+            Prescaler::ClkB => self.linear_dividers[1],
+            _ => 1 << (prescaler as u32),
+        }
+    }
+
+    /// Finds the `(pre, div)` of CLKA/CLKB closest to `freq`, with the smallest prescaler and
+    /// thus the finest resolution
+    fn linear_divider(&self, freq: Hertz) -> Result<(u8, u8), PwmError> {
+        let mck = self.clocks.master_clock_freq().0;
+        if freq.0 == 0 || freq.0 > mck {
+            return Err(PwmError::ClockOutOfRange);
+        }
+        (0..=MAX_PRE)
+            .map(|pre| (pre, ((mck >> pre) + freq.0 / 2) / freq.0))
+            .find(|&(_, div)| div <= MAX_DIV)
+            .map(|(pre, div)| (pre as u8, cmp::max(div, 1) as u8))
+            .ok_or(PwmError::ClockOutOfRange)
+    }
+
+    /// Returns the period of a channel in channel clock counts (`CPRDx`)
+    ///
+    /// Together with `get_duty_counts` and `set_duty_counts` this works on the raw register
+    /// values without any floating point math, e.g. for use in interrupt handlers.
+    pub fn get_period_counts(&self, channel: Channel) -> u16 {
+        self.period_counts(channel) as u16
+    }
+
+    /// Returns the duty cycle of a channel in channel clock counts (`CDTYx`)
+    pub fn get_duty_counts(&self, channel: Channel) -> u16 {
+        self.duty_counts(channel) as u16
+    }
+
+    /// Sets the duty cycle of a channel in channel clock counts, from 0 to the period counts
+    ///
+    /// Counts beyond the period are clamped to it, a 100% duty cycle. Like `set_duty`, a
+    /// running channel picks up the new duty cycle at the end of its
+    /// current period.
+    pub fn set_duty_counts(&mut self, channel: Channel, duty: u16) {
+        self.write_duty_counts(channel, u32::from(duty));
+    }
+
+    /// Sets the period (in seconds) of a single channel, leaving the other channels untouched
+    ///
+    /// The fastest prescaler at which the period still fits in the channel counter is selected
+    /// for the channel, among the power-of-two prescalers and CLKA/CLKB if they are turned on.
+    /// A running channel keeps its prescaler if the period fits with it: the period then goes
+    /// through `CPRDUPDx` and takes effect at the end of the current period. Otherwise the
+    /// channel is stopped, reprogrammed with the same duty ratio and restarted, since its
+    /// prescaler can't change while it runs.
+    pub fn set_channel_period(&mut self, channel: Channel, period: f32) {
+        // Pick the fastest channel clock at which the period still fits in the counter
+        let ticks = period * self.clocks.master_clock_freq().0 as f32
+            / self.alignments[channel as usize].factor() as f32;
+        let fits = |p: Prescaler| {
+            self.clock_divider(p) != 0 && ticks / self.clock_divider(p) as f32 <= MAX_CPRD as f32
+        };
+        let prescalers = Prescaler::ALL
+            .iter()
+            .chain(Prescaler::LINEAR.iter())
+            .cloned()
+            .filter(|&p| self.clock_divider(p) != 0);
+        let running = self.is_enabled(channel);
+        let current = self.prescalers[channel as usize];
+        let prescaler = if running && fits(current) {
+            // Keep the clock of a running channel when possible, so the change is glitch-free
+            current
+        } else {
+            prescalers
+                .clone()
+                .filter(|&p| fits(p))
+                .min_by_key(|&p| self.clock_divider(p))
+                .or_else(|| prescalers.max_by_key(|&p| self.clock_divider(p)))
+                .unwrap_or(Prescaler::Div1024)
+        };
+        let cprd = cmp::min((ticks / self.clock_divider(prescaler) as f32) as u32, MAX_CPRD);
+        self.prescalers[channel as usize] = prescaler;
+        self.pwm.wpcr.write_with_zero(|w| unsafe { w.wpkey().bits(WPKEY).wpcmd().bits(0).wprg3().set_bit() });
+        if running && prescaler == current {
+            cprdupd!(self.pwm, channel, |upd| upd.write_with_zero(|w| unsafe { w.cprdupd().bits(cprd) }));
+            self.unlock_sync_update(channel);
+            return;
+        }
+        if running {
+            // CMRx.cpre can't change while the channel runs: stop it, reprogram it with the
+            // same duty ratio and start it again
+            let old_cprd = self.period_counts(channel);
+            let duty = if old_cprd == 0 { 0.0 } else { self.duty_counts(channel) as f32 / old_cprd as f32 };
+            self.disable_channel(channel);
+            cprd!(self.pwm, channel, |reg| reg.write_with_zero(|w| unsafe { w.cprd().bits(cprd) }));
+            self.write_duty_counts(channel, (duty * cprd as f32) as u32);
+            self.enable_channel(channel);
+            return;
+        }
+        cprd!(self.pwm, channel, |reg| reg.write_with_zero(|w| unsafe { w.cprd().bits(cprd) }));
     }
 }
 
-#[derive(PartialEq)]
+#[derive(Clone, Copy, PartialEq)]
 pub enum Channel {
     CHID0 = 0,
     CHID1 = 1,
@@ -29,88 +642,315 @@ pub enum Channel {
     CHID7 = 7,
 }
 
+impl Channel {
+    /// All channels, in index order
+    const ALL: [Channel; 8] = [
+        Channel::CHID0,
+        Channel::CHID1,
+        Channel::CHID2,
+        Channel::CHID3,
+        Channel::CHID4,
+        Channel::CHID5,
+        Channel::CHID6,
+        Channel::CHID7,
+    ];
+
+    /// Returns the channel with index `index`, if there is one
+    pub fn from_index(index: u8) -> Option<Channel> {
+        Channel::ALL.get(usize::from(index)).copied()
+    }
+
+    /// Iterates over the eight channels, from CHID0 to CHID7
+    pub fn all() -> impl Iterator<Item = Channel> {
+        Channel::ALL.iter().copied()
+    }
+}
+
+impl From<Channel> for u8 {
+    fn from(channel: Channel) -> u8 {
+        channel as u8
+    }
+}
+
+/// Pin that outputs the waveform of a PWM channel, as PWMHx or PWMLx
+pub trait ChannelPin {
+    /// Channel whose output the pin is
+    const CHANNEL: Channel;
+}
+
+/// Channel of a pin muxed to the PWM, the channel type of the `hal::Pwm` implementation
+///
+/// Getting one borrows a pin muxed to the channel, which only proves the pin was muxed at that
+/// time: `Output` is `Copy`, as `hal::Pwm` takes channels by value, so it outlives the borrow
+/// and still drives the channel after the pin has been freed or muxed elsewhere. Use
+/// `PWM::channel` to keep the pin for as long as the channel is driven.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Output {
+    channel: Channel,
+}
+
+impl Output {
+    /// Returns the output of the channel driving a pin
+    pub fn new<PIN: ChannelPin>(_pin: &PIN) -> Self {
+        Output { channel: PIN::CHANNEL }
+    }
+
+    /// Returns which channel this is
+    pub fn channel(self) -> Channel {
+        self.channel
+    }
+}
+
+macro_rules! channel_pins {
+    ($($PXi:ident: $CHANNEL:ident,)+) => {
+        $(
+            impl ChannelPin for $PXi<Alternate<PeriphB>> {
+                const CHANNEL: Channel = Channel::$CHANNEL;
+            }
+        )+
+    }
+}
+
+channel_pins! {
+    // PWMHx
+    PA8: CHID0,
+    PB12: CHID0,
+    PC3: CHID0,
+    PA19: CHID1,
+    PB13: CHID1,
+    PC5: CHID1,
+    PA13: CHID2,
+    PB14: CHID2,
+    PC7: CHID2,
+    PA9: CHID3,
+    PB15: CHID3,
+    PC9: CHID3,
+    PC20: CHID4,
+    PC19: CHID5,
+    PC18: CHID6,
+    // PWMLx
+    PA21: CHID0,
+    PB16: CHID0,
+    PC2: CHID0,
+    PA12: CHID1,
+    PB17: CHID1,
+    PC4: CHID1,
+    PA20: CHID2,
+    PB18: CHID2,
+    PC6: CHID2,
+    PA0: CHID3,
+    PB19: CHID3,
+    PC8: CHID3,
+    PC21: CHID4,
+    PC22: CHID5,
+    PC23: CHID6,
+    PC24: CHID7,
+}
+
+/// Channel clock prescaler, dividing the Master Clock (MCK)
+#[derive(Clone, Copy, PartialEq)]
+pub enum Prescaler {
+    Div1 = 0,
+    Div2 = 1,
+    Div4 = 2,
+    Div8 = 3,
+    Div16 = 4,
+    Div32 = 5,
+    Div64 = 6,
+    Div128 = 7,
+    Div256 = 8,
+    Div512 = 9,
+    Div1024 = 10,
+    /// Linear clock CLKA, see `PWM::set_clock_a`
+    ClkA = 11,
+    /// Linear clock CLKB, see `PWM::set_clock_b`
+    ClkB = 12,
+}
+
+impl Prescaler {
+    /// All prescalers, from the fastest to the slowest channel clock
+    const ALL: [Prescaler; 11] = [
+        Prescaler::Div1,
+        Prescaler::Div2,
+        Prescaler::Div4,
+        Prescaler::Div8,
+        Prescaler::Div16,
+        Prescaler::Div32,
+        Prescaler::Div64,
+        Prescaler::Div128,
+        Prescaler::Div256,
+        Prescaler::Div512,
+        Prescaler::Div1024,
+    ];
+
+    /// The linear clocks
+    const LINEAR: [Prescaler; 2] = [Prescaler::ClkA, Prescaler::ClkB];
+
+    /// Value of the `CMRx.cpre` field selecting this prescaler
+    fn cpre(self) -> u8 {
+        self as u8
+    }
+
+    /// Returns the factor by which MCK is divided
+    ///
+    /// CLKA and CLKB are divided at runtime and return 0, use `PWM::clock_divider` for them.
+    pub fn factor(self) -> u32 {
+        match self {
+            Prescaler::ClkA | Prescaler::ClkB => 0,
+            _ => 1 << (self as u32),
+        }
+    }
+}
+
+/// Alignment of a channel's waveform within its period
+#[derive(Clone, Copy, PartialEq)]
+pub enum Alignment {
+    /// The counter counts up and resets at the end of the period
+    Left,
+    /// The counter counts up and then down, doubling the period
+    Center,
+}
+
+impl Alignment {
+    /// Returns how many channel clock ticks each `cprd` count lasts
+    fn factor(self) -> u32 {
+        match self {
+            Alignment::Left => 1,
+            Alignment::Center => 2,
+        }
+    }
+}
+
+/// Output level of a channel at the start of its period
+#[derive(Clone, Copy, PartialEq)]
+pub enum Polarity {
+    /// The output waveform starts at low level
+    Low,
+    /// The output waveform starts at high level
+    High,
+}
+
+/// Channel events that can raise the PWM interrupt
+#[derive(Clone, Copy, PartialEq)]
+pub enum Event {
+    /// The channel counter reached the end of the period
+    Period,
+    /// A fault protecting the channel became active
+    Fault,
+}
+
+impl Event {
+    /// Bit of the event in `IER1`/`IDR1`/`IMR1`/`ISR1`
+    fn mask(self, channel: Channel) -> u32 {
+        match self {
+            Event::Period => 1 << (channel as u32),
+            Event::Fault => 1 << (16 + channel as u32),
+        }
+    }
+}
+
+/// Fault inputs of the PWM controller
+#[derive(Clone, Copy, PartialEq)]
+pub enum Fault {
+    FAULT0 = 0,
+    FAULT1 = 1,
+    FAULT2 = 2,
+    FAULT3 = 3,
+    FAULT4 = 4,
+    FAULT5 = 5,
+    FAULT6 = 6,
+    FAULT7 = 7,
+}
+
+/// Comparison units of the PWM controller, all comparing with the counter of CHID0
+#[derive(Clone, Copy, PartialEq)]
+pub enum Comparison {
+    CMP0 = 0,
+    CMP1 = 1,
+    CMP2 = 2,
+    CMP3 = 3,
+    CMP4 = 4,
+    CMP5 = 5,
+    CMP6 = 6,
+    CMP7 = 7,
+}
+
+/// Direction of the channel counter, only meaningful for center-aligned channels
+#[derive(Clone, Copy, PartialEq)]
+pub enum CountDirection {
+    Up,
+    Down,
+}
+
+/// PWM event lines, which can trigger ADC conversions (`adc::Trigger::PwmEvent0`/`PwmEvent1`)
+#[derive(Clone, Copy, PartialEq)]
+pub enum EventLine {
+    Line0 = 0,
+    Line1 = 1,
+}
+
+/// Logic level of a signal
+#[derive(Clone, Copy, PartialEq)]
+pub enum Level {
+    Low,
+    High,
+}
+
+/// PWM configuration errors
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PwmError {
+    /// A dead-time is longer than its output's active part of the period: `CPRDx - CDTYx` for
+    /// PWMHx, `CDTYx` for PWMLx
+    DeadTimeExceedsPeriod,
+    /// The requested CLKA/CLKB frequency can't be reached from MCK
+    ClockOutOfRange,
+    /// The channel is clocked by CLKA/CLKB while that clock is turned off
+    ClockDisabled,
+}
+
 // This implementation strives to do something useful over being perfect, as
 // the "unproven" hal::Pwm interface can't express the set of things
 // available on SAM3X
 
-// Prescaler is set to be the Master Clock (MCK) directly
 const WPKEY: u32 = 0x50574D;
-const CPRE: u8 = 0b0000; // Master Clock directly; i.e. No Prescaler
-const PRESCALER: f32 = 1.0;
+// The channel counters are 16 bits wide
+const MAX_CPRD: u32 = 0xFFFF;
+// The dead-time counters are 12 bits wide
+const MAX_DT: u16 = 0xFFF;
+// CLKA/CLKB divide MCK by up to 2^10 * 255
+const MAX_PRE: u32 = 10;
+const MAX_DIV: u32 = 0xFF;
+// The comparison values are 24 bits wide
+const MAX_CV: u32 = 0xFF_FFFF;
+
+/// Bit of a channel in `ENA`/`DIS`/`SR`
+fn channel_mask(channel: Channel) -> u32 {
+    1 << (channel as u32)
+}
+
+/// Position of the fault mask of a channel in `FPE1` (CHID0 to CHID3) or `FPE2` (CHID4 to CHID7)
+fn fpe_shift(channel: Channel) -> u32 {
+    8 * (channel as u32 % 4)
+}
+
+/// Bits of both outputs of a channel in `OOV`/`OS`/`OSS`/`OSC`, PWMHx at bit x and PWMLx at
+/// bit 16 + x
+fn output_mask(channel: Channel) -> u32 {
+    0x1_0001 << (channel as u32)
+}
 
 impl hal::Pwm for PWM {
-    type Channel = Channel;
+    type Channel = Output;
     type Time = f32; // Seconds
     type Duty = f32; // 0.0 ... 1.0
 
-    fn enable(&mut self, channel: Self::Channel) {
-        self.peripherals.PWM.wpcr.write_with_zero(|w| unsafe {
-            w.wpkey().bits(WPKEY).wpcmd().bits(0).wprg1().set_bit()
-        }); 
-
-        let pwm_sr = self.peripherals.PWM.sr.read();
-        let channel_enabled = match channel {
-            Channel::CHID0 => pwm_sr.chid0().bit_is_set(),
-            Channel::CHID1 => pwm_sr.chid1().bit_is_set(),
-            Channel::CHID2 => pwm_sr.chid2().bit_is_set(),
-            Channel::CHID3 => pwm_sr.chid3().bit_is_set(),
-            Channel::CHID4 => pwm_sr.chid4().bit_is_set(),
-            Channel::CHID5 => pwm_sr.chid5().bit_is_set(),
-            Channel::CHID6 => pwm_sr.chid6().bit_is_set(),
-            Channel::CHID7 => pwm_sr.chid7().bit_is_set(),
-        };
-        if channel_enabled {
-            self.peripherals.PWM.dis.write_with_zero(|w| match channel {
-                Channel::CHID0 => w.chid0().set_bit(),
-                Channel::CHID1 => w.chid1().set_bit(),
-                Channel::CHID2 => w.chid2().set_bit(),
-                Channel::CHID3 => w.chid3().set_bit(),
-                Channel::CHID4 => w.chid4().set_bit(),
-                Channel::CHID5 => w.chid5().set_bit(),
-                Channel::CHID6 => w.chid6().set_bit(),
-                Channel::CHID7 => w.chid7().set_bit(),
-            });
-        }
-
-        // CALG is cleared, all PWM is left-aligned
-        // CPOL is set, output waveform starts high
-        match channel {
-            Channel::CHID0 => self.peripherals.PWM.cmr0.write_with_zero(|w| unsafe { w.cpre().bits(CPRE).cpol().set_bit().calg().clear_bit() }),
-            Channel::CHID1 => self.peripherals.PWM.cmr1.write_with_zero(|w| unsafe { w.cpre().bits(CPRE).cpol().set_bit().calg().clear_bit() }),
-            Channel::CHID2 => self.peripherals.PWM.cmr2.write_with_zero(|w| unsafe { w.cpre().bits(CPRE).cpol().set_bit().calg().clear_bit() }),
-            Channel::CHID3 => self.peripherals.PWM.cmr3.write_with_zero(|w| unsafe { w.cpre().bits(CPRE).cpol().set_bit().calg().clear_bit() }),
-            Channel::CHID4 => self.peripherals.PWM.cmr4.write_with_zero(|w| unsafe { w.cpre().bits(CPRE).cpol().set_bit().calg().clear_bit() }),
-            Channel::CHID5 => self.peripherals.PWM.cmr5.write_with_zero(|w| unsafe { w.cpre().bits(CPRE).cpol().set_bit().calg().clear_bit() }),
-            Channel::CHID6 => self.peripherals.PWM.cmr6.write_with_zero(|w| unsafe { w.cpre().bits(CPRE).cpol().set_bit().calg().clear_bit() }),
-            Channel::CHID7 => self.peripherals.PWM.cmr7.write_with_zero(|w| unsafe { w.cpre().bits(CPRE).cpol().set_bit().calg().clear_bit() }),
-        }
-
-        self.peripherals.PWM.ena.write_with_zero(|w| match channel {
-            Channel::CHID0 => w.chid0().set_bit(),
-            Channel::CHID1 => w.chid1().set_bit(),
-            Channel::CHID2 => w.chid2().set_bit(),
-            Channel::CHID3 => w.chid3().set_bit(),
-            Channel::CHID4 => w.chid4().set_bit(),
-            Channel::CHID5 => w.chid5().set_bit(),
-            Channel::CHID6 => w.chid6().set_bit(),
-            Channel::CHID7 => w.chid7().set_bit(),
-        });
-    }
-
-    fn disable(&mut self, channel: Self::Channel) {
-        self.peripherals.PWM.wpcr.write_with_zero(|w| unsafe {
-            w.wpkey().bits(WPKEY).wpcmd().bits(0).wprg1().set_bit()
-        });
-        self.peripherals.PWM.dis.write_with_zero(|w| match channel {
-            Channel::CHID0 => w.chid0().set_bit(),
-            Channel::CHID1 => w.chid1().set_bit(),
-            Channel::CHID2 => w.chid2().set_bit(),
-            Channel::CHID3 => w.chid3().set_bit(),
-            Channel::CHID4 => w.chid4().set_bit(),
-            Channel::CHID5 => w.chid5().set_bit(),
-            Channel::CHID6 => w.chid6().set_bit(),
-            Channel::CHID7 => w.chid7().set_bit(),
-        })
+    fn enable(&mut self, output: Self::Channel) {
+        self.enable_channel(output.channel);
+    }
+
+    fn disable(&mut self, output: Self::Channel) {
+        self.disable_channel(output.channel);
     }
 
     fn get_period(&self) -> Self::Time {
@@ -118,37 +958,28 @@ impl hal::Pwm for PWM {
         // channels could have their own periods.
         // Do something here and find the first enabled channel and return that
         // period.
-        let sr = self.peripherals.PWM.sr.read();
         let master_clock_frequency= self.clocks.master_clock_freq().0 as f32;
 
-        let cprd = 
-            if sr.chid0().bit_is_set() { self.peripherals.PWM.cprd0.read().cprd().bits() }
-            else if sr.chid1().bit_is_set() { self.peripherals.PWM.cprd1.read().cprd().bits() }
-            else if sr.chid2().bit_is_set() { self.peripherals.PWM.cprd2.read().cprd().bits() }
-            else if sr.chid3().bit_is_set() { self.peripherals.PWM.cprd3.read().cprd().bits() }
-            else if sr.chid4().bit_is_set() { self.peripherals.PWM.cprd4.read().cprd().bits() }
-            else if sr.chid5().bit_is_set() { self.peripherals.PWM.cprd5.read().cprd().bits() }
-            else if sr.chid6().bit_is_set() { self.peripherals.PWM.cprd6.read().cprd().bits() }
-            else if sr.chid7().bit_is_set() { self.peripherals.PWM.cprd7.read().cprd().bits() }
-            else { 0 }
-        ;
+        let (cprd, index) = match Channel::all().find(|&channel| self.is_enabled(channel)) {
+            Some(channel) => (self.period_counts(channel), channel as usize),
+            None => (0, 0),
+        };
         if cprd == 0 {
             0.0
         } else {
-            (PRESCALER * cprd as f32) / master_clock_frequency
+            let ticks_per_count = self.clock_divider(self.prescalers[index]) * self.alignments[index].factor();
+            (ticks_per_count as f32 * cprd as f32) / master_clock_frequency
         }
     }
 
-    fn get_duty(&self, channel: Self::Channel) -> Self::Duty {
-        match channel {
-            Channel::CHID0 => {self.peripherals.PWM.cdty0.read().cdty().bits() as f32 / self.peripherals.PWM.cprd0.read().cprd().bits() as f32},
-            Channel::CHID1 => {self.peripherals.PWM.cdty1.read().cdty().bits() as f32 / self.peripherals.PWM.cprd1.read().cprd().bits() as f32},
-            Channel::CHID2 => {self.peripherals.PWM.cdty2.read().cdty().bits() as f32 / self.peripherals.PWM.cprd2.read().cprd().bits() as f32},
-            Channel::CHID3 => {self.peripherals.PWM.cdty3.read().cdty().bits() as f32 / self.peripherals.PWM.cprd3.read().cprd().bits() as f32},
-            Channel::CHID4 => {self.peripherals.PWM.cdty4.read().cdty().bits() as f32 / self.peripherals.PWM.cprd4.read().cprd().bits() as f32},
-            Channel::CHID5 => {self.peripherals.PWM.cdty5.read().cdty().bits() as f32 / self.peripherals.PWM.cprd5.read().cprd().bits() as f32},
-            Channel::CHID6 => {self.peripherals.PWM.cdty6.read().cdty().bits() as f32 / self.peripherals.PWM.cprd6.read().cprd().bits() as f32},
-            Channel::CHID7 => {self.peripherals.PWM.cdty7.read().cdty().bits() as f32 / self.peripherals.PWM.cprd7.read().cprd().bits() as f32},
+    /// Returns the duty cycle of a channel, or 0.0 if its period hasn't been set
+    fn get_duty(&self, output: Self::Channel) -> Self::Duty {
+        let channel = output.channel;
+        let (cdty, cprd) = (self.duty_counts(channel), self.period_counts(channel));
+        if cprd == 0 {
+            0.0
+        } else {
+            cdty as f32 / cprd as f32
         }
     }
 
@@ -156,45 +987,98 @@ impl hal::Pwm for PWM {
         1.0
     }
 
-    fn set_duty(&mut self, channel: Self::Channel, duty: Self::Duty) {
+    /// Sets the duty cycle of a channel
+    ///
+    /// The duty cycle saturates to `[0.0, 1.0]`, so a value slightly out of range gives a fully
+    /// low or high output rather than a count beyond the period; NaN counts as 0.0. While the
+    /// channel is running, the duty goes through `CDTYUPDx` and takes effect at the end of the
+    /// current period, avoiding glitches. Before the period is set the duty cycle is 0, and
+    /// `get_duty` returns 0.0.
+    fn set_duty(&mut self, output: Self::Channel, duty: Self::Duty) {
+        let channel = output.channel;
         // duty_f = duty_u / period_u
         // duty_f * period_u = duty_u
-        let cprd = match channel {
-            Channel::CHID0 => self.peripherals.PWM.cprd0.read().cprd().bits() as f32,
-            Channel::CHID1 => self.peripherals.PWM.cprd1.read().cprd().bits() as f32,
-            Channel::CHID2 => self.peripherals.PWM.cprd2.read().cprd().bits() as f32,
-            Channel::CHID3 => self.peripherals.PWM.cprd3.read().cprd().bits() as f32,
-            Channel::CHID4 => self.peripherals.PWM.cprd4.read().cprd().bits() as f32,
-            Channel::CHID5 => self.peripherals.PWM.cprd5.read().cprd().bits() as f32,
-            Channel::CHID6 => self.peripherals.PWM.cprd6.read().cprd().bits() as f32,
-            Channel::CHID7 => self.peripherals.PWM.cprd7.read().cprd().bits() as f32,
-        };
+        let cprd = self.period_counts(channel) as f32;
+        // f32::max returns the other operand for NaN
+        let duty = duty.max(0.0).min(1.0);
         let duty_u = (duty * cprd) as u32;
-        match channel {
-            Channel::CHID0 => self.peripherals.PWM.cdty0.write_with_zero(|w| unsafe { w.cdty().bits(duty_u)}),
-            Channel::CHID1 => self.peripherals.PWM.cdty1.write_with_zero(|w| unsafe { w.cdty().bits(duty_u)}),
-            Channel::CHID2 => self.peripherals.PWM.cdty2.write_with_zero(|w| unsafe { w.cdty().bits(duty_u)}),
-            Channel::CHID3 => self.peripherals.PWM.cdty3.write_with_zero(|w| unsafe { w.cdty().bits(duty_u)}),
-            Channel::CHID4 => self.peripherals.PWM.cdty4.write_with_zero(|w| unsafe { w.cdty().bits(duty_u)}),
-            Channel::CHID5 => self.peripherals.PWM.cdty5.write_with_zero(|w| unsafe { w.cdty().bits(duty_u)}),
-            Channel::CHID6 => self.peripherals.PWM.cdty6.write_with_zero(|w| unsafe { w.cdty().bits(duty_u)}),
-            Channel::CHID7 => self.peripherals.PWM.cdty7.write_with_zero(|w| unsafe { w.cdty().bits(duty_u)}),
-        }
+        self.write_duty_counts(channel, duty_u);
     }
 
+    /// Sets the same period on all eight channels
+    ///
+    /// Use `PWM::set_channel_period` to give a channel its own period.
     fn set_period<P>(&mut self, period: P)
     where
             P: Into<Self::Time> {
-        let cprd = ((period.into() * self.clocks.master_clock_freq().0 as f32) / PRESCALER) as u32;
-        self.peripherals.PWM.wpcr.write_with_zero(|w| unsafe { w.wpkey().bits(WPKEY).wpcmd().bits(0).wprg3().set_bit() });
-        self.peripherals.PWM.cprd0.write_with_zero(|w| unsafe { w.cprd().bits(cprd) });
-        self.peripherals.PWM.cprd1.write_with_zero(|w| unsafe { w.cprd().bits(cprd) });
-        self.peripherals.PWM.cprd2.write_with_zero(|w| unsafe { w.cprd().bits(cprd) });
-        self.peripherals.PWM.cprd3.write_with_zero(|w| unsafe { w.cprd().bits(cprd) });
-        self.peripherals.PWM.cprd4.write_with_zero(|w| unsafe { w.cprd().bits(cprd) });
-        self.peripherals.PWM.cprd5.write_with_zero(|w| unsafe { w.cprd().bits(cprd) });
-        self.peripherals.PWM.cprd6.write_with_zero(|w| unsafe { w.cprd().bits(cprd) });
-        self.peripherals.PWM.cprd7.write_with_zero(|w| unsafe { w.cprd().bits(cprd) });
+        let period = period.into();
+        for channel in Channel::all() {
+            self.set_channel_period(channel, period);
+        }
     }
 }
 
+
+/// A single PWM channel owning its output pin, with its duty cycle in raw channel clock counts
+pub struct PwmChannel<'a, PIN> {
+    pwm: &'a PWM,
+    pin: PIN,
+}
+
+impl<'a, PIN: ChannelPin> PwmChannel<'a, PIN> {
+    /// Returns which channel this is
+    pub fn channel(&self) -> Channel {
+        PIN::CHANNEL
+    }
+
+    /// Disables the channel and releases its pin
+    pub fn free(self) -> PIN {
+        self.pwm.disable_channel(PIN::CHANNEL);
+        self.pin
+    }
+}
+
+impl<'a, PIN: ChannelPin> hal::PwmPin for PwmChannel<'a, PIN> {
+    type Duty = u16; // 0 ... CPRDx
+
+    fn disable(&mut self) {
+        self.pwm.disable_channel(PIN::CHANNEL);
+    }
+
+    fn enable(&mut self) {
+        self.pwm.enable_channel(PIN::CHANNEL);
+    }
+
+    fn get_duty(&self) -> u16 {
+        self.pwm.duty_counts(PIN::CHANNEL) as u16
+    }
+
+    /// Returns the channel period (`CPRDx`), which is a 100% duty cycle
+    fn get_max_duty(&self) -> u16 {
+        self.pwm.period_counts(PIN::CHANNEL) as u16
+    }
+
+    /// Sets the duty cycle, clamped to `get_max_duty`
+    fn set_duty(&mut self, duty: u16) {
+        self.pwm.write_duty_counts(PIN::CHANNEL, u32::from(duty));
+    }
+}
+
+#[cfg(feature = "eh1")]
+impl<'a, PIN> hal1::pwm::ErrorType for PwmChannel<'a, PIN> {
+    type Error = core::convert::Infallible;
+}
+
+#[cfg(feature = "eh1")]
+impl<'a, PIN: ChannelPin> hal1::pwm::SetDutyCycle for PwmChannel<'a, PIN> {
+    /// Returns the channel period (`CPRDx`), which is a 100% duty cycle
+    fn max_duty_cycle(&self) -> u16 {
+        self.pwm.period_counts(PIN::CHANNEL) as u16
+    }
+
+    /// Sets the duty cycle, clamped to `max_duty_cycle`
+    fn set_duty_cycle(&mut self, duty: u16) -> Result<(), Self::Error> {
+        self.pwm.write_duty_counts(PIN::CHANNEL, u32::from(duty));
+        Ok(())
+    }
+}
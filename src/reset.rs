@@ -0,0 +1,45 @@
+//! Reset Controller (RSTC)
+//!
+//! The RSTC records the source of the last reset in `RSTC_SR.RSTTYP`. This tells a
+//! watchdog reset in the field apart from a power cycle or a press of the reset button.
+
+use sam3x8e::RSTC;
+
+/// Source of the last reset (`RSTC_SR.RSTTYP`)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ResetCause {
+    /// First power-up, VDDCORE rising
+    PowerOn,
+    /// Return from backup mode
+    Backup,
+    /// Watchdog underflow
+    Watchdog,
+    /// Reset requested through `RSTC_CR`, e.g. by `software_reset`
+    Software,
+    /// NRST pin driven low
+    User,
+}
+
+/// Returns the source of the last reset
+pub fn reset_cause(rstc: &RSTC) -> ResetCause {
+    match rstc.sr.read().rsttyp().bits() {
+        0 => ResetCause::PowerOn,
+        1 => ResetCause::Backup,
+        2 => ResetCause::Watchdog,
+        3 => ResetCause::Software,
+        _ => ResetCause::User,
+    }
+}
+
+/// Resets the processor and the peripherals (`RSTC_CR.PROCRST`/`PERRST`)
+///
+/// The reset cause reads `Software` afterwards.
+pub fn software_reset(rstc: &RSTC) -> ! {
+    rstc.cr
+        .write_with_zero(|w| w.key().passwd().procrst().set_bit().perrst().set_bit());
+
+    // the reset takes a few cycles to kick in
+    loop {
+        cortex_m::asm::nop();
+    }
+}
@@ -0,0 +1,293 @@
+//! Real-Time Clock (RTC)
+//!
+//! The RTC keeps calendar time from the slow clock and sits in the backup domain, so it
+//! needs no peripheral clock and keeps running across resets as long as VDDBU is powered.
+//!
+//! Time and date are stored as BCD in `RTC_TIMR` and `RTC_CALR`. They can only be written
+//! after stopping the RTC with `RTC_CR.UPDTIM`/`UPDCAL` and waiting for `RTC_SR.ACKUPD`,
+//! which the RTC sets on its next one-second tick. `set_datetime` therefore blocks for up
+//! to a second.
+//!
+//! # Events
+//!
+//! The RTC can interrupt on an alarm set with `set_alarm` and on each second, minute, hour
+//! or day. The minute, hour and day events share the time event flag (`RTC_SR.TIMEV`), so
+//! only one of them can be listened to at a time. Pending events must be cleared with
+//! `clear_event`, e.g. from the RTC interrupt handler.
+
+use sam3x8e::RTC;
+
+/// RTC error
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RtcError {
+    /// The RTC rejected the time (`RTC_VER.NVTIM`)
+    InvalidTime,
+    /// The RTC rejected the date (`RTC_VER.NVCAL`)
+    InvalidDate,
+    /// The year is outside the 1900 to 2099 range of the calendar
+    YearOutOfRange,
+}
+
+/// RTC events
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RtcEvent {
+    /// The time and date matched the alarm
+    Alarm,
+    /// Every second
+    Second,
+    /// Every minute change
+    Minute,
+    /// Every hour change
+    Hour,
+    /// Every day, at midnight
+    Day,
+}
+
+impl RtcEvent {
+    /// Bit of the event in `RTC_SR`, `RTC_SCCR`, `RTC_IER` and `RTC_IDR`
+    fn mask(self) -> u32 {
+        match self {
+            RtcEvent::Alarm => 1 << 1,
+            RtcEvent::Second => 1 << 2,
+            RtcEvent::Minute | RtcEvent::Hour | RtcEvent::Day => 1 << 3,
+        }
+    }
+}
+
+/// Calendar date and time, in 24-hour format
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DateTime {
+    /// 1900 to 2099
+    pub year: u16,
+    /// 1 to 12
+    pub month: u8,
+    /// Day of the month, 1 to 31
+    pub day: u8,
+    /// 0 to 23
+    pub hour: u8,
+    /// 0 to 59
+    pub minute: u8,
+    /// 0 to 59
+    pub second: u8,
+}
+
+impl DateTime {
+    /// Returns the day of the week, 1 (Monday) to 7 (Sunday)
+    pub fn weekday(&self) -> u8 {
+        const OFFSETS: [u16; 12] = [0, 3, 2, 5, 0, 3, 5, 1, 4, 6, 2, 4];
+
+        let year = if self.month < 3 {
+            self.year - 1
+        } else {
+            self.year
+        };
+        let month = usize::from(self.month.max(1).min(12)) - 1;
+        let sunday_based =
+            (year + year / 4 - year / 100 + year / 400 + OFFSETS[month] + u16::from(self.day)) % 7;
+
+        if sunday_based == 0 {
+            7
+        } else {
+            sunday_based as u8
+        }
+    }
+}
+
+fn to_bcd(value: u8) -> u8 {
+    ((value / 10) << 4) | (value % 10)
+}
+
+fn from_bcd(bcd: u8) -> u8 {
+    (bcd >> 4) * 10 + (bcd & 0xF)
+}
+
+/// Real-Time Clock
+pub struct Rtc {
+    rtc: RTC,
+}
+
+impl Rtc {
+    /// Takes the RTC and selects 24-hour mode (`RTC_MR.HRMOD`)
+    ///
+    /// The running time and date are left untouched.
+    pub fn new(rtc: RTC) -> Self {
+        rtc.mr.modify(|_, w| w.hrmod().clear_bit());
+
+        Rtc { rtc }
+    }
+
+    /// Returns the current date and time
+    ///
+    /// `RTC_TIMR` and `RTC_CALR` are read until two consecutive reads agree, so that a
+    /// tick between the reads can't give a mixed-up result.
+    pub fn datetime(&self) -> DateTime {
+        loop {
+            let timr = self.rtc.timr.read().bits();
+            let calr = self.rtc.calr.read().bits();
+
+            if timr == self.rtc.timr.read().bits() && calr == self.rtc.calr.read().bits() {
+                return DateTime {
+                    year: u16::from(from_bcd(calr as u8 & 0x7F)) * 100
+                        + u16::from(from_bcd((calr >> 8) as u8)),
+                    month: from_bcd((calr >> 16) as u8 & 0x1F),
+                    day: from_bcd((calr >> 24) as u8 & 0x3F),
+                    hour: from_bcd((timr >> 16) as u8 & 0x3F),
+                    minute: from_bcd((timr >> 8) as u8 & 0x7F),
+                    second: from_bcd(timr as u8 & 0x7F),
+                };
+            }
+        }
+    }
+
+    /// Sets the date and time
+    ///
+    /// Blocks until the RTC acknowledges the update, up to one second. The day of the week
+    /// is computed from the date.
+    pub fn set_datetime(&mut self, datetime: &DateTime) -> Result<(), RtcError> {
+        if !(1900..=2099).contains(&datetime.year) {
+            return Err(RtcError::YearOutOfRange);
+        }
+
+        self.rtc
+            .cr
+            .modify(|_, w| w.updtim().set_bit().updcal().set_bit());
+        while self.rtc.sr.read().ackupd().bit_is_clear() {}
+        self.rtc.sccr.write_with_zero(|w| w.ackclr().set_bit());
+
+        self.rtc.timr.write_with_zero(|w| unsafe {
+            w.hour()
+                .bits(to_bcd(datetime.hour))
+                .min()
+                .bits(to_bcd(datetime.minute))
+                .sec()
+                .bits(to_bcd(datetime.second))
+        });
+        self.rtc.calr.write_with_zero(|w| unsafe {
+            w.cent()
+                .bits(to_bcd((datetime.year / 100) as u8))
+                .year()
+                .bits(to_bcd((datetime.year % 100) as u8))
+                .month()
+                .bits(to_bcd(datetime.month))
+                .date()
+                .bits(to_bcd(datetime.day))
+                .day()
+                .bits(datetime.weekday())
+        });
+
+        self.rtc
+            .cr
+            .modify(|_, w| w.updtim().clear_bit().updcal().clear_bit());
+
+        let ver = self.rtc.ver.read();
+        if ver.nvtim().bit_is_set() {
+            Err(RtcError::InvalidTime)
+        } else if ver.nvcal().bit_is_set() {
+            Err(RtcError::InvalidDate)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Sets the alarm to a date and time (`RTC_TIMALR`/`RTC_CALALR`)
+    ///
+    /// The month, day, hour, minute and second must all match; the year is ignored, so the
+    /// alarm fires again a year later. The alarm interrupt is enabled with
+    /// `listen(RtcEvent::Alarm)`.
+    pub fn set_alarm(&mut self, datetime: &DateTime) -> Result<(), RtcError> {
+        self.rtc.timalr.modify(|_, w| {
+            w.secen()
+                .clear_bit()
+                .minen()
+                .clear_bit()
+                .houren()
+                .clear_bit()
+        });
+        self.rtc
+            .calalr
+            .modify(|_, w| w.mthen().clear_bit().dateen().clear_bit());
+
+        self.rtc.timalr.write_with_zero(|w| unsafe {
+            w.hour()
+                .bits(to_bcd(datetime.hour))
+                .houren()
+                .set_bit()
+                .min()
+                .bits(to_bcd(datetime.minute))
+                .minen()
+                .set_bit()
+                .sec()
+                .bits(to_bcd(datetime.second))
+                .secen()
+                .set_bit()
+        });
+        self.rtc.calalr.write_with_zero(|w| unsafe {
+            w.month()
+                .bits(to_bcd(datetime.month))
+                .mthen()
+                .set_bit()
+                .date()
+                .bits(to_bcd(datetime.day))
+                .dateen()
+                .set_bit()
+        });
+
+        self.rtc
+            .sccr
+            .write_with_zero(|w| unsafe { w.bits(RtcEvent::Alarm.mask()) });
+
+        let ver = self.rtc.ver.read();
+        if ver.nvtimalr().bit_is_set() {
+            Err(RtcError::InvalidTime)
+        } else if ver.nvcalalr().bit_is_set() {
+            Err(RtcError::InvalidDate)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Starts listening for an event, enabling its interrupt in `RTC_IER`
+    ///
+    /// Listening for `Minute`, `Hour` or `Day` selects that event in `RTC_CR.TIMEVSEL`,
+    /// replacing any of the others.
+    pub fn listen(&mut self, event: RtcEvent) {
+        match event {
+            RtcEvent::Minute => self.rtc.cr.modify(|_, w| w.timevsel().minute()),
+            RtcEvent::Hour => self.rtc.cr.modify(|_, w| w.timevsel().hour()),
+            RtcEvent::Day => self.rtc.cr.modify(|_, w| w.timevsel().midnight()),
+            RtcEvent::Alarm | RtcEvent::Second => {}
+        }
+
+        self.rtc
+            .sccr
+            .write_with_zero(|w| unsafe { w.bits(event.mask()) });
+        self.rtc
+            .ier
+            .write_with_zero(|w| unsafe { w.bits(event.mask()) });
+    }
+
+    /// Stops listening for an event, disabling its interrupt in `RTC_IDR`
+    pub fn unlisten(&mut self, event: RtcEvent) {
+        self.rtc
+            .idr
+            .write_with_zero(|w| unsafe { w.bits(event.mask()) });
+    }
+
+    /// Returns whether an event flag is set in `RTC_SR`
+    pub fn is_event_pending(&self, event: RtcEvent) -> bool {
+        self.rtc.sr.read().bits() & event.mask() != 0
+    }
+
+    /// Clears an event flag through `RTC_SCCR`
+    pub fn clear_event(&mut self, event: RtcEvent) {
+        self.rtc
+            .sccr
+            .write_with_zero(|w| unsafe { w.bits(event.mask()) });
+    }
+
+    /// Releases the RTC peripheral
+    pub fn free(self) -> RTC {
+        self.rtc
+    }
+}
@@ -0,0 +1,125 @@
+//! Real-Time Timer (RTT)
+//!
+//! The RTT is a 32-bit counter incremented by the slow clock divided by a 16-bit prescaler
+//! (`RTT_MR.RTPRES`). Like the RTC it lives in the backup domain, needs no peripheral clock
+//! and keeps counting through a processor reset.
+//!
+//! With a prescaler of 32 the counter ticks at 1024 Hz from the 32.768 kHz crystal, giving
+//! a cheap millisecond-ish timebase without tying up a TC channel. At that rate the counter
+//! wraps after about 48 days.
+//!
+//! The prescaler must be at least 3 (0 means 65536). Reading `RTT_SR` clears the alarm and
+//! increment flags.
+
+use crate::pmc::Clocks;
+use crate::time::Hertz;
+use sam3x8e::RTT;
+
+/// RTT events
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RttEvent {
+    /// The counter reached the alarm value (`RTT_SR.ALMS`)
+    Alarm,
+    /// The counter was incremented (`RTT_SR.RTTINC`)
+    Increment,
+}
+
+/// Real-Time Timer
+pub struct Rtt {
+    rtt: RTT,
+    tick_freq: Hertz,
+}
+
+impl Rtt {
+    /// Restarts the counter from 0, ticking as close to `freq` as the prescaler allows
+    ///
+    /// `RTPRES` is `SLCK / freq`, clamped to 3 ..= 65535.
+    pub fn new<F>(rtt: RTT, freq: F, clocks: Clocks) -> Self
+    where
+        F: Into<Hertz>,
+    {
+        let slck = clocks.slck().0;
+        let rtpres = (slck / freq.into().0.max(1)).max(3).min(0xFFFF);
+
+        rtt.mr
+            .write_with_zero(|w| unsafe { w.rtpres().bits(rtpres as u16).rttrst().set_bit() });
+
+        Rtt {
+            rtt,
+            tick_freq: Hertz(slck / rtpres),
+        }
+    }
+
+    /// Returns the frequency the counter is incremented at
+    pub fn tick_freq(&self) -> Hertz {
+        self.tick_freq
+    }
+
+    /// Returns the counter value (`RTT_VR`)
+    ///
+    /// The counter runs from the slow clock, asynchronously to MCK, so it is read until two
+    /// consecutive reads agree.
+    pub fn counter(&self) -> u32 {
+        loop {
+            let value = self.rtt.vr.read().crtv().bits();
+            if value == self.rtt.vr.read().crtv().bits() {
+                return value;
+            }
+        }
+    }
+
+    /// Returns the time elapsed since the counter was restarted, in milliseconds
+    pub fn elapsed_ms(&self) -> u32 {
+        (u64::from(self.counter()) * 1_000 / u64::from(self.tick_freq.0)) as u32
+    }
+
+    /// Restarts the counter from 0 (`RTT_MR.RTTRST`)
+    pub fn reset(&mut self) {
+        self.rtt.mr.modify(|_, w| w.rttrst().set_bit());
+    }
+
+    /// Sets the counter value at which the alarm fires
+    ///
+    /// `RTT_AR.ALMV` holds the alarm value minus 1. The alarm interrupt is masked while
+    /// `RTT_AR` changes, as required, and restored afterwards.
+    pub fn set_alarm(&mut self, ticks: u32) {
+        let almien = self.rtt.mr.read().almien().bit_is_set();
+
+        self.rtt.mr.modify(|_, w| w.almien().clear_bit());
+        self.rtt
+            .ar
+            .write_with_zero(|w| unsafe { w.almv().bits(ticks.wrapping_sub(1)) });
+        self.rtt.mr.modify(|_, w| w.almien().bit(almien));
+    }
+
+    /// Starts listening for an event, enabling its interrupt in `RTT_MR`
+    pub fn listen(&mut self, event: RttEvent) {
+        match event {
+            RttEvent::Alarm => self.rtt.mr.modify(|_, w| w.almien().set_bit()),
+            RttEvent::Increment => self.rtt.mr.modify(|_, w| w.rttincien().set_bit()),
+        }
+    }
+
+    /// Stops listening for an event, disabling its interrupt in `RTT_MR`
+    pub fn unlisten(&mut self, event: RttEvent) {
+        match event {
+            RttEvent::Alarm => self.rtt.mr.modify(|_, w| w.almien().clear_bit()),
+            RttEvent::Increment => self.rtt.mr.modify(|_, w| w.rttincien().clear_bit()),
+        }
+    }
+
+    /// Returns whether an event flag is set in `RTT_SR`, clearing both flags
+    pub fn is_event_pending(&mut self, event: RttEvent) -> bool {
+        let sr = self.rtt.sr.read();
+
+        match event {
+            RttEvent::Alarm => sr.alms().bit_is_set(),
+            RttEvent::Increment => sr.rttinc().bit_is_set(),
+        }
+    }
+
+    /// Releases the RTT peripheral
+    pub fn free(self) -> RTT {
+        self.rtt
+    }
+}
@@ -0,0 +1,1437 @@
+//! Serial communication using the USART and UART peripherals
+//!
+//! The baud rate generators run in asynchronous mode with 16x oversampling,
+//! so the divisor written to `US_BRGR.CD` / `UART_BRGR.CD` is `MCK / (16 * baud)`, rounded to
+//! the nearest integer. Baud rates that give a divisor of 0 or beyond 16 bits are rejected with
+//! `SerialError::InvalidBaudRate`.
+//!
+//! The dedicated `UART` is wired to the Due's programming port (URXD on PA8, UTXD
+//! on PA9). It only supports 8-bit frames without hardware flow control.
+//!
+//! # 9-bit and multidrop framing
+//!
+//! A USART starts out with characters of up to 8 bits, read and written through the `u8`
+//! serial traits. `into_9bit` sets `US_MR.MODE9` and turns it into a `Serial<_, _, u16>`
+//! whose `u16` serial traits carry the ninth data bit in bit 8. `Parity::Multidrop` instead
+//! uses the parity bit to tell address characters from data: `write_address` sends an
+//! address (`US_CR.SENDA`), and `read_u9` reports received addresses with bit 8 set.
+//!
+//! # Hardware flow control
+//!
+//! `Serial::usart0_rts_cts` and friends put the USART in hardware handshaking mode. They
+//! take the RTS and CTS pins along with the data pins, as `(tx, rx, rts, cts)`:
+//!
+//! | USART  | RTS  | CTS  |
+//! |--------|------|------|
+//! | USART0 | PB25 | PB26 |
+//! | USART1 | PA14 | PA15 |
+//! | USART2 | PB22 | PB23 |
+//! | USART3 | PF5  | PF4  |
+//!
+//! PIOF is not bonded out on the SAM3X8E, so USART3 has no flow control on the Due.
+//!
+//! # ISO7816 smartcards
+//!
+//! `Serial::usart0_iso7816` and friends put a USART in ISO7816 T=0 mode. The card's I/O line
+//! goes to the TXD pin and its clock to the SCK pin, passed as `(io, sck)`; RXD is unused.
+//! SCK3 is on PIOE, which isn't bonded out, so USART3 can't drive a card.
+//! The USART drives SCK at `MCK / CD` and sends one bit every `US_FIDI.FI_DI_RATIO` clock
+//! cycles. Characters are 8 bits with even parity; the receiver answers a parity error with
+//! a NACK, and a character NACKed by the card is repeated up to `max_iterations` times.
+//!
+//! The line is half duplex, and the transmitter and receiver must not be enabled together.
+//! The USART starts out receiving (the card sends its answer to reset first);
+//! `iso7816_transmit` and `iso7816_receive` switch between the two.
+//!
+//! # LIN
+//!
+//! `Serial::usart0_lin` and friends put a USART in LIN master or slave mode, on its TXD and
+//! RXD pins through a LIN transceiver. The USART sends and checks the break, the sync byte,
+//! the identifier parity bits and the checksum on its own.
+//!
+//! A master runs a whole frame with `send_lin_frame` (it publishes the response) or
+//! `request_lin_frame` (a slave publishes it). A slave waits for a header with
+//! `read_lin_header`, then answers with `send_lin_response` or reads the response with
+//! `receive_lin_response`. The response length is the length of the slice, 1 to 8 bytes.
+//!
+//! # Interrupts
+//!
+//! `Serial` listens for any `Event`. Once split, `Rx` listens for the receiver events
+//! (`RxEvent`) and `Tx` for the transmitter ones (`TxEvent`), so that e.g. an interrupt
+//! handler feeding the transmitter from a ring buffer can stop listening for `TXRDY` once the
+//! buffer is empty.
+
+use core::convert::Infallible;
+use core::marker::PhantomData;
+
+use crate::gpio::pioa::{PA10, PA11, PA12, PA13, PA14, PA15, PA16, PA17, PA8, PA9};
+use crate::gpio::piob::{PB20, PB21, PB22, PB23, PB24, PB25, PB26};
+use crate::gpio::piod::{PD4, PD5};
+use crate::gpio::{Alternate, PeriphA, PeriphB};
+use crate::hal::serial;
+use crate::pmc::{Clocks, PeripheralId, Pmc};
+use crate::time::{Bps, Hertz, U32Ext};
+use sam3x8e::{UART, USART0, USART1, USART2, USART3};
+
+/// Serial error
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SerialError {
+    /// Framing error (stop bit not detected)
+    Framing,
+    /// RX buffer overrun
+    Overrun,
+    /// Parity check error
+    Parity,
+    /// The baud rate can't be reached from MCK
+    InvalidBaudRate,
+}
+
+/// LIN error
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum LinError {
+    /// A transmitted bit was read back with the other level (`LINBE`)
+    Bit,
+    /// The sync byte of a received header was out of tolerance (`LINISFE`)
+    InconsistentSync,
+    /// The identifier parity bits were wrong (`LINIPE`)
+    IdentifierParity,
+    /// The checksum of the response was wrong (`LINCE`)
+    Checksum,
+    /// No complete response came within the frame time (`LINSNRE`)
+    SlaveNotResponding,
+    /// The response is empty or longer than 8 bytes
+    InvalidLength,
+}
+
+/// Returns the `CD` divisor giving the closest baud rate to `baudrate` with 16x oversampling
+fn baud_divisor(mck: Hertz, baudrate: Bps) -> Result<u16, SerialError> {
+    let divisor = 16 * u64::from(baudrate.0);
+    if divisor == 0 {
+        return Err(SerialError::InvalidBaudRate);
+    }
+
+    let cd = (u64::from(mck.0) + divisor / 2) / divisor;
+    if cd == 0 || cd > 0xFFFF {
+        return Err(SerialError::InvalidBaudRate);
+    }
+    Ok(cd as u16)
+}
+
+/// Returns the error flagged in `US_CSR` in LIN mode, if any
+fn lin_error(csr: u32) -> Option<LinError> {
+    if csr & (1 << 25) != 0 {
+        Some(LinError::Bit)
+    } else if csr & (1 << 26) != 0 {
+        Some(LinError::InconsistentSync)
+    } else if csr & (1 << 27) != 0 {
+        Some(LinError::IdentifierParity)
+    } else if csr & (1 << 28) != 0 {
+        Some(LinError::Checksum)
+    } else if csr & (1 << 29) != 0 {
+        Some(LinError::SlaveNotResponding)
+    } else {
+        None
+    }
+}
+
+/// Role of a USART on a LIN bus
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LinMode {
+    /// Sends the headers (`US_MR.USART_MODE = LIN_MASTER`)
+    Master,
+    /// Answers the headers (`US_MR.USART_MODE = LIN_SLAVE`)
+    Slave,
+}
+
+/// LIN checksum model (`US_LINMR.CHKTYP`)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LinChecksum {
+    /// LIN 1.3, over the data bytes only
+    Classic,
+    /// LIN 2.x, over the protected identifier and the data bytes
+    Enhanced,
+}
+
+/// Parity mode
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Parity {
+    /// No parity bit
+    None,
+    /// Even parity
+    Even,
+    /// Odd parity
+    Odd,
+    /// Parity bit forced to 1
+    Mark,
+    /// Parity bit forced to 0
+    Space,
+    /// Parity bit marks address (1) and data (0) characters
+    Multidrop,
+}
+
+/// Number of stop bits
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StopBits {
+    /// 1 stop bit
+    Stop1,
+    /// 1.5 stop bits (asynchronous mode only)
+    Stop1p5,
+    /// 2 stop bits
+    Stop2,
+}
+
+/// Number of data bits per character
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WordLength {
+    /// 5 data bits
+    Bits5,
+    /// 6 data bits
+    Bits6,
+    /// 7 data bits
+    Bits7,
+    /// 8 data bits
+    Bits8,
+}
+
+/// USART frame format and baud rate
+///
+/// The default is 115200 baud, 8 data bits, no parity and 1 stop bit (8N1).
+#[derive(Clone, Copy)]
+pub struct Config {
+    pub baudrate: Bps,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+    pub word_length: WordLength,
+}
+
+impl Config {
+    pub fn baudrate(mut self, baudrate: Bps) -> Self {
+        self.baudrate = baudrate;
+        self
+    }
+
+    pub fn parity(mut self, parity: Parity) -> Self {
+        self.parity = parity;
+        self
+    }
+
+    pub fn parity_none(self) -> Self {
+        self.parity(Parity::None)
+    }
+
+    pub fn parity_even(self) -> Self {
+        self.parity(Parity::Even)
+    }
+
+    pub fn parity_odd(self) -> Self {
+        self.parity(Parity::Odd)
+    }
+
+    pub fn stop_bits(mut self, stop_bits: StopBits) -> Self {
+        self.stop_bits = stop_bits;
+        self
+    }
+
+    pub fn word_length(mut self, word_length: WordLength) -> Self {
+        self.word_length = word_length;
+        self
+    }
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            baudrate: 115_200_u32.bps(),
+            parity: Parity::None,
+            stop_bits: StopBits::Stop1,
+            word_length: WordLength::Bits8,
+        }
+    }
+}
+
+/// ISO7816 T=0 configuration
+///
+/// The default gives the card a 4 MHz clock with the 372 clock cycles per bit used until
+/// the speed is negotiated, no extra guard time and up to 3 repetitions of a NACKed
+/// character, with the direct convention.
+#[derive(Clone, Copy)]
+pub struct Iso7816Config {
+    /// Clock provided to the card on SCK, 1 to 5 MHz
+    pub card_clock: Hertz,
+    /// Clock cycles per bit, Fi / Di (`US_FIDI`)
+    pub fi_di_ratio: u16,
+    /// Extra guard time between transmitted characters, in bits (`US_TTGR`)
+    pub guard_time: u8,
+    /// Repetitions of a character NACKed by the card, 0 to 7 (`US_MR.MAX_ITERATION`)
+    pub max_iterations: u8,
+    /// Inverse convention: inverted levels, most significant bit first
+    pub inverse_convention: bool,
+}
+
+impl Iso7816Config {
+    pub fn card_clock(mut self, card_clock: Hertz) -> Self {
+        self.card_clock = card_clock;
+        self
+    }
+
+    pub fn fi_di_ratio(mut self, fi_di_ratio: u16) -> Self {
+        self.fi_di_ratio = fi_di_ratio;
+        self
+    }
+
+    pub fn guard_time(mut self, guard_time: u8) -> Self {
+        self.guard_time = guard_time;
+        self
+    }
+
+    pub fn max_iterations(mut self, max_iterations: u8) -> Self {
+        self.max_iterations = max_iterations;
+        self
+    }
+
+    pub fn inverse_convention(mut self, inverse_convention: bool) -> Self {
+        self.inverse_convention = inverse_convention;
+        self
+    }
+}
+
+impl Default for Iso7816Config {
+    fn default() -> Iso7816Config {
+        Iso7816Config {
+            card_clock: Hertz(4_000_000),
+            fi_di_ratio: 372,
+            guard_time: 0,
+            max_iterations: 3,
+            inverse_convention: false,
+        }
+    }
+}
+
+/// Serial events that can raise the USART/UART interrupt
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Event {
+    /// A character was received (`RXRDY`)
+    Rxrdy,
+    /// The transmit holding register is free (`TXRDY`)
+    Txrdy,
+    /// The transmitter is idle (`TXEMPTY`)
+    Txempty,
+    /// A character was received before the previous one was read (`OVRE`)
+    Overrun,
+    /// A stop bit was not detected (`FRAME`)
+    Framing,
+    /// A parity error, or an address character in multidrop mode (`PARE`)
+    Parity,
+}
+
+impl Event {
+    /// Bit of the event in `IER`/`IDR`/`IMR` and the status register
+    fn mask(self) -> u32 {
+        match self {
+            Event::Rxrdy => 1 << 0,
+            Event::Txrdy => 1 << 1,
+            Event::Overrun => 1 << 5,
+            Event::Framing => 1 << 6,
+            Event::Parity => 1 << 7,
+            Event::Txempty => 1 << 9,
+        }
+    }
+}
+
+/// Receiver events, handled through `Rx` once the serial is split
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RxEvent {
+    /// A character was received (`RXRDY`)
+    Rxrdy,
+    /// A character was received before the previous one was read (`OVRE`)
+    Overrun,
+    /// A stop bit was not detected (`FRAME`)
+    Framing,
+    /// A parity error, or an address character in multidrop mode (`PARE`)
+    Parity,
+}
+
+impl From<RxEvent> for Event {
+    fn from(event: RxEvent) -> Self {
+        match event {
+            RxEvent::Rxrdy => Event::Rxrdy,
+            RxEvent::Overrun => Event::Overrun,
+            RxEvent::Framing => Event::Framing,
+            RxEvent::Parity => Event::Parity,
+        }
+    }
+}
+
+/// Transmitter events, handled through `Tx` once the serial is split
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TxEvent {
+    /// The transmit holding register is free (`TXRDY`)
+    Txrdy,
+    /// The transmitter is idle (`TXEMPTY`)
+    Txempty,
+}
+
+impl From<TxEvent> for Event {
+    fn from(event: TxEvent) -> Self {
+        match event {
+            TxEvent::Txrdy => Event::Txrdy,
+            TxEvent::Txempty => Event::Txempty,
+        }
+    }
+}
+
+/// Pin usable as the TXD line of a USART or the UART
+pub trait TxPin<USART> {}
+
+/// Pin usable as the RXD line of a USART or the UART
+pub trait RxPin<USART> {}
+
+/// Pin usable as the RTS line of a USART
+pub trait RtsPin<USART> {}
+
+/// Pin usable as the CTS line of a USART
+pub trait CtsPin<USART> {}
+
+/// Pin usable as the SCK line of a USART
+pub trait SckPin<USART> {}
+
+/// Pins a USART or the UART can be constructed from, as `(tx, rx)`
+pub trait Pins<USART> {}
+
+impl<USART, TX, RX> Pins<USART> for (TX, RX)
+where
+    TX: TxPin<USART>,
+    RX: RxPin<USART>,
+{
+}
+
+/// Pins a USART can be constructed from with hardware handshaking, as `(tx, rx, rts, cts)`
+pub trait HandshakePins<USART> {}
+
+impl<USART, TX, RX, RTS, CTS> HandshakePins<USART> for (TX, RX, RTS, CTS)
+where
+    TX: TxPin<USART>,
+    RX: RxPin<USART>,
+    RTS: RtsPin<USART>,
+    CTS: CtsPin<USART>,
+{
+}
+
+/// Pins a USART can be constructed from in ISO7816 mode, as `(io, sck)`
+///
+/// The I/O line of the card is the USART's TXD pin.
+pub trait Iso7816Pins<USART> {}
+
+impl<USART, IO, SCK> Iso7816Pins<USART> for (IO, SCK)
+where
+    IO: TxPin<USART>,
+    SCK: SckPin<USART>,
+{
+}
+
+impl TxPin<UART> for PA9<Alternate<PeriphA>> {}
+impl RxPin<UART> for PA8<Alternate<PeriphA>> {}
+
+impl TxPin<USART0> for PA11<Alternate<PeriphA>> {}
+impl RxPin<USART0> for PA10<Alternate<PeriphA>> {}
+impl RtsPin<USART0> for PB25<Alternate<PeriphA>> {}
+impl CtsPin<USART0> for PB26<Alternate<PeriphA>> {}
+impl SckPin<USART0> for PA17<Alternate<PeriphB>> {}
+
+impl TxPin<USART1> for PA13<Alternate<PeriphA>> {}
+impl RxPin<USART1> for PA12<Alternate<PeriphA>> {}
+impl RtsPin<USART1> for PA14<Alternate<PeriphA>> {}
+impl CtsPin<USART1> for PA15<Alternate<PeriphA>> {}
+impl SckPin<USART1> for PA16<Alternate<PeriphA>> {}
+
+impl TxPin<USART2> for PB20<Alternate<PeriphA>> {}
+impl RxPin<USART2> for PB21<Alternate<PeriphA>> {}
+impl RtsPin<USART2> for PB22<Alternate<PeriphA>> {}
+impl CtsPin<USART2> for PB23<Alternate<PeriphA>> {}
+impl SckPin<USART2> for PB24<Alternate<PeriphA>> {}
+
+impl TxPin<USART3> for PD4<Alternate<PeriphB>> {}
+impl RxPin<USART3> for PD5<Alternate<PeriphB>> {}
+
+/// Serial abstraction
+///
+/// `WORD` is the character type of the serial traits: `u8`, or `u16` after `into_9bit`.
+pub struct Serial<USART, PINS, WORD = u8> {
+    usart: USART,
+    pins: PINS,
+    _word: PhantomData<WORD>,
+}
+
+/// Serial receiver
+pub struct Rx<USART, WORD = u8> {
+    _usart: PhantomData<(USART, WORD)>,
+}
+
+/// Serial transmitter
+pub struct Tx<USART, WORD = u8> {
+    _usart: PhantomData<(USART, WORD)>,
+}
+
+macro_rules! hal {
+    ($($USARTX:ident: (
+        $usartX:ident,
+        $usartX_rts_cts:ident,
+        $usartX_iso7816:ident,
+        $usartX_lin:ident,
+        $pid:ident
+    ),)+) => {
+        $(
+            impl<PINS> Serial<$USARTX, PINS> {
+                /// Configures a USART peripheral for asynchronous operation
+                ///
+                /// With `Parity::Multidrop` use `read_u9` to tell addresses from data; the `u8`
+                /// serial traits drop the address bit.
+                pub fn $usartX(
+                    usart: $USARTX,
+                    pins: PINS,
+                    config: Config,
+                    clocks: Clocks,
+                    pmc: &mut Pmc,
+                ) -> Result<Self, SerialError>
+                where
+                    PINS: Pins<$USARTX>,
+                {
+                    Self::configure(&usart, config, clocks, pmc, false)?;
+
+                    Ok(Serial {
+                        usart,
+                        pins,
+                        _word: PhantomData,
+                    })
+                }
+
+                /// Configures a USART peripheral for asynchronous operation with RTS/CTS
+                /// hardware handshaking (`US_MR.USART_MODE = HW_HANDSHAKING`)
+                pub fn $usartX_rts_cts(
+                    usart: $USARTX,
+                    pins: PINS,
+                    config: Config,
+                    clocks: Clocks,
+                    pmc: &mut Pmc,
+                ) -> Result<Self, SerialError>
+                where
+                    PINS: HandshakePins<$USARTX>,
+                {
+                    Self::configure(&usart, config, clocks, pmc, true)?;
+
+                    Ok(Serial {
+                        usart,
+                        pins,
+                        _word: PhantomData,
+                    })
+                }
+
+                fn configure(
+                    usart: &$USARTX,
+                    config: Config,
+                    clocks: Clocks,
+                    pmc: &mut Pmc,
+                    handshaking: bool,
+                ) -> Result<(), SerialError> {
+                    let cd = baud_divisor(clocks.mck(), config.baudrate)?;
+
+                    pmc.enable_peripheral(PeripheralId::$pid);
+
+                    // reset and disable the receiver and transmitter
+                    usart.cr().write_with_zero(|w| {
+                        w.rstrx().set_bit()
+                            .rsttx().set_bit()
+                            .rxdis().set_bit()
+                            .txdis().set_bit()
+                            .rststa().set_bit()
+                    });
+
+                    usart.mr().write_with_zero(|w| {
+                        w.usclks().mck().chmode().normal();
+
+                        if handshaking {
+                            w.usart_mode().hw_handshaking();
+                        } else {
+                            w.usart_mode().normal();
+                        }
+
+                        match config.parity {
+                            Parity::None => w.par().no(),
+                            Parity::Even => w.par().even(),
+                            Parity::Odd => w.par().odd(),
+                            Parity::Mark => w.par().mark(),
+                            Parity::Space => w.par().space(),
+                            Parity::Multidrop => w.par().multidrop(),
+                        };
+
+                        match config.stop_bits {
+                            StopBits::Stop1 => w.nbstop()._1_bit(),
+                            StopBits::Stop1p5 => w.nbstop()._1_5_bit(),
+                            StopBits::Stop2 => w.nbstop()._2_bit(),
+                        };
+
+                        match config.word_length {
+                            WordLength::Bits5 => w.chrl()._5_bit(),
+                            WordLength::Bits6 => w.chrl()._6_bit(),
+                            WordLength::Bits7 => w.chrl()._7_bit(),
+                            WordLength::Bits8 => w.chrl()._8_bit(),
+                        }
+                    });
+
+                    usart.brgr.write_with_zero(|w| unsafe { w.cd().bits(cd) });
+
+                    usart.cr().write_with_zero(|w| w.rxen().set_bit().txen().set_bit());
+                    Ok(())
+                }
+
+                /// Configures a USART for ISO7816 T=0 communication with a smartcard
+                ///
+                /// The receiver is enabled, ready for the answer to reset. `CD` is rounded up
+                /// so that the card clock doesn't exceed `config.card_clock`.
+                pub fn $usartX_iso7816(
+                    usart: $USARTX,
+                    pins: PINS,
+                    config: Iso7816Config,
+                    clocks: Clocks,
+                    pmc: &mut Pmc,
+                ) -> Self
+                where
+                    PINS: Iso7816Pins<$USARTX>,
+                {
+                    pmc.enable_peripheral(PeripheralId::$pid);
+
+                    // reset and disable the receiver and transmitter
+                    usart.cr().write_with_zero(|w| {
+                        w.rstrx().set_bit()
+                            .rsttx().set_bit()
+                            .rxdis().set_bit()
+                            .txdis().set_bit()
+                            .rststa().set_bit()
+                            .rstit().set_bit()
+                            .rstnack().set_bit()
+                    });
+
+                    usart.mr().write_with_zero(|w| unsafe {
+                        w.usart_mode().is07816_t_0()
+                            .usclks().mck()
+                            .clko().set_bit()
+                            .chrl()._8_bit()
+                            .par().even()
+                            .nbstop()._1_bit()
+                            .msbf().bit(config.inverse_convention)
+                            .invdata().bit(config.inverse_convention)
+                            .max_iteration().bits(config.max_iterations.min(7))
+                    });
+
+                    let card_clock = config.card_clock.0;
+                    let cd = (clocks.mck().0 + card_clock - 1) / card_clock;
+                    assert!(cd > 0 && cd <= 0xFFFF);
+                    usart.brgr.write_with_zero(|w| unsafe { w.cd().bits(cd as u16) });
+                    usart
+                        .fidi
+                        .write_with_zero(|w| unsafe { w.fi_di_ratio().bits(config.fi_di_ratio) });
+                    usart.ttgr.write_with_zero(|w| unsafe { w.tg().bits(config.guard_time) });
+
+                    usart.cr().write_with_zero(|w| w.rxen().set_bit());
+
+                    Serial {
+                        usart,
+                        pins,
+                        _word: PhantomData,
+                    }
+                }
+
+                /// Turns the ISO7816 line around to transmit, disabling the receiver
+                pub fn iso7816_transmit(&mut self) {
+                    self.usart
+                        .cr()
+                        .write_with_zero(|w| w.rxdis().set_bit().txen().set_bit());
+                }
+
+                /// Turns the ISO7816 line around to receive once the last character is sent
+                ///
+                /// Blocks until the transmitter is empty, so that repetitions of a NACKed
+                /// character are not cut short.
+                pub fn iso7816_receive(&mut self) {
+                    while self.usart.csr().read().txempty().bit_is_clear() {}
+                    self.usart
+                        .cr()
+                        .write_with_zero(|w| w.txdis().set_bit().rxen().set_bit());
+                }
+
+                /// Returns whether a character was NACKed more than `max_iterations` times
+                /// (`US_CSR.ITER`), clearing the flag
+                pub fn check_iteration_error(&mut self) -> bool {
+                    let iter = self.usart.csr().read().iter().bit_is_set();
+                    if iter {
+                        self.usart.cr().write_with_zero(|w| w.rstit().set_bit());
+                    }
+                    iter
+                }
+
+                /// Returns the number of parity errors seen in ISO7816 mode (`US_NER`)
+                ///
+                /// Reading `US_NER` resets the count.
+                pub fn take_error_count(&mut self) -> u8 {
+                    self.usart.ner.read().nb_errors().bits()
+                }
+
+                /// Configures a USART as a LIN master or slave
+                ///
+                /// LIN buses usually run at 19200 baud.
+                pub fn $usartX_lin(
+                    usart: $USARTX,
+                    pins: PINS,
+                    mode: LinMode,
+                    baudrate: Bps,
+                    checksum: LinChecksum,
+                    clocks: Clocks,
+                    pmc: &mut Pmc,
+                ) -> Result<Self, SerialError>
+                where
+                    PINS: Pins<$USARTX>,
+                {
+                    let cd = baud_divisor(clocks.mck(), baudrate)?;
+
+                    pmc.enable_peripheral(PeripheralId::$pid);
+
+                    // reset and disable the receiver and transmitter
+                    usart.cr().write_with_zero(|w| {
+                        w.rstrx().set_bit()
+                            .rsttx().set_bit()
+                            .rxdis().set_bit()
+                            .txdis().set_bit()
+                            .rststa().set_bit()
+                    });
+
+                    usart.mr().write_with_zero(|w| {
+                        match mode {
+                            LinMode::Master => w.usart_mode().lin_master(),
+                            LinMode::Slave => w.usart_mode().lin_slave(),
+                        };
+                        w.usclks().mck()
+                    });
+
+                    usart.linmr.write_with_zero(|w| {
+                        w.nact().ignore()
+                            .chktyp().bit(checksum == LinChecksum::Classic)
+                    });
+
+                    usart.brgr.write_with_zero(|w| unsafe { w.cd().bits(cd) });
+
+                    usart.cr().write_with_zero(|w| w.rxen().set_bit().txen().set_bit());
+
+                    Ok(Serial {
+                        usart,
+                        pins,
+                        _word: PhantomData,
+                    })
+                }
+
+                /// Sends a LIN header and publishes its response (master mode)
+                ///
+                /// Blocks until the frame is complete.
+                pub fn send_lin_frame(&mut self, id: u8, data: &[u8]) -> Result<(), LinError> {
+                    self.set_lin_response(true, data.len())?;
+                    self.usart.linir.write_with_zero(|w| unsafe { w.idchr().bits(id & 0x3F) });
+
+                    self.publish_lin_response(data)
+                }
+
+                /// Sends a LIN header and receives the response of a slave (master mode)
+                ///
+                /// Blocks until the frame is complete or the slave fails to answer.
+                pub fn request_lin_frame(
+                    &mut self,
+                    id: u8,
+                    buffer: &mut [u8],
+                ) -> Result<(), LinError> {
+                    self.set_lin_response(false, buffer.len())?;
+                    self.usart.linir.write_with_zero(|w| unsafe { w.idchr().bits(id & 0x3F) });
+
+                    self.subscribe_lin_response(buffer)
+                }
+
+                /// Returns the identifier of a received LIN header (slave mode)
+                ///
+                /// The identifier parity bits are stripped. The response must then be sent
+                /// or received right away.
+                pub fn read_lin_header(&mut self) -> nb::Result<u8, LinError> {
+                    let csr = self.usart.csr_lin_mode().read();
+
+                    if let Some(err) = lin_error(csr.bits()) {
+                        self.usart.cr().write_with_zero(|w| w.rststa().set_bit());
+                        Err(nb::Error::Other(err))
+                    } else if csr.linid().bit_is_set() {
+                        Ok(self.usart.linir.read().idchr().bits() & 0x3F)
+                    } else {
+                        Err(nb::Error::WouldBlock)
+                    }
+                }
+
+                /// Publishes the response to the header just read (slave mode)
+                pub fn send_lin_response(&mut self, data: &[u8]) -> Result<(), LinError> {
+                    self.set_lin_response(true, data.len())?;
+
+                    self.publish_lin_response(data)
+                }
+
+                /// Receives the response to the header just read (slave mode)
+                pub fn receive_lin_response(&mut self, buffer: &mut [u8]) -> Result<(), LinError> {
+                    self.set_lin_response(false, buffer.len())?;
+
+                    self.subscribe_lin_response(buffer)
+                }
+
+                /// Sets the direction and length of the next response (`US_LINMR`)
+                ///
+                /// Fails with `LinError::InvalidLength` unless `len` is 1 to 8 bytes.
+                fn set_lin_response(&mut self, publish: bool, len: usize) -> Result<(), LinError> {
+                    if !(1..=8).contains(&len) {
+                        return Err(LinError::InvalidLength);
+                    }
+
+                    self.usart.cr().write_with_zero(|w| w.rststa().set_bit());
+                    self.usart.linmr.modify(|_, w| {
+                        if publish {
+                            w.nact().publish();
+                        } else {
+                            w.nact().subscribe();
+                        }
+                        unsafe { w.dlc().bits(len as u8 - 1) }
+                    });
+                    Ok(())
+                }
+
+                fn publish_lin_response(&mut self, data: &[u8]) -> Result<(), LinError> {
+                    for &byte in data {
+                        while self.usart.csr_lin_mode().read().txrdy().bit_is_clear() {}
+                        self.usart
+                            .thr
+                            .write_with_zero(|w| unsafe { w.txchr().bits(u16::from(byte)) });
+                    }
+
+                    self.wait_lin_transfer()
+                }
+
+                fn subscribe_lin_response(&mut self, buffer: &mut [u8]) -> Result<(), LinError> {
+                    for byte in buffer.iter_mut() {
+                        loop {
+                            let csr = self.usart.csr_lin_mode().read();
+                            if let Some(err) = lin_error(csr.bits()) {
+                                self.usart.cr().write_with_zero(|w| w.rststa().set_bit());
+                                return Err(err);
+                            }
+                            if csr.rxrdy().bit_is_set() {
+                                break;
+                            }
+                        }
+                        *byte = self.usart.rhr.read().rxchr().bits() as u8;
+                    }
+
+                    self.wait_lin_transfer()
+                }
+
+                /// Waits for the end of the frame (`US_CSR.LINTC`) and checks for errors
+                fn wait_lin_transfer(&mut self) -> Result<(), LinError> {
+                    let csr = loop {
+                        let csr = self.usart.csr_lin_mode().read();
+                        if csr.lintc().bit_is_set() || lin_error(csr.bits()).is_some() {
+                            break csr;
+                        }
+                    };
+                    self.usart.cr().write_with_zero(|w| w.rststa().set_bit());
+
+                    match lin_error(csr.bits()) {
+                        Some(err) => Err(err),
+                        None => Ok(()),
+                    }
+                }
+
+                /// Switches to 9-bit characters (`US_MR.MODE9`), read and written through the
+                /// `u16` serial traits with the ninth bit in bit 8
+                pub fn into_9bit(self) -> Serial<$USARTX, PINS, u16> {
+                    self.usart
+                        .cr()
+                        .write_with_zero(|w| w.rxdis().set_bit().txdis().set_bit());
+                    self.usart.mr().modify(|_, w| w.mode9().set_bit());
+                    self.usart
+                        .cr()
+                        .write_with_zero(|w| w.rxen().set_bit().txen().set_bit());
+
+                    Serial {
+                        usart: self.usart,
+                        pins: self.pins,
+                        _word: PhantomData,
+                    }
+                }
+            }
+
+            impl<PINS, WORD> Serial<$USARTX, PINS, WORD> {
+                /// Splits the `Serial` abstraction into a transmitter and a receiver half
+                pub fn split(self) -> (Tx<$USARTX, WORD>, Rx<$USARTX, WORD>) {
+                    (
+                        Tx {
+                            _usart: PhantomData,
+                        },
+                        Rx {
+                            _usart: PhantomData,
+                        },
+                    )
+                }
+
+                /// Starts listening for an event, enabling its interrupt in `US_IER`
+                pub fn listen(&mut self, event: Event) {
+                    self.usart.ier().write_with_zero(|w| unsafe { w.bits(event.mask()) });
+                }
+
+                /// Stops listening for an event, disabling its interrupt in `US_IDR`
+                pub fn unlisten(&mut self, event: Event) {
+                    self.usart.idr().write_with_zero(|w| unsafe { w.bits(event.mask()) });
+                }
+
+                /// Returns whether an event flag is set in `US_CSR`
+                pub fn is_event_pending(&self, event: Event) -> bool {
+                    self.usart.csr().read().bits() & event.mask() != 0
+                }
+
+                /// Returns whether a received character is waiting to be read
+                pub fn is_rx_ready(&self) -> bool {
+                    self.usart.csr().read().rxrdy().bit_is_set()
+                }
+
+                /// Returns whether a character can be written
+                pub fn is_tx_ready(&self) -> bool {
+                    self.usart.csr().read().txrdy().bit_is_set()
+                }
+
+                /// Returns whether all written characters have been sent
+                pub fn is_tx_empty(&self) -> bool {
+                    self.usart.csr().read().txempty().bit_is_set()
+                }
+
+                /// Clears the overrun, framing and parity error flags (`US_CR.RSTSTA`)
+                pub fn clear_errors(&mut self) {
+                    self.usart.cr().write_with_zero(|w| w.rststa().set_bit());
+                }
+
+                /// Disables the USART and releases the peripheral and pins
+                pub fn free(self) -> ($USARTX, PINS) {
+                    self.usart
+                        .cr()
+                        .write_with_zero(|w| w.rxdis().set_bit().txdis().set_bit());
+                    (self.usart, self.pins)
+                }
+            }
+
+            impl<WORD> Rx<$USARTX, WORD> {
+                /// Starts listening for a receiver event, enabling its interrupt in `US_IER`
+                ///
+                /// `US_IER` and `US_IDR` only act on the bits written, so this doesn't race with
+                /// the `Tx` half.
+                pub fn listen(&mut self, event: RxEvent) {
+                    // NOTE(unsafe) write-only register, only the event's bit is affected
+                    let usart = unsafe { &*$USARTX::ptr() };
+                    let mask = Event::from(event).mask();
+                    usart.ier().write_with_zero(|w| unsafe { w.bits(mask) });
+                }
+
+                /// Stops listening for a receiver event, disabling its interrupt in `US_IDR`
+                pub fn unlisten(&mut self, event: RxEvent) {
+                    // NOTE(unsafe) write-only register, only the event's bit is affected
+                    let usart = unsafe { &*$USARTX::ptr() };
+                    let mask = Event::from(event).mask();
+                    usart.idr().write_with_zero(|w| unsafe { w.bits(mask) });
+                }
+
+                /// Returns whether a receiver event flag is set in `US_CSR`
+                pub fn is_event_pending(&self, event: RxEvent) -> bool {
+                    // NOTE(unsafe) atomic read with no side effects
+                    let usart = unsafe { &*$USARTX::ptr() };
+                    usart.csr().read().bits() & Event::from(event).mask() != 0
+                }
+
+                /// Clears the overrun, framing and parity error flags (`US_CR.RSTSTA`)
+                pub fn clear_errors(&mut self) {
+                    // NOTE(unsafe) write-only register, RSTSTA only affects the receiver flags
+                    let usart = unsafe { &*$USARTX::ptr() };
+                    usart.cr().write_with_zero(|w| w.rststa().set_bit());
+                }
+
+                /// Reads a 9-bit character
+                ///
+                /// In `Parity::Multidrop` mode bit 8 is set when the character was sent as an
+                /// address; otherwise bit 8 is the ninth data bit of a 9-bit character
+                /// (`into_9bit`).
+                pub fn read_u9(&mut self) -> nb::Result<u16, SerialError> {
+                    // NOTE(unsafe) atomic read with no side effects
+                    let usart = unsafe { &*$USARTX::ptr() };
+                    let csr = usart.csr().read();
+                    let multidrop = usart.mr().read().par().is_multidrop();
+
+                    let err = if csr.ovre().bit_is_set() {
+                        Some(SerialError::Overrun)
+                    } else if csr.frame().bit_is_set() {
+                        Some(SerialError::Framing)
+                    } else if csr.pare().bit_is_set() && !multidrop {
+                        Some(SerialError::Parity)
+                    } else {
+                        None
+                    };
+
+                    if let Some(err) = err {
+                        // clear the error flags
+                        usart.cr().write_with_zero(|w| w.rststa().set_bit());
+                        Err(nb::Error::Other(err))
+                    } else if csr.rxrdy().bit_is_set() {
+                        let mut data = usart.rhr.read().rxchr().bits();
+                        if multidrop && csr.pare().bit_is_set() {
+                            // PARE flags a received address character
+                            usart.cr().write_with_zero(|w| w.rststa().set_bit());
+                            data |= 1 << 8;
+                        }
+                        Ok(data)
+                    } else {
+                        Err(nb::Error::WouldBlock)
+                    }
+                }
+            }
+
+            impl serial::Read<u8> for Rx<$USARTX> {
+                type Error = SerialError;
+
+                fn read(&mut self) -> nb::Result<u8, SerialError> {
+                    self.read_u9().map(|data| data as u8)
+                }
+            }
+
+            impl serial::Read<u16> for Rx<$USARTX, u16> {
+                type Error = SerialError;
+
+                fn read(&mut self) -> nb::Result<u16, SerialError> {
+                    self.read_u9()
+                }
+            }
+
+            impl<WORD> Tx<$USARTX, WORD> {
+                /// Starts listening for a transmitter event, enabling its interrupt in `US_IER`
+                ///
+                /// An interrupt-driven transmitter listens for `TxEvent::Txrdy` while it has
+                /// characters to send and stops listening once its buffer is empty.
+                pub fn listen(&mut self, event: TxEvent) {
+                    // NOTE(unsafe) write-only register, only the event's bit is affected
+                    let usart = unsafe { &*$USARTX::ptr() };
+                    let mask = Event::from(event).mask();
+                    usart.ier().write_with_zero(|w| unsafe { w.bits(mask) });
+                }
+
+                /// Stops listening for a transmitter event, disabling its interrupt in `US_IDR`
+                pub fn unlisten(&mut self, event: TxEvent) {
+                    // NOTE(unsafe) write-only register, only the event's bit is affected
+                    let usart = unsafe { &*$USARTX::ptr() };
+                    let mask = Event::from(event).mask();
+                    usart.idr().write_with_zero(|w| unsafe { w.bits(mask) });
+                }
+
+                /// Returns whether a transmitter event flag is set in `US_CSR`
+                pub fn is_event_pending(&self, event: TxEvent) -> bool {
+                    // NOTE(unsafe) atomic read with no side effects
+                    let usart = unsafe { &*$USARTX::ptr() };
+                    usart.csr().read().bits() & Event::from(event).mask() != 0
+                }
+
+                /// Sends a character with the address bit set (`Parity::Multidrop` mode)
+                pub fn write_address(&mut self, address: u8) -> nb::Result<(), Infallible> {
+                    // NOTE(unsafe) atomic read with no side effects
+                    let usart = unsafe { &*$USARTX::ptr() };
+
+                    if usart.csr().read().txrdy().bit_is_set() {
+                        usart.cr().write_with_zero(|w| w.senda().set_bit());
+                        usart.thr.write_with_zero(|w| unsafe { w.txchr().bits(u16::from(address)) });
+                        Ok(())
+                    } else {
+                        Err(nb::Error::WouldBlock)
+                    }
+                }
+
+                /// Sends a 9-bit character (`into_9bit`)
+                pub fn write_u9(&mut self, word: u16) -> nb::Result<(), Infallible> {
+                    // NOTE(unsafe) atomic read with no side effects
+                    let usart = unsafe { &*$USARTX::ptr() };
+
+                    if usart.csr().read().txrdy().bit_is_set() {
+                        usart.thr.write_with_zero(|w| unsafe { w.txchr().bits(word & 0x1FF) });
+                        Ok(())
+                    } else {
+                        Err(nb::Error::WouldBlock)
+                    }
+                }
+            }
+
+            impl serial::Write<u8> for Tx<$USARTX> {
+                type Error = Infallible;
+
+                fn flush(&mut self) -> nb::Result<(), Infallible> {
+                    // NOTE(unsafe) atomic read with no side effects
+                    let usart = unsafe { &*$USARTX::ptr() };
+
+                    if usart.csr().read().txempty().bit_is_set() {
+                        Ok(())
+                    } else {
+                        Err(nb::Error::WouldBlock)
+                    }
+                }
+
+                fn write(&mut self, byte: u8) -> nb::Result<(), Infallible> {
+                    self.write_u9(u16::from(byte))
+                }
+            }
+
+            impl serial::Write<u16> for Tx<$USARTX, u16> {
+                type Error = Infallible;
+
+                fn flush(&mut self) -> nb::Result<(), Infallible> {
+                    let mut tx: Tx<$USARTX> = Tx {
+                        _usart: PhantomData,
+                    };
+                    serial::Write::<u8>::flush(&mut tx)
+                }
+
+                fn write(&mut self, word: u16) -> nb::Result<(), Infallible> {
+                    self.write_u9(word)
+                }
+            }
+
+            impl<PINS, WORD> Serial<$USARTX, PINS, WORD> {
+                /// Reads a 9-bit character, see `Rx::read_u9`
+                pub fn read_u9(&mut self) -> nb::Result<u16, SerialError> {
+                    let mut rx: Rx<$USARTX> = Rx {
+                        _usart: PhantomData,
+                    };
+                    rx.read_u9()
+                }
+
+                /// Sends a character with the address bit set, see `Tx::write_address`
+                pub fn write_address(&mut self, address: u8) -> nb::Result<(), Infallible> {
+                    let mut tx: Tx<$USARTX> = Tx {
+                        _usart: PhantomData,
+                    };
+                    tx.write_address(address)
+                }
+
+                /// Sends a 9-bit character, see `Tx::write_u9`
+                pub fn write_u9(&mut self, word: u16) -> nb::Result<(), Infallible> {
+                    let mut tx: Tx<$USARTX> = Tx {
+                        _usart: PhantomData,
+                    };
+                    tx.write_u9(word)
+                }
+            }
+
+            impl<PINS> serial::Read<u8> for Serial<$USARTX, PINS> {
+                type Error = SerialError;
+
+                fn read(&mut self) -> nb::Result<u8, SerialError> {
+                    self.read_u9().map(|data| data as u8)
+                }
+            }
+
+            impl<PINS> serial::Read<u16> for Serial<$USARTX, PINS, u16> {
+                type Error = SerialError;
+
+                fn read(&mut self) -> nb::Result<u16, SerialError> {
+                    self.read_u9()
+                }
+            }
+
+            impl<PINS> serial::Write<u8> for Serial<$USARTX, PINS> {
+                type Error = Infallible;
+
+                fn flush(&mut self) -> nb::Result<(), Infallible> {
+                    let mut tx: Tx<$USARTX> = Tx {
+                        _usart: PhantomData,
+                    };
+                    serial::Write::<u8>::flush(&mut tx)
+                }
+
+                fn write(&mut self, byte: u8) -> nb::Result<(), Infallible> {
+                    self.write_u9(u16::from(byte))
+                }
+            }
+
+            impl<PINS> serial::Write<u16> for Serial<$USARTX, PINS, u16> {
+                type Error = Infallible;
+
+                fn flush(&mut self) -> nb::Result<(), Infallible> {
+                    let mut tx: Tx<$USARTX> = Tx {
+                        _usart: PhantomData,
+                    };
+                    serial::Write::<u8>::flush(&mut tx)
+                }
+
+                fn write(&mut self, word: u16) -> nb::Result<(), Infallible> {
+                    self.write_u9(word)
+                }
+            }
+        )+
+    }
+}
+
+hal! {
+    USART0: (usart0, usart0_rts_cts, usart0_iso7816, usart0_lin, USART0),
+    USART1: (usart1, usart1_rts_cts, usart1_iso7816, usart1_lin, USART1),
+    USART2: (usart2, usart2_rts_cts, usart2_iso7816, usart2_lin, USART2),
+    USART3: (usart3, usart3_rts_cts, usart3_iso7816, usart3_lin, USART3),
+}
+
+/// Serial abstraction over the dedicated UART peripheral
+pub struct Uart<PINS> {
+    uart: UART,
+    pins: PINS,
+}
+
+impl<PINS> Uart<PINS> {
+    /// Configures the UART for 8N1 operation
+    pub fn new(
+        uart: UART,
+        pins: PINS,
+        baud_rate: Bps,
+        clocks: Clocks,
+        pmc: &mut Pmc,
+    ) -> Result<Self, SerialError>
+    where
+        PINS: Pins<UART>,
+    {
+        let cd = baud_divisor(clocks.mck(), baud_rate)?;
+
+        pmc.enable_peripheral(PeripheralId::UART);
+
+        // reset and disable the receiver and transmitter
+        uart.cr.write_with_zero(|w| {
+            w.rstrx()
+                .set_bit()
+                .rsttx()
+                .set_bit()
+                .rxdis()
+                .set_bit()
+                .txdis()
+                .set_bit()
+                .rststa()
+                .set_bit()
+        });
+
+        uart.mr.write_with_zero(|w| w.par().no().chmode().normal());
+
+        uart.brgr.write_with_zero(|w| unsafe { w.cd().bits(cd) });
+
+        uart.cr
+            .write_with_zero(|w| w.rxen().set_bit().txen().set_bit());
+
+        Ok(Uart { uart, pins })
+    }
+
+    /// Splits the `Uart` abstraction into a transmitter and a receiver half
+    pub fn split(self) -> (Tx<UART>, Rx<UART>) {
+        (
+            Tx {
+                _usart: PhantomData,
+            },
+            Rx {
+                _usart: PhantomData,
+            },
+        )
+    }
+
+    /// Starts listening for an event, enabling its interrupt in `UART_IER`
+    pub fn listen(&mut self, event: Event) {
+        self.uart
+            .ier
+            .write_with_zero(|w| unsafe { w.bits(event.mask()) });
+    }
+
+    /// Stops listening for an event, disabling its interrupt in `UART_IDR`
+    pub fn unlisten(&mut self, event: Event) {
+        self.uart
+            .idr
+            .write_with_zero(|w| unsafe { w.bits(event.mask()) });
+    }
+
+    /// Returns whether an event flag is set in `UART_SR`
+    pub fn is_event_pending(&self, event: Event) -> bool {
+        self.uart.sr.read().bits() & event.mask() != 0
+    }
+
+    /// Returns whether a received character is waiting to be read
+    pub fn is_rx_ready(&self) -> bool {
+        self.uart.sr.read().rxrdy().bit_is_set()
+    }
+
+    /// Returns whether a character can be written
+    pub fn is_tx_ready(&self) -> bool {
+        self.uart.sr.read().txrdy().bit_is_set()
+    }
+
+    /// Returns whether all written characters have been sent
+    pub fn is_tx_empty(&self) -> bool {
+        self.uart.sr.read().txempty().bit_is_set()
+    }
+
+    /// Clears the overrun, framing and parity error flags (`UART_CR.RSTSTA`)
+    pub fn clear_errors(&mut self) {
+        self.uart.cr.write_with_zero(|w| w.rststa().set_bit());
+    }
+
+    /// Disables the UART and releases the peripheral and pins
+    pub fn free(self) -> (UART, PINS) {
+        self.uart
+            .cr
+            .write_with_zero(|w| w.rxdis().set_bit().txdis().set_bit());
+        (self.uart, self.pins)
+    }
+}
+
+impl Rx<UART> {
+    /// Starts listening for a receiver event, enabling its interrupt in `UART_IER`
+    pub fn listen(&mut self, event: RxEvent) {
+        // NOTE(unsafe) write-only register, only the event's bit is affected
+        let uart = unsafe { &*UART::ptr() };
+        let mask = Event::from(event).mask();
+        uart.ier.write_with_zero(|w| unsafe { w.bits(mask) });
+    }
+
+    /// Stops listening for a receiver event, disabling its interrupt in `UART_IDR`
+    pub fn unlisten(&mut self, event: RxEvent) {
+        // NOTE(unsafe) write-only register, only the event's bit is affected
+        let uart = unsafe { &*UART::ptr() };
+        let mask = Event::from(event).mask();
+        uart.idr.write_with_zero(|w| unsafe { w.bits(mask) });
+    }
+
+    /// Returns whether a receiver event flag is set in `UART_SR`
+    pub fn is_event_pending(&self, event: RxEvent) -> bool {
+        // NOTE(unsafe) atomic read with no side effects
+        let uart = unsafe { &*UART::ptr() };
+        uart.sr.read().bits() & Event::from(event).mask() != 0
+    }
+
+    /// Clears the overrun, framing and parity error flags (`UART_CR.RSTSTA`)
+    pub fn clear_errors(&mut self) {
+        // NOTE(unsafe) write-only register, RSTSTA only affects the receiver flags
+        let uart = unsafe { &*UART::ptr() };
+        uart.cr.write_with_zero(|w| w.rststa().set_bit());
+    }
+}
+
+impl Tx<UART> {
+    /// Starts listening for a transmitter event, enabling its interrupt in `UART_IER`
+    pub fn listen(&mut self, event: TxEvent) {
+        // NOTE(unsafe) write-only register, only the event's bit is affected
+        let uart = unsafe { &*UART::ptr() };
+        let mask = Event::from(event).mask();
+        uart.ier.write_with_zero(|w| unsafe { w.bits(mask) });
+    }
+
+    /// Stops listening for a transmitter event, disabling its interrupt in `UART_IDR`
+    pub fn unlisten(&mut self, event: TxEvent) {
+        // NOTE(unsafe) write-only register, only the event's bit is affected
+        let uart = unsafe { &*UART::ptr() };
+        let mask = Event::from(event).mask();
+        uart.idr.write_with_zero(|w| unsafe { w.bits(mask) });
+    }
+
+    /// Returns whether a transmitter event flag is set in `UART_SR`
+    pub fn is_event_pending(&self, event: TxEvent) -> bool {
+        // NOTE(unsafe) atomic read with no side effects
+        let uart = unsafe { &*UART::ptr() };
+        uart.sr.read().bits() & Event::from(event).mask() != 0
+    }
+}
+
+impl serial::Read<u8> for Rx<UART> {
+    type Error = SerialError;
+
+    fn read(&mut self) -> nb::Result<u8, SerialError> {
+        // NOTE(unsafe) atomic read with no side effects
+        let uart = unsafe { &*UART::ptr() };
+        let sr = uart.sr.read();
+
+        let err = if sr.ovre().bit_is_set() {
+            Some(SerialError::Overrun)
+        } else if sr.frame().bit_is_set() {
+            Some(SerialError::Framing)
+        } else if sr.pare().bit_is_set() {
+            Some(SerialError::Parity)
+        } else {
+            None
+        };
+
+        if let Some(err) = err {
+            // clear the error flags
+            uart.cr.write_with_zero(|w| w.rststa().set_bit());
+            Err(nb::Error::Other(err))
+        } else if sr.rxrdy().bit_is_set() {
+            Ok(uart.rhr.read().rxchr().bits())
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+}
+
+impl serial::Write<u8> for Tx<UART> {
+    type Error = Infallible;
+
+    fn flush(&mut self) -> nb::Result<(), Infallible> {
+        // NOTE(unsafe) atomic read with no side effects
+        let uart = unsafe { &*UART::ptr() };
+
+        if uart.sr.read().txempty().bit_is_set() {
+            Ok(())
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+
+    fn write(&mut self, byte: u8) -> nb::Result<(), Infallible> {
+        // NOTE(unsafe) atomic read with no side effects
+        let uart = unsafe { &*UART::ptr() };
+
+        if uart.sr.read().txrdy().bit_is_set() {
+            uart.thr
+                .write_with_zero(|w| unsafe { w.txchr().bits(byte) });
+            Ok(())
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+}
+
+impl<PINS> serial::Read<u8> for Uart<PINS> {
+    type Error = SerialError;
+
+    fn read(&mut self) -> nb::Result<u8, SerialError> {
+        let mut rx: Rx<UART> = Rx {
+            _usart: PhantomData,
+        };
+        rx.read()
+    }
+}
+
+impl<PINS> serial::Write<u8> for Uart<PINS> {
+    type Error = Infallible;
+
+    fn flush(&mut self) -> nb::Result<(), Infallible> {
+        let mut tx: Tx<UART> = Tx {
+            _usart: PhantomData,
+        };
+        tx.flush()
+    }
+
+    fn write(&mut self, byte: u8) -> nb::Result<(), Infallible> {
+        let mut tx: Tx<UART> = Tx {
+            _usart: PhantomData,
+        };
+        tx.write(byte)
+    }
+}
@@ -0,0 +1,200 @@
+//! Static Memory Controller (SMC)
+//!
+//! The SMC drives asynchronous devices on the External Bus Interface (EBI): SRAM, flash,
+//! or parallel LCD controllers with an 8080-style bus. Each of the eight chip selects NCS0
+//! to NCS7 maps 16 MB of the address space starting at `0x6000_0000 + 0x0100_0000 * n`,
+//! and reads and writes there turn into bus cycles on the EBI pins.
+//!
+//! A read strobes NRD and a write NWE, each with a setup time before the pulse and a hold
+//! time after it, within a cycle. `configure` converts these times from nanoseconds to MCK
+//! cycles, rounding up, and writes them to `SMC_SETUPx`, `SMC_PULSEx` and `SMC_CYCLEx`. The
+//! chip select follows the same timing as the strobe.
+//!
+//! The EBI pins (data, address, NRD, NWE and the NCS lines used) must be configured for
+//! their peripheral function.
+
+use crate::pmc::{Clocks, PeripheralId, Pmc};
+use sam3x8e::SMC;
+
+/// Base address of the chip select 0 memory region
+const EBI_BASE: usize = 0x6000_0000;
+
+/// Size of the memory region of a chip select
+const EBI_CS_SIZE: usize = 0x0100_0000;
+
+/// Writes `$bits` to the register of a chip select family (`setup0` to `setup7`, `mode0` to
+/// `mode7`...) picked by a `ChipSelect`
+macro_rules! write_per_cs {
+    (
+        $smc:expr,
+        $cs:expr,
+        $r0:ident $r1:ident $r2:ident $r3:ident $r4:ident $r5:ident $r6:ident $r7:ident,
+        $bits:expr
+    ) => {
+        match $cs {
+            ChipSelect::NCS0 => $smc.$r0.write_with_zero(|w| unsafe { w.bits($bits) }),
+            ChipSelect::NCS1 => $smc.$r1.write_with_zero(|w| unsafe { w.bits($bits) }),
+            ChipSelect::NCS2 => $smc.$r2.write_with_zero(|w| unsafe { w.bits($bits) }),
+            ChipSelect::NCS3 => $smc.$r3.write_with_zero(|w| unsafe { w.bits($bits) }),
+            ChipSelect::NCS4 => $smc.$r4.write_with_zero(|w| unsafe { w.bits($bits) }),
+            ChipSelect::NCS5 => $smc.$r5.write_with_zero(|w| unsafe { w.bits($bits) }),
+            ChipSelect::NCS6 => $smc.$r6.write_with_zero(|w| unsafe { w.bits($bits) }),
+            ChipSelect::NCS7 => $smc.$r7.write_with_zero(|w| unsafe { w.bits($bits) }),
+        }
+    };
+}
+
+macro_rules! write_setup {
+    ($smc:expr, $cs:expr, $bits:expr) => {
+        write_per_cs!($smc, $cs, setup0 setup1 setup2 setup3 setup4 setup5 setup6 setup7, $bits)
+    };
+}
+
+macro_rules! write_pulse {
+    ($smc:expr, $cs:expr, $bits:expr) => {
+        write_per_cs!($smc, $cs, pulse0 pulse1 pulse2 pulse3 pulse4 pulse5 pulse6 pulse7, $bits)
+    };
+}
+
+macro_rules! write_cycle {
+    ($smc:expr, $cs:expr, $bits:expr) => {
+        write_per_cs!($smc, $cs, cycle0 cycle1 cycle2 cycle3 cycle4 cycle5 cycle6 cycle7, $bits)
+    };
+}
+
+macro_rules! write_mode {
+    ($smc:expr, $cs:expr, $bits:expr) => {
+        write_per_cs!($smc, $cs, mode0 mode1 mode2 mode3 mode4 mode5 mode6 mode7, $bits)
+    };
+}
+
+/// Chip select lines of the EBI
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ChipSelect {
+    NCS0 = 0,
+    NCS1 = 1,
+    NCS2 = 2,
+    NCS3 = 3,
+    NCS4 = 4,
+    NCS5 = 5,
+    NCS6 = 6,
+    NCS7 = 7,
+}
+
+impl ChipSelect {
+    /// Returns the first address of the memory region of this chip select
+    pub fn base_address(self) -> *mut u8 {
+        (EBI_BASE + EBI_CS_SIZE * self as usize) as *mut u8
+    }
+}
+
+/// Width of the data bus (`SMC_MODEx.DBW`)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BusWidth {
+    /// D0 to D7
+    Bits8,
+    /// D0 to D15
+    Bits16,
+}
+
+/// Timing of a read or write strobe, in nanoseconds
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Strobe {
+    /// From the address being valid to the strobe going low
+    pub setup_ns: u32,
+    /// Width of the strobe
+    pub pulse_ns: u32,
+    /// From the strobe going high to the next access
+    pub hold_ns: u32,
+}
+
+/// Configuration of a chip select
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Config {
+    pub bus_width: BusWidth,
+    /// NRD timing
+    pub read: Strobe,
+    /// NWE timing
+    pub write: Strobe,
+}
+
+/// Encodes a number of cycles as `low + step * high`, rounding up to the next encodable value
+///
+/// The SMC fields only reach some values: `SETUP` is `128 * x[5] + x[4:0]`, `PULSE` is
+/// `256 * x[6] + x[5:0]` and `CYCLE` is `256 * x[8:7] + x[6:0]`. Returns the field value
+/// along with the number of cycles it stands for.
+fn encode(cycles: u32, low_bits: u32, step: u32, max_high: u32) -> (u32, u32) {
+    let low_max = (1 << low_bits) - 1;
+
+    for high in 0..=max_high {
+        if cycles <= high * step + low_max {
+            let low = cycles.saturating_sub(high * step);
+            return ((high << low_bits) | low, high * step + low);
+        }
+    }
+
+    ((max_high << low_bits) | low_max, max_high * step + low_max)
+}
+
+/// Static Memory Controller
+pub struct Smc {
+    smc: SMC,
+}
+
+impl Smc {
+    /// Enables the SMC clock
+    pub fn new(smc: SMC, pmc: &mut Pmc) -> Self {
+        pmc.enable_peripheral(PeripheralId::SMC);
+
+        Smc { smc }
+    }
+
+    /// Sets the bus width and timings of a chip select
+    ///
+    /// Accesses are controlled by NRD and NWE (`SMC_MODEx.READ_MODE`/`WRITE_MODE`); NWAIT
+    /// is ignored.
+    pub fn configure(&mut self, cs: ChipSelect, config: &Config, clocks: Clocks) {
+        let mck = u64::from(clocks.mck().0);
+        let to_cycles = |ns: u32| ((u64::from(ns) * mck + 999_999_999) / 1_000_000_000) as u32;
+
+        let (rd_setup, rd_setup_cycles) = encode(to_cycles(config.read.setup_ns), 5, 128, 1);
+        let (rd_pulse, rd_pulse_cycles) = encode(to_cycles(config.read.pulse_ns), 6, 256, 1);
+        let (rd_cycle, _) = encode(
+            rd_setup_cycles + rd_pulse_cycles + to_cycles(config.read.hold_ns),
+            7,
+            256,
+            3,
+        );
+        let (wr_setup, wr_setup_cycles) = encode(to_cycles(config.write.setup_ns), 5, 128, 1);
+        let (wr_pulse, wr_pulse_cycles) = encode(to_cycles(config.write.pulse_ns), 6, 256, 1);
+        let (wr_cycle, _) = encode(
+            wr_setup_cycles + wr_pulse_cycles + to_cycles(config.write.hold_ns),
+            7,
+            256,
+            3,
+        );
+
+        // NCS_RD_SETUP, NRD_SETUP, NCS_WR_SETUP, NWE_SETUP
+        let setup = (rd_setup << 24) | (rd_setup << 16) | (wr_setup << 8) | wr_setup;
+        write_setup!(self.smc, cs, setup);
+        // NCS_RD_PULSE, NRD_PULSE, NCS_WR_PULSE, NWE_PULSE
+        let pulse = (rd_pulse << 24) | (rd_pulse << 16) | (wr_pulse << 8) | wr_pulse;
+        write_pulse!(self.smc, cs, pulse);
+        // NRD_CYCLE, NWE_CYCLE
+        let cycle = (rd_cycle << 16) | wr_cycle;
+        write_cycle!(self.smc, cs, cycle);
+
+        // READ_MODE, WRITE_MODE, DBW
+        let dbw = match config.bus_width {
+            BusWidth::Bits8 => 0,
+            BusWidth::Bits16 => 1 << 12,
+        };
+        let mode = dbw | (1 << 1) | 1;
+        write_mode!(self.smc, cs, mode);
+    }
+
+    /// Releases the SMC peripheral
+    pub fn free(self) -> SMC {
+        self.smc
+    }
+}
@@ -0,0 +1,458 @@
+//! Serial Peripheral Interface (SPI) in master mode
+//!
+//! Each of the four hardware chip selects (NPCS0..3) has its own configuration register
+//! `SPI_CSRx` holding the bit rate (`SCBR`), the clock mode (`CPOL`/`NCPHA`) and the number
+//! of bits per transfer. The SPI starts with variable peripheral select (`MR.PS`), so every
+//! transfer names its chip select in `TDR.PCS` and the hardware switches the NPCS lines
+//! and the `SPI_CSRx` settings on its own. `set_peripheral_select` can instead fix the chip
+//! select in `MR.PCS`: `TDR.PCS` is then ignored and all transfers, including those of
+//! `Device` handles, go to that chip select.
+//!
+//! Transfers on `Spi` itself go to NPCS0; the NPCS0 line can be left unconnected and the
+//! chip select driven from a GPIO instead. For the other chip selects, configure them with
+//! `configure` and talk to the device through the `Device` handle returned by `device`.
+//! The NPCS pins used must be configured for their peripheral function.
+//!
+//! Transfers are 8 bits wide by default. `set_word_size` selects 9 to 16 bits per transfer
+//! for a chip select; such transfers go through the `u16` traits, e.g. an MCP3201 conversion
+//! is read as a single 16-bit word.
+//!
+//! # Slave mode
+//!
+//! `SpiSlave` runs the SPI as a slave (`SPI_MR.MSTR = 0`). The master provides the clock,
+//! so there is no bit rate to set; the clock mode and word size come from `SPI_CSR0`. The
+//! slave is selected by the master through NPCS0 (PA28), which must be passed along with
+//! the other pins as `(sck, miso, mosi, nss)`. Each transfer clocked by the master shifts
+//! out the word written last, so the reply to a word can only be sent in the next transfer.
+
+use crate::gpio::pioa::{PA25, PA26, PA27, PA28};
+use crate::gpio::{Alternate, PeriphA};
+use crate::hal;
+pub use crate::hal::spi::{Mode, Phase, Polarity, MODE_0, MODE_1, MODE_2, MODE_3};
+use crate::pmc::{Clocks, PeripheralId, Pmc};
+use crate::time::Hertz;
+use sam3x8e::SPI0;
+
+/// SPI error
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SpiError {
+    /// A byte was received before the previous one was read
+    Overrun,
+    /// Another master drove NPCS0 low (mode fault)
+    ModeFault,
+    /// The master clocked a transfer before a word was written (slave mode only)
+    Underrun,
+    /// The bit rate is zero, or too low to reach from MCK with the clock divider:
+    /// `SPI_CSRx.SCBR` up to 255, or `US_BRGR.CD` up to 65535 for a USART in SPI mode
+    InvalidFrequency,
+    /// The number of bits per transfer is outside 8 to 16
+    InvalidWordSize,
+}
+
+/// Hardware chip select lines
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ChipSelect {
+    NPCS0 = 0,
+    NPCS1 = 1,
+    NPCS2 = 2,
+    NPCS3 = 3,
+}
+
+impl ChipSelect {
+    /// Value of `TDR.PCS`/`MR.PCS` selecting this line (no external decoder)
+    fn pcs(self) -> u8 {
+        !(1 << (self as u8)) & 0xF
+    }
+}
+
+/// How transfers pick their chip select (`SPI_MR.PS`)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PeripheralSelect {
+    /// Every transfer goes to the chip select in `MR.PCS`
+    Fixed(ChipSelect),
+    /// Every transfer names its chip select in `TDR.PCS`
+    Variable,
+}
+
+/// Pin usable as the SPCK line of the SPI
+pub trait SckPin<SPI> {}
+
+/// Pin usable as the MISO line of the SPI
+pub trait MisoPin<SPI> {}
+
+/// Pin usable as the MOSI line of the SPI
+pub trait MosiPin<SPI> {}
+
+/// Pins the SPI can be constructed from, as `(sck, miso, mosi)`
+pub trait Pins<SPI> {}
+
+impl<SPI, SCK, MISO, MOSI> Pins<SPI> for (SCK, MISO, MOSI)
+where
+    SCK: SckPin<SPI>,
+    MISO: MisoPin<SPI>,
+    MOSI: MosiPin<SPI>,
+{
+}
+
+/// Pin usable as the slave select input (NPCS0) of the SPI in slave mode
+pub trait NssPin<SPI> {}
+
+/// Pins the SPI can be constructed from in slave mode, as `(sck, miso, mosi, nss)`
+pub trait SlavePins<SPI> {}
+
+impl<SPI, SCK, MISO, MOSI, NSS> SlavePins<SPI> for (SCK, MISO, MOSI, NSS)
+where
+    SCK: SckPin<SPI>,
+    MISO: MisoPin<SPI>,
+    MOSI: MosiPin<SPI>,
+    NSS: NssPin<SPI>,
+{
+}
+
+impl SckPin<SPI0> for PA27<Alternate<PeriphA>> {}
+impl MisoPin<SPI0> for PA25<Alternate<PeriphA>> {}
+impl MosiPin<SPI0> for PA26<Alternate<PeriphA>> {}
+impl NssPin<SPI0> for PA28<Alternate<PeriphA>> {}
+
+/// SPI master abstraction
+pub struct Spi<PINS> {
+    spi: SPI0,
+    pins: PINS,
+    clocks: Clocks,
+}
+
+/// SPI slave abstraction
+pub struct SpiSlave<PINS> {
+    spi: SPI0,
+    pins: PINS,
+}
+
+/// Device on one hardware chip select of the SPI
+///
+/// Transfers go through `TDR.PCS`, so the hardware asserts the device's NPCS line and
+/// applies its `SPI_CSRx` settings, which are set with `Spi::configure`.
+pub struct Device<'a, PINS> {
+    spi: &'a mut Spi<PINS>,
+    cs: ChipSelect,
+}
+
+impl<PINS> Spi<PINS> {
+    /// Configures the SPI as a master doing 8-bit transfers on NPCS0
+    ///
+    /// The bit rate is `MCK / SCBR`, with `SCBR` rounded up so that it doesn't exceed `freq`.
+    /// Fails with `SpiError::InvalidFrequency` if `freq` can't be reached, see `configure`.
+    pub fn spi0<F>(
+        spi: SPI0,
+        pins: PINS,
+        mode: Mode,
+        freq: F,
+        clocks: Clocks,
+        pmc: &mut Pmc,
+    ) -> Result<Self, SpiError>
+    where
+        PINS: Pins<SPI0>,
+        F: Into<Hertz>,
+    {
+        pmc.enable_peripheral(PeripheralId::SPI0);
+
+        spi.cr.write_with_zero(|w| w.spidis().set_bit());
+        spi.cr.write_with_zero(|w| w.swrst().set_bit());
+
+        // master, variable peripheral select, mode fault detection disabled
+        spi.mr
+            .write_with_zero(|w| w.mstr().set_bit().ps().set_bit().modfdis().set_bit());
+
+        for csr in spi.csr.iter() {
+            csr.reset();
+        }
+
+        let mut spi = Spi { spi, pins, clocks };
+        spi.configure(ChipSelect::NPCS0, mode, freq)?;
+
+        spi.spi.cr.write_with_zero(|w| w.spien().set_bit());
+
+        Ok(spi)
+    }
+
+    /// Sets the clock mode and bit rate used for transfers on a chip select (`SPI_CSRx`)
+    ///
+    /// Fails with `SpiError::InvalidFrequency` if `freq` is zero or below `MCK / 255`. Bit
+    /// rates above MCK are capped at MCK.
+    pub fn configure<F>(&mut self, cs: ChipSelect, mode: Mode, freq: F) -> Result<(), SpiError>
+    where
+        F: Into<Hertz>,
+    {
+        let freq = freq.into().0;
+        if freq == 0 {
+            return Err(SpiError::InvalidFrequency);
+        }
+        let (mck, freq) = (u64::from(self.clocks.mck().0), u64::from(freq));
+        let scbr = ((mck + freq - 1) / freq).max(1);
+        if scbr > 0xFF {
+            return Err(SpiError::InvalidFrequency);
+        }
+
+        self.spi.csr[cs as usize].modify(|_, w| unsafe {
+            w.cpol()
+                .bit(mode.polarity == Polarity::IdleHigh)
+                .ncpha()
+                .bit(mode.phase == Phase::CaptureOnFirstTransition)
+                .scbr()
+                .bits(scbr as u8)
+        });
+
+        Ok(())
+    }
+
+    /// Selects fixed or variable peripheral select (`SPI_MR.PS` and `SPI_MR.PCS`)
+    ///
+    /// With `PeripheralSelect::Fixed` the SPI keeps driving one chip select, whatever
+    /// chip select the transfers name.
+    pub fn set_peripheral_select(&mut self, select: PeripheralSelect) {
+        match select {
+            PeripheralSelect::Fixed(cs) => self
+                .spi
+                .mr
+                .modify(|_, w| unsafe { w.ps().clear_bit().pcs().bits(cs.pcs()) }),
+            PeripheralSelect::Variable => self.spi.mr.modify(|_, w| w.ps().set_bit()),
+        }
+    }
+
+    /// Sets the number of bits per transfer on a chip select (`SPI_CSRx.BITS`), 8 to 16
+    ///
+    /// Other sizes are rejected with `SpiError::InvalidWordSize`.
+    pub fn set_word_size(&mut self, cs: ChipSelect, bits: u8) -> Result<(), SpiError> {
+        if !(8..=16).contains(&bits) {
+            return Err(SpiError::InvalidWordSize);
+        }
+
+        self.spi.csr[cs as usize].modify(|_, w| unsafe { w.bits_().bits(bits - 8) });
+        Ok(())
+    }
+
+    /// Sets the chip select timing of a chip select
+    ///
+    /// `dlybs` is the delay from NPCS assertion to the first SPCK edge, in MCK cycles
+    /// (`SPI_CSRx.DLYBS`, 0 means half an SPCK period). `dlybct` is the delay between
+    /// consecutive transfers, in units of 32 MCK cycles (`SPI_CSRx.DLYBCT`).
+    pub fn set_delays(&mut self, cs: ChipSelect, dlybs: u8, dlybct: u8) {
+        self.spi.csr[cs as usize]
+            .modify(|_, w| unsafe { w.dlybs().bits(dlybs).dlybct().bits(dlybct) });
+    }
+
+    /// Returns a handle doing transfers on a chip select
+    pub fn device(&mut self, cs: ChipSelect) -> Device<'_, PINS> {
+        Device { spi: self, cs }
+    }
+
+    /// Disables the SPI and releases the peripheral and pins
+    pub fn free(self) -> (SPI0, PINS) {
+        self.spi.cr.write_with_zero(|w| w.spidis().set_bit());
+        (self.spi, self.pins)
+    }
+
+    fn read_data(&mut self) -> nb::Result<u16, SpiError> {
+        let sr = self.spi.sr.read();
+
+        if sr.ovres().bit_is_set() {
+            Err(nb::Error::Other(SpiError::Overrun))
+        } else if sr.modf().bit_is_set() {
+            Err(nb::Error::Other(SpiError::ModeFault))
+        } else if sr.rdrf().bit_is_set() {
+            Ok(self.spi.rdr.read().rd().bits())
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+
+    fn send_data(&mut self, cs: ChipSelect, data: u16) -> nb::Result<(), SpiError> {
+        let sr = self.spi.sr.read();
+
+        if sr.ovres().bit_is_set() {
+            Err(nb::Error::Other(SpiError::Overrun))
+        } else if sr.modf().bit_is_set() {
+            Err(nb::Error::Other(SpiError::ModeFault))
+        } else if sr.tdre().bit_is_set() {
+            self.spi
+                .tdr
+                .write_with_zero(|w| unsafe { w.td().bits(data).pcs().bits(cs.pcs()) });
+            Ok(())
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+}
+
+impl<PINS> hal::spi::FullDuplex<u8> for Spi<PINS> {
+    type Error = SpiError;
+
+    fn read(&mut self) -> nb::Result<u8, SpiError> {
+        self.read_data().map(|data| data as u8)
+    }
+
+    fn send(&mut self, byte: u8) -> nb::Result<(), SpiError> {
+        self.send_data(ChipSelect::NPCS0, u16::from(byte))
+    }
+}
+
+impl<PINS> hal::blocking::spi::transfer::Default<u8> for Spi<PINS> {}
+
+impl<PINS> hal::blocking::spi::write::Default<u8> for Spi<PINS> {}
+
+impl<PINS> hal::spi::FullDuplex<u16> for Spi<PINS> {
+    type Error = SpiError;
+
+    fn read(&mut self) -> nb::Result<u16, SpiError> {
+        self.read_data()
+    }
+
+    fn send(&mut self, word: u16) -> nb::Result<(), SpiError> {
+        self.send_data(ChipSelect::NPCS0, word)
+    }
+}
+
+impl<PINS> hal::blocking::spi::transfer::Default<u16> for Spi<PINS> {}
+
+impl<PINS> hal::blocking::spi::write::Default<u16> for Spi<PINS> {}
+
+impl<'a, PINS> hal::spi::FullDuplex<u8> for Device<'a, PINS> {
+    type Error = SpiError;
+
+    fn read(&mut self) -> nb::Result<u8, SpiError> {
+        self.spi.read_data().map(|data| data as u8)
+    }
+
+    fn send(&mut self, byte: u8) -> nb::Result<(), SpiError> {
+        self.spi.send_data(self.cs, u16::from(byte))
+    }
+}
+
+impl<'a, PINS> hal::blocking::spi::transfer::Default<u8> for Device<'a, PINS> {}
+
+impl<'a, PINS> hal::blocking::spi::write::Default<u8> for Device<'a, PINS> {}
+
+impl<'a, PINS> hal::spi::FullDuplex<u16> for Device<'a, PINS> {
+    type Error = SpiError;
+
+    fn read(&mut self) -> nb::Result<u16, SpiError> {
+        self.spi.read_data()
+    }
+
+    fn send(&mut self, word: u16) -> nb::Result<(), SpiError> {
+        self.spi.send_data(self.cs, word)
+    }
+}
+
+impl<'a, PINS> hal::blocking::spi::transfer::Default<u16> for Device<'a, PINS> {}
+
+impl<'a, PINS> hal::blocking::spi::write::Default<u16> for Device<'a, PINS> {}
+
+impl<PINS> SpiSlave<PINS> {
+    /// Configures the SPI as a slave doing 8-bit transfers
+    pub fn spi0(spi: SPI0, pins: PINS, mode: Mode, pmc: &mut Pmc) -> Self
+    where
+        PINS: SlavePins<SPI0>,
+    {
+        pmc.enable_peripheral(PeripheralId::SPI0);
+
+        spi.cr.write_with_zero(|w| w.spidis().set_bit());
+        spi.cr.write_with_zero(|w| w.swrst().set_bit());
+
+        // slave, NPCS0 is the slave select input
+        spi.mr.write_with_zero(|w| w.mstr().clear_bit());
+
+        spi.csr[0].write_with_zero(|w| {
+            w.cpol()
+                .bit(mode.polarity == Polarity::IdleHigh)
+                .ncpha()
+                .bit(mode.phase == Phase::CaptureOnFirstTransition)
+        });
+
+        spi.cr.write_with_zero(|w| w.spien().set_bit());
+
+        SpiSlave { spi, pins }
+    }
+
+    /// Sets the number of bits per transfer (`SPI_CSR0.BITS`), 8 to 16
+    ///
+    /// Other sizes are rejected with `SpiError::InvalidWordSize`.
+    pub fn set_word_size(&mut self, bits: u8) -> Result<(), SpiError> {
+        if !(8..=16).contains(&bits) {
+            return Err(SpiError::InvalidWordSize);
+        }
+
+        self.spi.csr[0].modify(|_, w| unsafe { w.bits_().bits(bits - 8) });
+        Ok(())
+    }
+
+    /// Returns whether the master is selecting the slave (NPCS0 low)
+    pub fn is_selected(&self) -> bool {
+        self.spi.sr.read().nssr().bit_is_clear()
+    }
+
+    /// Disables the SPI and releases the peripheral and pins
+    pub fn free(self) -> (SPI0, PINS) {
+        self.spi.cr.write_with_zero(|w| w.spidis().set_bit());
+        (self.spi, self.pins)
+    }
+
+    fn read_data(&mut self) -> nb::Result<u16, SpiError> {
+        let sr = self.spi.sr.read();
+
+        if sr.ovres().bit_is_set() {
+            Err(nb::Error::Other(SpiError::Overrun))
+        } else if sr.rdrf().bit_is_set() {
+            Ok(self.spi.rdr.read().rd().bits())
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+
+    fn send_data(&mut self, data: u16) -> nb::Result<(), SpiError> {
+        let sr = self.spi.sr.read();
+
+        if sr.undes().bit_is_set() {
+            Err(nb::Error::Other(SpiError::Underrun))
+        } else if sr.tdre().bit_is_set() {
+            self.spi
+                .tdr
+                .write_with_zero(|w| unsafe { w.td().bits(data) });
+            Ok(())
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+}
+
+impl<PINS> hal::spi::FullDuplex<u8> for SpiSlave<PINS> {
+    type Error = SpiError;
+
+    fn read(&mut self) -> nb::Result<u8, SpiError> {
+        self.read_data().map(|data| data as u8)
+    }
+
+    fn send(&mut self, byte: u8) -> nb::Result<(), SpiError> {
+        self.send_data(u16::from(byte))
+    }
+}
+
+impl<PINS> hal::blocking::spi::transfer::Default<u8> for SpiSlave<PINS> {}
+
+impl<PINS> hal::blocking::spi::write::Default<u8> for SpiSlave<PINS> {}
+
+impl<PINS> hal::spi::FullDuplex<u16> for SpiSlave<PINS> {
+    type Error = SpiError;
+
+    fn read(&mut self) -> nb::Result<u16, SpiError> {
+        self.read_data()
+    }
+
+    fn send(&mut self, word: u16) -> nb::Result<(), SpiError> {
+        self.send_data(word)
+    }
+}
+
+impl<PINS> hal::blocking::spi::transfer::Default<u16> for SpiSlave<PINS> {}
+
+impl<PINS> hal::blocking::spi::write::Default<u16> for SpiSlave<PINS> {}
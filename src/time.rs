@@ -1,24 +1,43 @@
 //! Time units
 
+use core::cmp;
+
 use crate::pmc::Clocks;
 use cortex_m::peripheral::DWT;
 
-/// Bits per second
+/// Bits per second, the baud rate of a serial line
+///
+/// This is a distinct type from `Hertz`, so a frequency can't be passed where a baud rate is
+/// expected by mistake.
 #[derive(Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct Bps(pub u32);
 
 /// Hertz
 #[derive(Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct Hertz(pub u32);
 
 /// KiloHertz
 #[derive(Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct KiloHertz(pub u32);
 
 /// MegaHertz
 #[derive(Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct MegaHertz(pub u32);
 
+/// MicroSeconds
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct MicroSeconds(pub u32);
+
+/// MilliSeconds
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct MilliSeconds(pub u32);
+
 /// Extension trait that adds convenience methods to the `u32` type
 pub trait U32Ext {
     /// Wrap in `Bps`
@@ -32,6 +51,12 @@ pub trait U32Ext {
 
     /// Wrap in `MegaHertz`
     fn mhz(self) -> MegaHertz;
+
+    /// Wrap in `MicroSeconds`
+    fn us(self) -> MicroSeconds;
+
+    /// Wrap in `MilliSeconds`
+    fn ms(self) -> MilliSeconds;
 }
 
 impl U32Ext for u32 {
@@ -50,6 +75,14 @@ impl U32Ext for u32 {
     fn mhz(self) -> MegaHertz {
         MegaHertz(self)
     }
+
+    fn us(self) -> MicroSeconds {
+        MicroSeconds(self)
+    }
+
+    fn ms(self) -> MilliSeconds {
+        MilliSeconds(self)
+    }
 }
 
 impl Into<Hertz> for KiloHertz {
@@ -70,6 +103,58 @@ impl Into<KiloHertz> for MegaHertz {
     }
 }
 
+impl Into<MicroSeconds> for MilliSeconds {
+    fn into(self) -> MicroSeconds {
+        MicroSeconds(self.0 * 1_000)
+    }
+}
+
+/// The frequency with this period, e.g. `20_000.us()` gives 50 Hz
+///
+/// The result saturates: a period of 0 gives the fastest frequency, 1 MHz, and periods longer
+/// than a second, which `Hertz` can't express, give 1 Hz.
+impl Into<Hertz> for MicroSeconds {
+    fn into(self) -> Hertz {
+        Hertz((1_000_000 / self.0.max(1)).max(1))
+    }
+}
+
+/// The frequency with this period, e.g. `20.ms()` gives 50 Hz
+///
+/// The result saturates: a period of 0 gives the fastest frequency, 1 kHz, and periods longer
+/// than a second, which `Hertz` can't express, give 1 Hz.
+impl Into<Hertz> for MilliSeconds {
+    fn into(self) -> Hertz {
+        Hertz((1_000 / self.0.max(1)).max(1))
+    }
+}
+
+impl Hertz {
+    /// Returns the period of this frequency, rounded to the nearest microsecond
+    ///
+    /// Panics if the frequency is 0 Hz.
+    pub fn into_duration(self) -> MicroSeconds {
+        let freq = u64::from(self.0);
+        MicroSeconds(((1_000_000 + freq / 2) / freq) as u32)
+    }
+}
+
+/// Returns the number of ticks of a clock at `freq` lasting `duration`, rounded to the
+/// nearest tick
+pub fn ticks_for(duration: MicroSeconds, freq: Hertz) -> u64 {
+    (u64::from(duration.0) * u64::from(freq.0) + 500_000) / 1_000_000
+}
+
+/// Returns how long `ticks` ticks of a clock at `freq` last, rounded to the nearest
+/// microsecond and saturated to `u32::MAX` microseconds
+///
+/// Panics if the frequency is 0 Hz.
+pub fn duration_of(ticks: u32, freq: Hertz) -> MicroSeconds {
+    let freq = u64::from(freq.0);
+    let us = (u64::from(ticks) * 1_000_000 + freq / 2) / freq;
+    MicroSeconds(cmp::min(us, u64::from(u32::max_value())) as u32)
+}
+
 /// A monotonic nondecreasing timer
 #[derive(Clone, Copy)]
 pub struct MonoTimer {
@@ -85,7 +170,7 @@ impl MonoTimer {
         drop(dwt);
 
         MonoTimer {
-            frequency: clocks.slck(),
+            frequency: clocks.hclk(),
         }
     }
 
@@ -114,3 +199,84 @@ impl Instant {
         DWT::get_cycle_count().wrapping_sub(self.now)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{duration_of, ticks_for, Hertz, MicroSeconds, MilliSeconds};
+
+    #[test]
+    fn into_duration_rounds_to_nearest() {
+        assert_eq!(Hertz(1).into_duration().0, 1_000_000);
+        assert_eq!(Hertz(1_000_000).into_duration().0, 1);
+        // 333333.3 us
+        assert_eq!(Hertz(3).into_duration().0, 333_333);
+        // 142857.1 us
+        assert_eq!(Hertz(7).into_duration().0, 142_857);
+        // 1.5 us, halves round up
+        assert_eq!(Hertz(666_667).into_duration().0, 1);
+        assert_eq!(Hertz(666_666).into_duration().0, 2);
+        // 0.5 us
+        assert_eq!(Hertz(2_000_000).into_duration().0, 1);
+        // 0.49 us
+        assert_eq!(Hertz(2_000_001).into_duration().0, 0);
+        assert_eq!(Hertz(u32::max_value()).into_duration().0, 0);
+    }
+
+    #[test]
+    fn ticks_for_rounds_to_nearest() {
+        assert_eq!(ticks_for(MicroSeconds(1), Hertz(84_000_000)), 84);
+        assert_eq!(ticks_for(MicroSeconds(1_000_000), Hertz(1)), 1);
+        // 0.5 tick
+        assert_eq!(ticks_for(MicroSeconds(500_000), Hertz(1)), 1);
+        // 0.499999 tick
+        assert_eq!(ticks_for(MicroSeconds(499_999), Hertz(1)), 0);
+        // MCK / 128 at 84 MHz, 0.65625 tick
+        assert_eq!(ticks_for(MicroSeconds(1), Hertz(656_250)), 1);
+        // 32.768 ticks of the slow clock
+        assert_eq!(ticks_for(MicroSeconds(1_000), Hertz(32_768)), 33);
+        assert_eq!(
+            ticks_for(MicroSeconds(u32::max_value()), Hertz(u32::max_value())),
+            18_446_744_065_120
+        );
+    }
+
+    #[test]
+    fn duration_of_rounds_to_nearest() {
+        assert_eq!(duration_of(84, Hertz(84_000_000)).0, 1);
+        assert_eq!(duration_of(42, Hertz(84_000_000)).0, 1);
+        assert_eq!(duration_of(41, Hertz(84_000_000)).0, 0);
+        assert_eq!(duration_of(32_768, Hertz(32_768)).0, 1_000_000);
+        assert_eq!(duration_of(1, Hertz(3)).0, 333_333);
+        assert_eq!(duration_of(2, Hertz(3)).0, 666_667);
+    }
+
+    #[test]
+    fn duration_of_saturates() {
+        assert_eq!(duration_of(u32::max_value(), Hertz(1)).0, u32::max_value());
+        assert_eq!(duration_of(4_294, Hertz(1)).0, 4_294_000_000);
+    }
+
+    #[test]
+    fn period_into_hertz_saturates() {
+        let hz = |period: MicroSeconds| -> Hertz { period.into() };
+        assert_eq!(hz(MicroSeconds(20_000)).0, 50);
+        assert_eq!(hz(MicroSeconds(0)).0, 1_000_000);
+        assert_eq!(hz(MicroSeconds(2_000_000)).0, 1);
+
+        let hz = |period: MilliSeconds| -> Hertz { period.into() };
+        assert_eq!(hz(MilliSeconds(20)).0, 50);
+        assert_eq!(hz(MilliSeconds(0)).0, 1_000);
+        assert_eq!(hz(MilliSeconds(2_000)).0, 1);
+    }
+
+    #[test]
+    fn round_trip_at_timer_clocks() {
+        for &freq in [656_250, 1_000_000, 42_000_000, 84_000_000].iter() {
+            let freq = Hertz(freq);
+            for &us in [1_000, 20_000, 1_000_000].iter() {
+                let ticks = ticks_for(MicroSeconds(us), freq);
+                assert_eq!(duration_of(ticks as u32, freq).0, us);
+            }
+        }
+    }
+}
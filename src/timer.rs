@@ -0,0 +1,740 @@
+//! Timer/Counter (TC)
+//!
+//! Each of the three TC blocks (TC0, TC1, TC2) holds three channels. A `Timer` takes a whole
+//! block and counts on its channel 0 (peripheral TC0, TC3 or TC6).
+//!
+//! The counters are 32 bits wide. `CountDown` counts on `TimerClock::Clock1` (MCK / 2): at
+//! 84 MHz this gives a resolution of 23.8 ns and periods up to about 102 s, so any timeout
+//! of 1 Hz or more fits without switching to a slower clock.
+//!
+//! Unlike the 16-bit TCs of other Atmel parts, no channel chaining (`TC_BMR`) is needed to
+//! get 32-bit counts: `count` returns the full counter value, and `start_us` takes timeouts
+//! of up to 2^32 us (over an hour) with microsecond resolution.
+//!
+//! # Input capture
+//!
+//! `start_capture` measures the signal on the TIOA line of channel 0: TIOA0 (PB25, Due pin
+//! D2) for TC0 and TIOA6 (PC25, D5) for TC2. TIOA3 of TC1 is on PE9, which the SAM3X8E
+//! doesn't bond out. The pin must be configured for its peripheral function.
+//!
+//! # PWM output
+//!
+//! A `Timer` also implements `embedded_hal::Pwm`, generating waveforms on TIOA and TIOB of
+//! channel 0 with the period set by RC and the duty cycles by RA and RB. This adds PWM
+//! outputs on pins the PWM controller doesn't reach: TIOA0 (PB25, D2) and TIOB0 (PB27, D13)
+//! for TC0, TIOA6 (PC25, D5) and TIOB6 (PC26, D4) for TC2.
+//!
+//! # Quadrature decoder
+//!
+//! A `QuadratureDecoder` takes a whole TC block and counts the edges of a rotary encoder's
+//! phases in hardware (`TC_BMR.QDEN`): PHA on TIOA and PHB on TIOB of channel 0, i.e. TIOA0
+//! (PB25, D2) and TIOB0 (PB27, D13) for TC0, TIOA6 (PC25, D5) and TIOB6 (PC26, D4) for TC2.
+//! An optional index signal on TIOB of channel 1 resets the position and counts revolutions
+//! on channel 1. The pins must be configured for their peripheral function.
+//!
+//! # Monotonic timer
+//!
+//! A TC `MonoTimer` counts freely on channel 0 of a block, for timestamps: `now` returns an
+//! `Instant` and `Instant::elapsed` the ticks since. Unlike `time::MonoTimer` it doesn't
+//! need the DWT cycle counter, which debuggers also use. At MCK / 2 the 32-bit counter wraps
+//! after about 102 s at 84 MHz; longer intervals need a slower clock.
+//!
+//! # SysTick
+//!
+//! `SysTimer` implements `CountDown` on the core's SysTick timer, leaving all TC channels
+//! free. Its reload value is only 24 bits wide (about 200 ms at 84 MHz); longer periods are
+//! counted as several equal SysTick wraps.
+
+use core::marker::PhantomData;
+
+use cortex_m::peripheral::syst::SystClkSource;
+use cortex_m::peripheral::SYST;
+
+use crate::hal::timer::{CountDown, Periodic};
+use crate::hal::{Direction, Qei};
+use crate::pmc::{Clocks, PeripheralId, Pmc};
+use crate::time::{self, Hertz, MicroSeconds};
+use sam3x8e::{TC0, TC1, TC2};
+use void::Void;
+
+/// Counter clocks of a TC channel (`TC_CMR.TCCLKS`)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TimerClock {
+    /// MCK / 2
+    Clock1,
+    /// MCK / 8
+    Clock2,
+    /// MCK / 32
+    Clock3,
+    /// MCK / 128
+    Clock4,
+    /// Slow clock (SLCK)
+    Clock5,
+}
+
+impl TimerClock {
+    /// Clocks from fastest to slowest
+    const ALL: [TimerClock; 5] = [
+        TimerClock::Clock1,
+        TimerClock::Clock2,
+        TimerClock::Clock3,
+        TimerClock::Clock4,
+        TimerClock::Clock5,
+    ];
+
+    fn tcclks(self) -> u8 {
+        match self {
+            TimerClock::Clock1 => 0,
+            TimerClock::Clock2 => 1,
+            TimerClock::Clock3 => 2,
+            TimerClock::Clock4 => 3,
+            TimerClock::Clock5 => 4,
+        }
+    }
+
+    /// Returns the frequency of the clock
+    pub fn freq(self, clocks: &Clocks) -> Hertz {
+        match self {
+            TimerClock::Clock1 => Hertz(clocks.mck().0 / 2),
+            TimerClock::Clock2 => Hertz(clocks.mck().0 / 8),
+            TimerClock::Clock3 => Hertz(clocks.mck().0 / 32),
+            TimerClock::Clock4 => Hertz(clocks.mck().0 / 128),
+            TimerClock::Clock5 => clocks.slck(),
+        }
+    }
+}
+
+/// Waveform outputs of a TC channel
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TimerOutput {
+    /// TIOA, duty cycle set by RA
+    TIOA,
+    /// TIOB, duty cycle set by RB
+    TIOB,
+}
+
+/// Period and low time of a signal, as captured in RB and RA
+#[derive(Clone, Copy)]
+pub struct Capture {
+    /// Counter ticks from a falling edge to the next rising edge
+    pub low_ticks: u32,
+    /// Counter ticks between two falling edges
+    pub period_ticks: u32,
+    /// Frequency of the counter clock
+    pub tick_freq: Hertz,
+}
+
+impl Capture {
+    /// Returns the frequency of the signal
+    pub fn frequency(&self) -> Hertz {
+        Hertz(self.tick_freq.0 / self.period_ticks.max(1))
+    }
+
+    /// Returns the period of the signal in microseconds
+    pub fn period_us(&self) -> u32 {
+        time::duration_of(self.period_ticks, self.tick_freq).0
+    }
+
+    /// Returns the fraction of the period the signal is high, from 0.0 to 1.0
+    pub fn duty(&self) -> f32 {
+        if self.period_ticks == 0 {
+            return 0.0;
+        }
+        let high = self.period_ticks.saturating_sub(self.low_ticks);
+        high as f32 / self.period_ticks as f32
+    }
+}
+
+/// Returns the fastest clock whose count for `us` microseconds fits in the 32-bit counter,
+/// along with that count
+fn clock_for_us(clocks: &Clocks, us: u32) -> (TimerClock, u32) {
+    TimerClock::ALL
+        .iter()
+        .map(|clock| (*clock, time::ticks_for(MicroSeconds(us), clock.freq(clocks))))
+        .find(|(_, ticks)| *ticks <= u64::from(u32::max_value()))
+        .map(|(clock, ticks)| (clock, ticks as u32))
+        .unwrap_or((TimerClock::Clock5, u32::max_value()))
+}
+
+/// Largest SysTick reload value
+const MAX_RVR: u32 = 0x00FF_FFFF;
+
+/// Timer running on channel 0 of a TC block
+pub struct Timer<TC> {
+    tc: TC,
+    clocks: Clocks,
+    clock: TimerClock,
+}
+
+macro_rules! hal {
+    ($($TCX:ident: ($tcX:ident, $pid:ident),)+) => {
+        $(
+            impl Timer<$TCX> {
+                /// Enables the clock of channel 0 of a TC block
+                pub fn $tcX(tc: $TCX, clocks: Clocks, pmc: &mut Pmc) -> Self {
+                    pmc.enable_peripheral(PeripheralId::$pid);
+
+                    tc.ccr0.write_with_zero(|w| w.clkdis().set_bit());
+
+                    Timer {
+                        tc,
+                        clocks,
+                        clock: TimerClock::Clock1,
+                    }
+                }
+
+                /// Returns the clock the counter currently runs on
+                pub fn clock(&self) -> TimerClock {
+                    self.clock
+                }
+
+                /// Starts capturing the signal on TIOA in capture mode
+                ///
+                /// Each falling edge resets the counter (`ABETRG`, `ETRGEDG = FALLING`) after
+                /// loading RB; each rising edge loads RA (`LDRA = RISING`, `LDRB = FALLING`).
+                pub fn start_capture(&mut self, clock: TimerClock) {
+                    self.clock = clock;
+
+                    self.tc.ccr0.write_with_zero(|w| w.clkdis().set_bit());
+
+                    self.tc.cmr0().write_with_zero(|w| {
+                        w.tcclks()
+                            .bits(clock.tcclks())
+                            .ldra()
+                            .rising()
+                            .ldrb()
+                            .falling()
+                            .abetrg()
+                            .set_bit()
+                            .etrgedg()
+                            .falling()
+                    });
+
+                    // reading SR clears stale load flags
+                    self.tc.sr0.read();
+                    self.tc
+                        .ccr0
+                        .write_with_zero(|w| w.clken().set_bit().swtrg().set_bit());
+                }
+
+                /// Returns the latest capture once RB has been loaded (`TC_SR.LDRBS`)
+                ///
+                /// Reading RB clears the load flags.
+                pub fn capture(&mut self) -> nb::Result<Capture, Void> {
+                    if self.tc.sr0.read().ldrbs().bit_is_clear() {
+                        return Err(nb::Error::WouldBlock);
+                    }
+
+                    let low_ticks = self.tc.ra0.read().ra().bits();
+                    let period_ticks = self.tc.rb0.read().rb().bits();
+
+                    Ok(Capture {
+                        low_ticks,
+                        period_ticks,
+                        tick_freq: self.clock.freq(&self.clocks),
+                    })
+                }
+
+                /// Stops the counter
+                pub fn stop(&mut self) {
+                    self.tc.ccr0.write_with_zero(|w| w.clkdis().set_bit());
+                }
+
+                /// Stops the counter and releases the TC block
+                pub fn free(self) -> $TCX {
+                    self.tc.ccr0.write_with_zero(|w| w.clkdis().set_bit());
+                    self.tc
+                }
+            }
+
+            impl CountDown for Timer<$TCX> {
+                type Time = Hertz;
+
+                /// Starts a periodic count down
+                ///
+                /// The period is generated by RC compare in waveform mode (`WAVSEL = UP_RC`).
+                /// A timeout of 0 Hz has no period: the counter is left stopped and `wait`
+                /// never returns `Ok`.
+                fn start<T>(&mut self, timeout: T)
+                where
+                    T: Into<Hertz>,
+                {
+                    let timeout = timeout.into().0;
+                    if timeout == 0 {
+                        self.stop();
+                        // reading SR clears a pending RC compare
+                        self.tc.sr0.read();
+                        return;
+                    }
+
+                    let clock = TimerClock::Clock1;
+                    let ticks = clock.freq(&self.clocks).0 / timeout;
+                    self.start_ticks(clock, ticks);
+                }
+
+                /// Returns `Ok` once per period, when the counter has reached RC (`TC_SR.CPCS`)
+                fn wait(&mut self) -> nb::Result<(), Void> {
+                    if self.tc.sr0.read().cpcs().bit_is_set() {
+                        Ok(())
+                    } else {
+                        Err(nb::Error::WouldBlock)
+                    }
+                }
+            }
+
+            impl Timer<$TCX> {
+                /// Starts a periodic count down of `timeout_us` microseconds, polled with `wait`
+                ///
+                /// The fastest clock whose count for the whole timeout fits in the 32-bit
+                /// counter is selected.
+                pub fn start_us(&mut self, timeout_us: u32) {
+                    let (clock, ticks) = clock_for_us(&self.clocks, timeout_us);
+                    self.start_ticks(clock, ticks);
+                }
+
+                /// Sets up TIOA to emit a single high pulse of `width_us` microseconds on each
+                /// `fire`
+                ///
+                /// TIOA goes high on the software trigger (`ASWTRG`) and low at RA compare; the
+                /// counter clock is disabled at RC compare, one tick later (`CPCDIS`). Like
+                /// `start_us`, the fastest clock fitting the width is selected.
+                pub fn start_one_pulse(&mut self, width_us: u32) {
+                    let (clock, ticks) = clock_for_us(&self.clocks, width_us);
+                    let ticks = ticks.max(1).min(u32::max_value() - 1);
+                    self.clock = clock;
+
+                    self.tc.ccr0.write_with_zero(|w| w.clkdis().set_bit());
+
+                    self.tc.cmr0_wave_eq_1().write_with_zero(|w| {
+                        w.tcclks()
+                            .bits(clock.tcclks())
+                            .wave()
+                            .set_bit()
+                            .wavsel()
+                            .up_rc()
+                            .cpcdis()
+                            .set_bit()
+                            .eevt()
+                            .xc0()
+                            .aswtrg()
+                            .set()
+                            .acpa()
+                            .clear()
+                    });
+                    self.tc.ra0.write_with_zero(|w| unsafe { w.ra().bits(ticks) });
+                    self.tc.rc0.write_with_zero(|w| unsafe { w.rc().bits(ticks + 1) });
+                }
+
+                /// Emits the pulse set up by `start_one_pulse`
+                ///
+                /// Firing again while a pulse is under way restarts it, stretching it.
+                pub fn fire(&mut self) {
+                    // reading SR clears a stale compare flag
+                    self.tc.sr0.read();
+                    self.tc
+                        .ccr0
+                        .write_with_zero(|w| w.clken().set_bit().swtrg().set_bit());
+                }
+
+                /// Returns whether the last pulse fired has ended (`TC_SR.CLKSTA`)
+                pub fn is_pulse_done(&self) -> bool {
+                    self.tc.sr0.read().clksta().bit_is_clear()
+                }
+
+                /// Returns the counter value (`TC_CV`)
+                pub fn count(&self) -> u32 {
+                    self.tc.cv0.read().cv().bits()
+                }
+
+                fn start_ticks(&mut self, clock: TimerClock, ticks: u32) {
+                    self.clock = clock;
+
+                    self.tc.ccr0.write_with_zero(|w| w.clkdis().set_bit());
+
+                    self.tc.cmr0_wave_eq_1().write_with_zero(|w| {
+                        w.tcclks()
+                            .bits(clock.tcclks())
+                            .wave()
+                            .set_bit()
+                            .wavsel()
+                            .up_rc()
+                    });
+                    self.tc
+                        .rc0
+                        .write_with_zero(|w| unsafe { w.rc().bits(ticks.max(1)) });
+
+                    // reading SR clears a stale compare flag
+                    self.tc.sr0.read();
+                    self.tc
+                        .ccr0
+                        .write_with_zero(|w| w.clken().set_bit().swtrg().set_bit());
+                }
+            }
+
+            impl Periodic for Timer<$TCX> {}
+
+            impl hal::Pwm for Timer<$TCX> {
+                type Channel = TimerOutput;
+                type Time = f32; // Seconds
+                type Duty = f32; // 0.0 ... 1.0
+
+                /// Starts driving an output: high from the start of the period until RA (TIOA)
+                /// or RB (TIOB), then low
+                fn enable(&mut self, output: TimerOutput) {
+                    self.tc.cmr0_wave_eq_1().modify(|_, w| match output {
+                        TimerOutput::TIOA => w.acpc().set().acpa().clear(),
+                        TimerOutput::TIOB => w.bcpc().set().bcpb().clear(),
+                    });
+
+                    if self.tc.sr0.read().clksta().bit_is_clear() {
+                        self.tc
+                            .ccr0
+                            .write_with_zero(|w| w.clken().set_bit().swtrg().set_bit());
+                    }
+                }
+
+                /// Stops driving an output, which goes low at the end of the current period
+                fn disable(&mut self, output: TimerOutput) {
+                    self.tc.cmr0_wave_eq_1().modify(|_, w| match output {
+                        TimerOutput::TIOA => w.acpc().clear().acpa().none(),
+                        TimerOutput::TIOB => w.bcpc().clear().bcpb().none(),
+                    });
+                }
+
+                fn get_period(&self) -> f32 {
+                    self.tc.rc0.read().rc().bits() as f32 / self.clock.freq(&self.clocks).0 as f32
+                }
+
+                /// Returns the duty cycle of an output, or 0.0 if the period hasn't been set
+                fn get_duty(&self, output: TimerOutput) -> f32 {
+                    let rc = self.tc.rc0.read().rc().bits();
+                    if rc == 0 {
+                        return 0.0;
+                    }
+                    let compare = match output {
+                        TimerOutput::TIOA => self.tc.ra0.read().ra().bits(),
+                        TimerOutput::TIOB => self.tc.rb0.read().rb().bits(),
+                    };
+                    compare as f32 / rc as f32
+                }
+
+                fn get_max_duty(&self) -> f32 {
+                    1.0
+                }
+
+                /// Sets the duty cycle of an output
+                ///
+                /// The duty cycle saturates to `[0.0, 1.0]`, so the compare value never goes
+                /// beyond RC; NaN counts as 0.0.
+                fn set_duty(&mut self, output: TimerOutput, duty: f32) {
+                    // f32::max returns the other operand for NaN
+                    let duty = duty.max(0.0).min(1.0);
+                    let compare = (duty * self.tc.rc0.read().rc().bits() as f32) as u32;
+                    match output {
+                        TimerOutput::TIOA => {
+                            self.tc.ra0.write_with_zero(|w| unsafe { w.ra().bits(compare) })
+                        }
+                        TimerOutput::TIOB => {
+                            self.tc.rb0.write_with_zero(|w| unsafe { w.rb().bits(compare) })
+                        }
+                    }
+                }
+
+                /// Sets the period (in seconds) shared by TIOA and TIOB, keeping their duty cycles
+                ///
+                /// The fastest clock at which the period fits in the counter is selected.
+                fn set_period<P>(&mut self, period: P)
+                where
+                    P: Into<f32>,
+                {
+                    let period = period.into();
+                    let duty_a = self.get_duty(TimerOutput::TIOA);
+                    let duty_b = self.get_duty(TimerOutput::TIOB);
+
+                    let clocks = self.clocks;
+                    let (clock, ticks) = TimerClock::ALL
+                        .iter()
+                        .map(|clock| (*clock, period * clock.freq(&clocks).0 as f32))
+                        .find(|(_, ticks)| *ticks <= u32::max_value() as f32)
+                        .unwrap_or((TimerClock::Clock5, u32::max_value() as f32));
+                    self.clock = clock;
+
+                    // EEVT must not select TIOB, which would turn it into an input
+                    self.tc.cmr0_wave_eq_1().modify(|_, w| {
+                        w.tcclks()
+                            .bits(clock.tcclks())
+                            .wave()
+                            .set_bit()
+                            .wavsel()
+                            .up_rc()
+                            .eevt()
+                            .xc0()
+                    });
+                    self.tc
+                        .rc0
+                        .write_with_zero(|w| unsafe { w.rc().bits(ticks as u32) });
+
+                    self.set_duty(TimerOutput::TIOA, duty_a);
+                    self.set_duty(TimerOutput::TIOB, duty_b);
+                }
+            }
+        )+
+    }
+}
+
+hal! {
+    TC0: (tc0, TC0),
+    TC1: (tc1, TC3),
+    TC2: (tc2, TC6),
+}
+
+/// Quadrature decoder on channels 0 and 1 of a TC block
+pub struct QuadratureDecoder<TC> {
+    tc: TC,
+}
+
+macro_rules! qdec {
+    ($($TCX:ident: ($tcX:ident, $pid0:ident, $pid1:ident),)+) => {
+        $(
+            impl QuadratureDecoder<$TCX> {
+                /// Starts decoding the encoder on TIOA0/TIOB0 (or TIOA6/TIOB6)
+                ///
+                /// Both edges of both phases are counted (`EDGPHA`), four counts per encoder
+                /// line. Channel 0 counts the position and channel 1 the index pulses
+                /// (`POSEN`); both run in capture mode on XC0, triggered by TIOA.
+                pub fn $tcX(tc: $TCX, pmc: &mut Pmc) -> Self {
+                    pmc.enable_peripheral(PeripheralId::$pid0);
+                    pmc.enable_peripheral(PeripheralId::$pid1);
+
+                    tc.bmr.write_with_zero(|w| {
+                        w.qden().set_bit().posen().set_bit().edgpha().set_bit()
+                    });
+                    tc.cmr0().write_with_zero(|w| {
+                        w.tcclks().xc0().abetrg().set_bit().etrgedg().rising()
+                    });
+                    tc.cmr1().write_with_zero(|w| {
+                        w.tcclks().xc0().abetrg().set_bit().etrgedg().rising()
+                    });
+
+                    tc.ccr0.write_with_zero(|w| w.clken().set_bit().swtrg().set_bit());
+                    tc.ccr1.write_with_zero(|w| w.clken().set_bit().swtrg().set_bit());
+
+                    QuadratureDecoder { tc }
+                }
+
+                /// Returns the number of index pulses seen (`TC_CV1`), signed by direction
+                ///
+                /// Stays at 0 if the encoder has no index signal.
+                pub fn revolutions(&self) -> i32 {
+                    self.tc.cv1.read().cv().bits() as i32
+                }
+
+                /// Stops decoding and releases the TC block
+                pub fn free(self) -> $TCX {
+                    self.tc.ccr0.write_with_zero(|w| w.clkdis().set_bit());
+                    self.tc.ccr1.write_with_zero(|w| w.clkdis().set_bit());
+                    self.tc.bmr.write_with_zero(|w| w.qden().clear_bit());
+                    self.tc
+                }
+            }
+
+            impl Qei for QuadratureDecoder<$TCX> {
+                type Count = i32;
+
+                /// Returns the position in counts (`TC_CV0`), negative when the encoder turned
+                /// backwards past its start
+                fn count(&self) -> i32 {
+                    self.tc.cv0.read().cv().bits() as i32
+                }
+
+                /// Returns the direction of the last count (`TC_QISR.DIR`)
+                fn direction(&self) -> Direction {
+                    if self.tc.qisr.read().dir().bit_is_set() {
+                        Direction::Downcounting
+                    } else {
+                        Direction::Upcounting
+                    }
+                }
+            }
+        )+
+    }
+}
+
+qdec! {
+    TC0: (tc0, TC0, TC1),
+    TC1: (tc1, TC3, TC4),
+    TC2: (tc2, TC6, TC7),
+}
+
+/// Free-running counter on channel 0 of a TC block
+pub struct MonoTimer<TC> {
+    tc: TC,
+    frequency: Hertz,
+}
+
+/// A reading of a TC `MonoTimer`
+pub struct Instant<TC> {
+    now: u32,
+    frequency: Hertz,
+    _tc: PhantomData<TC>,
+}
+
+// NOTE(derive) would require `TC: Copy`
+impl<TC> Clone for Instant<TC> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<TC> Copy for Instant<TC> {}
+
+macro_rules! mono {
+    ($($TCX:ident: ($tcX:ident, $pid:ident),)+) => {
+        $(
+            impl MonoTimer<$TCX> {
+                /// Starts counting on channel 0 of a TC block at the rate of `clock`
+                ///
+                /// The channel runs in capture mode without any trigger, so the counter
+                /// wraps around from `u32::MAX` to 0.
+                pub fn $tcX(tc: $TCX, clock: TimerClock, clocks: Clocks, pmc: &mut Pmc) -> Self {
+                    pmc.enable_peripheral(PeripheralId::$pid);
+
+                    tc.ccr0.write_with_zero(|w| w.clkdis().set_bit());
+                    tc.cmr0().write_with_zero(|w| w.tcclks().bits(clock.tcclks()));
+                    tc.ccr0.write_with_zero(|w| w.clken().set_bit().swtrg().set_bit());
+
+                    MonoTimer {
+                        tc,
+                        frequency: clock.freq(&clocks),
+                    }
+                }
+
+                /// Returns the frequency the counter ticks at
+                pub fn frequency(&self) -> Hertz {
+                    self.frequency
+                }
+
+                /// Returns an `Instant` corresponding to "now"
+                pub fn now(&self) -> Instant<$TCX> {
+                    Instant {
+                        now: self.tc.cv0.read().cv().bits(),
+                        frequency: self.frequency,
+                        _tc: PhantomData,
+                    }
+                }
+
+                /// Stops the counter and releases the TC block
+                pub fn free(self) -> $TCX {
+                    self.tc.ccr0.write_with_zero(|w| w.clkdis().set_bit());
+                    self.tc
+                }
+            }
+
+            impl Instant<$TCX> {
+                /// Ticks elapsed since the `Instant` was created
+                ///
+                /// Only meaningful while the `MonoTimer` that created it is running.
+                pub fn elapsed(self) -> u32 {
+                    // NOTE(unsafe) atomic read of a register the MonoTimer doesn't write to
+                    let now = unsafe { (*$TCX::ptr()).cv0.read().cv().bits() };
+                    now.wrapping_sub(self.now)
+                }
+
+                /// Microseconds elapsed since the `Instant` was created
+                pub fn elapsed_us(self) -> MicroSeconds {
+                    time::duration_of(self.elapsed(), self.frequency)
+                }
+            }
+        )+
+    }
+}
+
+mono! {
+    TC0: (tc0, TC0),
+    TC1: (tc1, TC3),
+    TC2: (tc2, TC6),
+}
+
+/// Count down timer on the system timer (SysTick)
+pub struct SysTimer {
+    syst: SYST,
+    clocks: Clocks,
+    wraps: u32,
+    remaining: u32,
+}
+
+impl SysTimer {
+    /// Configures the system timer (SysTick) to count core clock cycles
+    pub fn syst(mut syst: SYST, clocks: Clocks) -> Self {
+        syst.disable_counter();
+        syst.set_clock_source(SystClkSource::Core);
+
+        SysTimer {
+            syst,
+            clocks,
+            wraps: 1,
+            remaining: 1,
+        }
+    }
+
+    /// Stops the counter
+    pub fn stop(&mut self) {
+        self.syst.disable_counter();
+    }
+
+    /// Stops the counter and releases the system timer (SysTick) resource
+    pub fn free(mut self) -> SYST {
+        self.syst.disable_counter();
+        self.syst
+    }
+}
+
+impl CountDown for SysTimer {
+    type Time = Hertz;
+
+    /// Starts a periodic count down
+    ///
+    /// Periods longer than a full SysTick reload are split into equal wraps, so the period
+    /// may be short by up to one core clock cycle per wrap. A timeout of 0 Hz has no period:
+    /// the counter is left stopped and `wait` never returns `Ok`.
+    fn start<T>(&mut self, timeout: T)
+    where
+        T: Into<Hertz>,
+    {
+        let timeout = timeout.into().0;
+        if timeout == 0 {
+            self.stop();
+            // reading COUNTFLAG clears a pending wrap
+            self.syst.has_wrapped();
+            return;
+        }
+
+        let ticks = (self.clocks.hclk().0 / timeout).max(2);
+        let wraps = (ticks + MAX_RVR) / (MAX_RVR + 1);
+
+        self.wraps = wraps;
+        self.remaining = wraps;
+
+        self.syst.disable_counter();
+        self.syst.set_reload(ticks / wraps - 1);
+        self.syst.clear_current();
+        self.syst.enable_counter();
+    }
+
+    /// Returns `Ok` once per period, when SysTick has wrapped the required number of times
+    fn wait(&mut self) -> nb::Result<(), Void> {
+        if !self.syst.has_wrapped() {
+            return Err(nb::Error::WouldBlock);
+        }
+
+        self.remaining -= 1;
+        if self.remaining == 0 {
+            self.remaining = self.wraps;
+            Ok(())
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+}
+
+impl Periodic for SysTimer {}
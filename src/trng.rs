@@ -0,0 +1,63 @@
+//! True Random Number Generator (TRNG)
+//!
+//! Once enabled, the TRNG produces a new 32-bit random value every 84 clock cycles, flagged
+//! by `TRNG_ISR.DATRDY` and read from `TRNG_ODATA`. Reading `TRNG_ISR` clears the flag.
+//!
+//! `Trng` implements the embedded-hal `blocking::rng::Read` trait to fill byte buffers.
+
+use core::convert::Infallible;
+
+use crate::hal;
+use crate::pmc::{PeripheralId, Pmc};
+use sam3x8e::TRNG;
+
+/// Password of `TRNG_CR.KEY`, "RNG" in ASCII
+const KEY: u32 = 0x52_4E_47;
+
+/// True Random Number Generator
+pub struct Trng {
+    trng: TRNG,
+}
+
+impl Trng {
+    /// Enables the TRNG clock and starts generating random values
+    pub fn new(trng: TRNG, pmc: &mut Pmc) -> Self {
+        pmc.enable_peripheral(PeripheralId::TRNG);
+
+        trng.cr
+            .write_with_zero(|w| unsafe { w.key().bits(KEY).enable().set_bit() });
+
+        Trng { trng }
+    }
+
+    /// Returns a random word, if one is ready
+    pub fn read_u32(&mut self) -> nb::Result<u32, Infallible> {
+        if self.trng.isr.read().datrdy().bit_is_set() {
+            Ok(self.trng.odata.read().odata().bits())
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+
+    /// Stops the TRNG and releases the peripheral
+    pub fn free(self) -> TRNG {
+        self.trng
+            .cr
+            .write_with_zero(|w| unsafe { w.key().bits(KEY).enable().clear_bit() });
+
+        self.trng
+    }
+}
+
+impl hal::blocking::rng::Read for Trng {
+    type Error = Infallible;
+
+    fn read(&mut self, buffer: &mut [u8]) -> Result<(), Infallible> {
+        for chunk in buffer.chunks_mut(4) {
+            let word = nb::block!(self.read_u32())?;
+            chunk.copy_from_slice(&word.to_le_bytes()[..chunk.len()]);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,376 @@
+//! Two-Wire Interface (TWI, I2C) in master mode
+//!
+//! On the Due, TWI1 (TWD1 on PB12, TWCK1 on PB13) is wired to the SDA/SCL header and
+//! TWI0 (TWD0 on PA17, TWCK0 on PA18) to SDA1/SCL1.
+//!
+//! The peripheral can't issue a repeated start on its own. `WriteRead` with up to 3 bytes
+//! to write sends them as the internal address (`TWI_IADR`), which the hardware follows
+//! with a repeated start. Longer writes are sent as a separate transfer ending with a STOP.
+//!
+//! `read_from` and `write_to` take an `Address` (7 or 10 bits) and an internal address of
+//! up to 3 bytes, mapped to `TWI_MMR.DADR`, `TWI_MMR.IADRSZ` and `TWI_IADR`.
+//!
+//! A slave holding SDA low makes the peripheral wait forever. `set_timeout` bounds every
+//! wait on the status register and makes the transfer fail with `TwiError::Timeout`;
+//! `recover_bus` then clocks the stuck slave free.
+
+use crate::gpio::pioa::{PA17, PA18};
+use crate::gpio::piob::{PB12, PB13};
+use crate::gpio::{Alternate, PeriphA};
+use crate::hal::blocking::i2c::{Read, Write, WriteRead};
+use crate::pmc::{Clocks, PeripheralId, Pmc};
+use crate::time::Hertz;
+use cortex_m::asm;
+use sam3x8e::{PIOA, PIOB, TWI0, TWI1};
+
+/// TWI error
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TwiError {
+    /// The slave didn't acknowledge its address or a data byte
+    Nack,
+    /// Another master won the bus arbitration
+    ArbitrationLost,
+    /// The internal address doesn't fit in `TWI_IADR`
+    InternalAddressTooLong,
+    /// The status register was polled `timeout` times without the transfer progressing
+    Timeout,
+}
+
+/// Device address of a slave
+///
+/// This is the address the slave answers to on the bus. It is distinct from the internal
+/// address, which selects a register or memory location inside the device and is sent as
+/// the first bytes of a transfer (see `Twi::read_from` and `Twi::write_to`).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Address {
+    /// 7-bit address
+    SevenBit(u8),
+    /// 10-bit address
+    TenBit(u16),
+}
+
+/// Pin usable as the TWD (SDA) line of a TWI
+pub trait TwdPin<TWI> {}
+
+/// Pin usable as the TWCK (SCL) line of a TWI
+pub trait TwckPin<TWI> {}
+
+/// Pins a TWI can be constructed from, as `(twd, twck)`
+pub trait Pins<TWI> {}
+
+impl<TWI, TWD, TWCK> Pins<TWI> for (TWD, TWCK)
+where
+    TWD: TwdPin<TWI>,
+    TWCK: TwckPin<TWI>,
+{
+}
+
+impl TwdPin<TWI0> for PA17<Alternate<PeriphA>> {}
+impl TwckPin<TWI0> for PA18<Alternate<PeriphA>> {}
+
+impl TwdPin<TWI1> for PB12<Alternate<PeriphA>> {}
+impl TwckPin<TWI1> for PB13<Alternate<PeriphA>> {}
+
+/// TWI master abstraction
+pub struct Twi<TWI, PINS> {
+    twi: TWI,
+    pins: PINS,
+    clocks: Clocks,
+    timeout: Option<u32>,
+}
+
+/// Computes `TWI_CWGR` as `(ckdiv, cldiv, chdiv)` for a bus speed
+///
+/// Each half of the clock lasts `(xxDIV * 2^CKDIV + 4)` MCK cycles. Up to 100 kHz the low
+/// and high times are equal; in fast mode the low time gets two thirds of the period to meet
+/// the 1.3 us minimum low time.
+fn clock_dividers(mck: u32, speed: u32) -> (u8, u8, u8) {
+    let period = mck / speed;
+    let (low, high) = if speed <= 100_000 {
+        (period / 2, period - period / 2)
+    } else {
+        (period * 2 / 3, period - period * 2 / 3)
+    };
+    let low = low.saturating_sub(4);
+    let high = high.saturating_sub(4);
+
+    let mut ckdiv = 0;
+    while (low.max(high) + (1 << ckdiv) - 1) >> ckdiv > 0xFF {
+        ckdiv += 1;
+    }
+    assert!(ckdiv <= 7);
+
+    let cldiv = (low + (1 << ckdiv) - 1) >> ckdiv;
+    let chdiv = (high + (1 << ckdiv) - 1) >> ckdiv;
+
+    (ckdiv as u8, cldiv as u8, chdiv as u8)
+}
+
+macro_rules! hal {
+    ($($TWIX:ident: ($twiX:ident, $pid:ident, $PIOX:ident, $twd:expr, $twck:expr),)+) => {
+        $(
+            impl<PINS> Twi<$TWIX, PINS> {
+                /// Configures a TWI peripheral as a bus master
+                ///
+                /// `speed` is typically 100 kHz (standard mode) or 400 kHz (fast mode).
+                pub fn $twiX<F>(
+                    twi: $TWIX,
+                    pins: PINS,
+                    speed: F,
+                    clocks: Clocks,
+                    pmc: &mut Pmc,
+                ) -> Self
+                where
+                    PINS: Pins<$TWIX>,
+                    F: Into<Hertz>,
+                {
+                    pmc.enable_peripheral(PeripheralId::$pid);
+
+                    twi.cr.write_with_zero(|w| w.swrst().set_bit());
+                    // reading RHR after the reset discards a stale received byte
+                    twi.rhr.read();
+
+                    let (ckdiv, cldiv, chdiv) = clock_dividers(clocks.mck().0, speed.into().0);
+                    twi.cwgr.write_with_zero(|w| unsafe {
+                        w.ckdiv().bits(ckdiv).cldiv().bits(cldiv).chdiv().bits(chdiv)
+                    });
+
+                    twi.cr.write_with_zero(|w| w.svdis().set_bit().msen().set_bit());
+
+                    Twi {
+                        twi,
+                        pins,
+                        clocks,
+                        timeout: None,
+                    }
+                }
+
+                /// Disables the TWI and releases the peripheral and pins
+                pub fn free(self) -> ($TWIX, PINS) {
+                    self.twi.cr.write_with_zero(|w| w.msdis().set_bit());
+                    (self.twi, self.pins)
+                }
+
+                /// Bounds the waits of the blocking operations to `timeout` reads of `TWI_SR`
+                ///
+                /// `None` (the default) waits forever.
+                pub fn set_timeout(&mut self, timeout: Option<u32>) {
+                    self.timeout = timeout;
+                }
+
+                /// Frees the bus from a slave holding SDA low
+                ///
+                /// TWCK is taken over as an open-drain GPIO and pulsed up to 9 times at about
+                /// 100 kHz until the slave releases SDA, then a STOP condition is generated by
+                /// hand. The pins are handed back to the TWI, which is reset and re-enabled
+                /// with its clock settings.
+                pub fn recover_bus(&mut self) {
+                    // NOTE(unsafe) the TWD and TWCK pins are owned through `self.pins`
+                    let pio = unsafe { &*$PIOX::ptr() };
+                    let twd = 1 << $twd;
+                    let twck = 1 << $twck;
+                    let half_period = self.clocks.mck().0 / 200_000;
+
+                    unsafe {
+                        pio.sodr.write_with_zero(|w| w.bits(twd | twck));
+                        pio.mder.write_with_zero(|w| w.bits(twd | twck));
+                        pio.oer.write_with_zero(|w| w.bits(twd | twck));
+                        pio.per.write_with_zero(|w| w.bits(twd | twck));
+
+                        for _ in 0..9 {
+                            if pio.pdsr.read().bits() & twd != 0 {
+                                break;
+                            }
+                            pio.codr.write_with_zero(|w| w.bits(twck));
+                            asm::delay(half_period);
+                            pio.sodr.write_with_zero(|w| w.bits(twck));
+                            asm::delay(half_period);
+                        }
+
+                        // STOP: SDA rises while SCL is high
+                        pio.codr.write_with_zero(|w| w.bits(twd));
+                        asm::delay(half_period);
+                        pio.sodr.write_with_zero(|w| w.bits(twd));
+                        asm::delay(half_period);
+
+                        pio.pdr.write_with_zero(|w| w.bits(twd | twck));
+                    }
+
+                    let cwgr = self.twi.cwgr.read().bits();
+                    self.twi.cr.write_with_zero(|w| w.swrst().set_bit());
+                    self.twi.rhr.read();
+                    self.twi.cwgr.write_with_zero(|w| unsafe { w.bits(cwgr) });
+                    self.twi.cr.write_with_zero(|w| w.svdis().set_bit().msen().set_bit());
+                }
+
+                /// Waits for `TXRDY`, `RXRDY` or `TXCOMP`, failing on NACK or arbitration loss
+                ///
+                /// Flags are checked from a single read of `TWI_SR`, since reading it clears
+                /// `NACK` and `ARBLST`.
+                fn wait<P>(&self, ready: P) -> Result<(), TwiError>
+                where
+                    P: Fn(&sam3x8e::$twiX::sr::R) -> bool,
+                {
+                    let mut polls = 0u32;
+                    loop {
+                        if let Some(timeout) = self.timeout {
+                            if polls == timeout {
+                                return Err(TwiError::Timeout);
+                            }
+                            polls += 1;
+                        }
+
+                        let sr = self.twi.sr.read();
+                        if sr.nack().bit_is_set() {
+                            return Err(TwiError::Nack);
+                        }
+                        if sr.arblst().bit_is_set() {
+                            return Err(TwiError::ArbitrationLost);
+                        }
+                        if ready(&sr) {
+                            return Ok(());
+                        }
+                    }
+                }
+
+                /// Writes to a device, starting with the bytes of an internal address
+                ///
+                /// On the wire the internal address bytes are ordinary data bytes, so `internal`
+                /// may have any length here.
+                pub fn write_to(
+                    &mut self,
+                    address: Address,
+                    internal: &[u8],
+                    bytes: &[u8],
+                ) -> Result<(), TwiError> {
+                    self.set_address(address, &[], false)?;
+
+                    if internal.is_empty() && bytes.is_empty() {
+                        self.twi.cr.write_with_zero(|w| w.quick().set_bit());
+                        return self.wait(|sr| sr.txcomp().bit_is_set());
+                    }
+
+                    // the first write to THR starts the transfer
+                    for byte in internal.iter().chain(bytes) {
+                        self.twi.thr.write_with_zero(|w| unsafe { w.txdata().bits(*byte) });
+                        self.wait(|sr| sr.txrdy().bit_is_set())?;
+                    }
+
+                    self.twi.cr.write_with_zero(|w| w.stop().set_bit());
+                    self.wait(|sr| sr.txcomp().bit_is_set())
+                }
+
+                /// Reads from a device at an internal address
+                ///
+                /// The internal address bytes (most significant first) are written before a
+                /// repeated start and the read. Up to 3 bytes are supported, or 2 with a 10-bit
+                /// device address.
+                pub fn read_from(
+                    &mut self,
+                    address: Address,
+                    internal: &[u8],
+                    buffer: &mut [u8],
+                ) -> Result<(), TwiError> {
+                    self.set_address(address, internal, true)?;
+
+                    let last = match buffer.len() {
+                        0 => return Ok(()),
+                        len => len - 1,
+                    };
+
+                    if last == 0 {
+                        // STOP must be requested together with START for a single byte
+                        self.twi.cr.write_with_zero(|w| w.start().set_bit().stop().set_bit());
+                    } else {
+                        self.twi.cr.write_with_zero(|w| w.start().set_bit());
+                    }
+
+                    for (i, byte) in buffer.iter_mut().enumerate() {
+                        if i == last && last != 0 {
+                            // STOP is sent after the byte currently being received
+                            self.twi.cr.write_with_zero(|w| w.stop().set_bit());
+                        }
+                        self.wait(|sr| sr.rxrdy().bit_is_set())?;
+                        *byte = self.twi.rhr.read().rxdata().bits();
+                    }
+
+                    self.wait(|sr| sr.txcomp().bit_is_set())
+                }
+
+                /// Programs `TWI_MMR` and `TWI_IADR` for a transfer
+                ///
+                /// A 10-bit address goes out as `0b11110` followed by its two upper bits in
+                /// `DADR`, and its low byte as the first internal address byte.
+                fn set_address(
+                    &mut self,
+                    address: Address,
+                    internal: &[u8],
+                    read: bool,
+                ) -> Result<(), TwiError> {
+                    let (dadr, mut iadr, mut iadrsz) = match address {
+                        Address::SevenBit(addr) => (addr & 0x7F, 0u32, 0),
+                        Address::TenBit(addr) => {
+                            (0x78 | ((addr >> 8) & 0x3) as u8, u32::from(addr as u8), 1)
+                        }
+                    };
+
+                    if iadrsz + internal.len() > 3 {
+                        return Err(TwiError::InternalAddressTooLong);
+                    }
+                    for byte in internal {
+                        iadr = (iadr << 8) | u32::from(*byte);
+                        iadrsz += 1;
+                    }
+
+                    self.twi.mmr.write_with_zero(|w| unsafe {
+                        w.dadr().bits(dadr).mread().bit(read).iadrsz().bits(iadrsz as u8)
+                    });
+                    self.twi.iadr.write_with_zero(|w| unsafe { w.iadr().bits(iadr) });
+
+                    Ok(())
+                }
+            }
+
+            impl<PINS> Write for Twi<$TWIX, PINS> {
+                type Error = TwiError;
+
+                fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), TwiError> {
+                    self.write_to(Address::SevenBit(addr), &[], bytes)
+                }
+            }
+
+            impl<PINS> Read for Twi<$TWIX, PINS> {
+                type Error = TwiError;
+
+                fn read(&mut self, addr: u8, buffer: &mut [u8]) -> Result<(), TwiError> {
+                    self.read_from(Address::SevenBit(addr), &[], buffer)
+                }
+            }
+
+            impl<PINS> WriteRead for Twi<$TWIX, PINS> {
+                type Error = TwiError;
+
+                fn write_read(
+                    &mut self,
+                    addr: u8,
+                    bytes: &[u8],
+                    buffer: &mut [u8],
+                ) -> Result<(), TwiError> {
+                    if bytes.is_empty() || bytes.len() > 3 {
+                        self.write(addr, bytes)?;
+                        return self.read(addr, buffer);
+                    }
+
+                    // the bytes to write go out as the internal address
+                    self.read_from(Address::SevenBit(addr), bytes, buffer)
+                }
+            }
+        )+
+    }
+}
+
+hal! {
+    TWI0: (twi0, TWI0, PIOA, 17, 18),
+    TWI1: (twi1, TWI1, PIOB, 12, 13),
+}
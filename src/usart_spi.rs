@@ -0,0 +1,157 @@
+//! USART in SPI master mode
+//!
+//! The USARTs can act as an SPI master (`US_MR.USART_MODE = SPI_MASTER`), which gives a
+//! second SPI bus when SPI0 is taken. The transmit line becomes MOSI, the receive line MISO
+//! and the USART clock line SPCK:
+//!
+//! | USART  | SCK        | MOSI (TXD) | MISO (RXD) |
+//! |--------|------------|------------|------------|
+//! | USART0 | PA17 (B)   | PA11 (A)   | PA10 (A)   |
+//! | USART1 | PA16 (A)   | PA13 (A)   | PA12 (A)   |
+//! | USART2 | PB24 (A)   | PB20 (A)   | PB21 (A)   |
+//!
+//! SCK0 shares PA17 with TWD0, so USART0 can't be used as an SPI master together with TWI0.
+//! USART3's clock line is on PIOE, which the SAM3X8E doesn't bond out.
+//!
+//! Transfers are 8 bits wide, most significant bit first. The chip select is left to a GPIO
+//! pin driven by the application. The bit rate is `MCK / CD` (`US_BRGR.CD`), with `CD` at
+//! least 6.
+
+use crate::hal;
+use crate::hal::spi::{Mode, Phase, Polarity};
+use crate::pmc::{Clocks, PeripheralId, Pmc};
+use crate::serial::{RxPin, SckPin, TxPin};
+use crate::spi::SpiError;
+use crate::time::Hertz;
+use sam3x8e::{USART0, USART1, USART2};
+
+/// Bit of `US_MR.CLKO`, which drives SCK from the USART
+const CLKO: u32 = 1 << 18;
+
+/// Pins a USART can be constructed from in SPI mode, as `(sck, miso, mosi)`
+///
+/// MISO is the USART's RXD pin and MOSI its TXD pin.
+pub trait Pins<USART> {}
+
+impl<USART, SCK, MISO, MOSI> Pins<USART> for (SCK, MISO, MOSI)
+where
+    SCK: SckPin<USART>,
+    MISO: RxPin<USART>,
+    MOSI: TxPin<USART>,
+{
+}
+
+/// USART in SPI master mode
+pub struct UsartSpi<USART, PINS> {
+    usart: USART,
+    pins: PINS,
+}
+
+macro_rules! hal {
+    ($($USARTX:ident: ($usartX:ident, $pid:ident),)+) => {
+        $(
+            impl<PINS> UsartSpi<$USARTX, PINS> {
+                /// Configures a USART as an SPI master doing 8-bit transfers
+                ///
+                /// `CD` is rounded up so that the bit rate doesn't exceed `freq`. Fails with
+                /// `SpiError::InvalidFrequency` if `freq` is zero or below `MCK / 65535`.
+                pub fn $usartX<F>(
+                    usart: $USARTX,
+                    pins: PINS,
+                    mode: Mode,
+                    freq: F,
+                    clocks: Clocks,
+                    pmc: &mut Pmc,
+                ) -> Result<Self, SpiError>
+                where
+                    PINS: Pins<$USARTX>,
+                    F: Into<Hertz>,
+                {
+                    let freq = freq.into().0;
+                    if freq == 0 {
+                        return Err(SpiError::InvalidFrequency);
+                    }
+                    let (mck, freq) = (u64::from(clocks.mck().0), u64::from(freq));
+                    let cd = ((mck + freq - 1) / freq).max(6);
+                    if cd > 0xFFFF {
+                        return Err(SpiError::InvalidFrequency);
+                    }
+
+                    pmc.enable_peripheral(PeripheralId::$pid);
+
+                    // reset and disable the receiver and transmitter
+                    usart.cr_spi_mode().write_with_zero(|w| {
+                        w.rstrx().set_bit()
+                            .rsttx().set_bit()
+                            .rxdis().set_bit()
+                            .txdis().set_bit()
+                            .rststa().set_bit()
+                    });
+
+                    usart.mr_spi_mode().write_with_zero(|w| unsafe {
+                        w.bits(CLKO)
+                            .usart_mode().spi_master()
+                            .usclks().mck()
+                            .chrl()._8_bit()
+                            .cpol().bit(mode.polarity == Polarity::IdleHigh)
+                            .cpha().bit(mode.phase == Phase::CaptureOnFirstTransition)
+                    });
+
+                    usart.brgr.write_with_zero(|w| unsafe { w.cd().bits(cd as u16) });
+
+                    usart
+                        .cr_spi_mode()
+                        .write_with_zero(|w| w.rxen().set_bit().txen().set_bit());
+
+                    Ok(UsartSpi { usart, pins })
+                }
+
+                /// Disables the USART and releases the peripheral and pins
+                pub fn free(self) -> ($USARTX, PINS) {
+                    self.usart
+                        .cr_spi_mode()
+                        .write_with_zero(|w| w.rxdis().set_bit().txdis().set_bit());
+                    (self.usart, self.pins)
+                }
+            }
+
+            impl<PINS> hal::spi::FullDuplex<u8> for UsartSpi<$USARTX, PINS> {
+                type Error = SpiError;
+
+                fn read(&mut self) -> nb::Result<u8, SpiError> {
+                    let csr = self.usart.csr_spi_mode().read();
+
+                    if csr.ovre().bit_is_set() {
+                        self.usart.cr_spi_mode().write_with_zero(|w| w.rststa().set_bit());
+                        Err(nb::Error::Other(SpiError::Overrun))
+                    } else if csr.rxrdy().bit_is_set() {
+                        Ok(self.usart.rhr.read().rxchr().bits() as u8)
+                    } else {
+                        Err(nb::Error::WouldBlock)
+                    }
+                }
+
+                fn send(&mut self, byte: u8) -> nb::Result<(), SpiError> {
+                    if self.usart.csr_spi_mode().read().txrdy().bit_is_set() {
+                        self.usart
+                            .thr
+                            .write_with_zero(|w| unsafe { w.txchr().bits(u16::from(byte)) });
+                        Ok(())
+                    } else {
+                        Err(nb::Error::WouldBlock)
+                    }
+                }
+            }
+
+            impl<PINS> hal::blocking::spi::transfer::Default<u8> for UsartSpi<$USARTX, PINS> {}
+
+            impl<PINS> hal::blocking::spi::write::Default<u8> for UsartSpi<$USARTX, PINS> {}
+        )+
+    }
+}
+
+hal! {
+    USART0: (usart0, USART0),
+    USART1: (usart1, USART1),
+    USART2: (usart2, USART2),
+}
@@ -0,0 +1,483 @@
+//! USB On-The-Go High Speed controller (UOTGHS) in device mode
+//!
+//! The Due's native USB port is wired to the UOTGHS. Its transceiver runs from the 480 MHz
+//! UTMI PLL, so the clocks must be frozen with `CFGR::enable_upll` (which needs a 12 MHz
+//! crystal) before the controller can be used.
+//!
+//! `UsbBus` implements the `usb-device` bus trait, limited to full speed: wrap it in a
+//! `UsbBusAllocator` to build a `UsbDevice` and its classes. Control, bulk and interrupt
+//! endpoints are supported; isochronous endpoints aren't.
+//!
+//! The controller has ten endpoints, each working in a single direction apart from the
+//! control endpoint 0, so an IN and an OUT endpoint can't share a number. Each endpoint gets
+//! one bank of the 4 KB DPRAM, read and written through its 32 KB FIFO window at
+//! `0x2018_0000 + 0x8000 * n`.
+//!
+//! The device is polled: `UsbDevice::poll` should be called from the main loop or from the
+//! `UOTGHS` interrupt handler, which fires on a bus reset, suspend or resume, on received
+//! packets and on completed IN transfers.
+
+use core::cell::Cell;
+use core::ptr;
+
+use cortex_m::interrupt::{self, Mutex};
+use usb_device::bus::PollResult;
+use usb_device::endpoint::{EndpointAddress, EndpointType};
+use usb_device::{Result, UsbDirection, UsbError};
+
+use crate::pmc::{self, ClockError, Clocks, PeripheralId, Pmc};
+use sam3x8e::UOTGHS;
+
+/// Number of device endpoints
+const ENDPOINTS: usize = 10;
+
+/// Size of the DPRAM shared by the endpoint banks
+const DPRAM_SIZE: u16 = 4096;
+
+/// FIFO window of endpoint 0, the window of endpoint `n` is `FIFO_SIZE * n` further
+const FIFO_BASE: usize = 0x2018_0000;
+const FIFO_SIZE: usize = 0x8000;
+
+/// Largest packet of a full-speed control endpoint
+const MAX_CONTROL_PACKET: u16 = 64;
+
+/// Largest endpoint bank (`UOTGHS_DEVEPTCFGx.EPSIZE`)
+const MAX_PACKET: u16 = 1024;
+
+// Bits of UOTGHS_DEVISR/DEVICR/DEVIER/DEVIDR
+const SUSP: u32 = 1 << 0;
+const EORST: u32 = 1 << 3;
+const WAKEUP: u32 = 1 << 4;
+const PEP_0: u32 = 1 << 12;
+
+/// Configuration of an allocated endpoint
+#[derive(Clone, Copy)]
+struct Endpoint {
+    ep_type: EndpointType,
+    /// Direction of a non-control endpoint, `In` for the control endpoint
+    direction: UsbDirection,
+    /// Bank size, a power of two from 8 to 1024 bytes
+    size: u16,
+}
+
+impl Endpoint {
+    /// Returns `UOTGHS_DEVEPTCFGx.EPTYPE`
+    fn eptype(&self) -> u8 {
+        match self.ep_type {
+            EndpointType::Control => 0,
+            EndpointType::Isochronous => 1,
+            EndpointType::Bulk => 2,
+            EndpointType::Interrupt => 3,
+        }
+    }
+
+    /// Returns `UOTGHS_DEVEPTCFGx.EPSIZE`, the bank size as `8 << EPSIZE`
+    fn epsize(&self) -> u8 {
+        (self.size.trailing_zeros() - 3) as u8
+    }
+}
+
+/// USB device bus on the UOTGHS
+pub struct UsbBus {
+    uotghs: UOTGHS,
+    endpoints: [Option<Endpoint>; ENDPOINTS],
+    /// Endpoints with an IN packet written and not reported as sent yet
+    in_pending: Mutex<Cell<u16>>,
+}
+
+// NOTE(unsafe) the registers are only accessed through `&self` with single stores to the
+// set/clear registers, and the shared state is behind a `Mutex`
+unsafe impl Sync for UsbBus {}
+
+impl UsbBus {
+    /// Enables the controller in full-speed device mode, detached from the bus
+    ///
+    /// The ID pin is ignored, so the controller stays a device whatever cable is plugged in.
+    /// The device attaches to the bus when the `UsbDevice` is built. Fails if the UPLL isn't
+    /// running or if the controller clock doesn't become usable.
+    pub fn new(
+        uotghs: UOTGHS,
+        clocks: Clocks,
+        pmc: &mut Pmc,
+    ) -> core::result::Result<Self, ClockError> {
+        clocks.validate(PeripheralId::UOTGHS)?;
+
+        pmc.enable_peripheral(PeripheralId::UOTGHS);
+        pmc.enable_usb_clock();
+
+        uotghs.ctrl.write(|w| {
+            w.uide()
+                .clear_bit()
+                .uimod()
+                .device()
+                .otgpade()
+                .set_bit()
+                .usbe()
+                .set_bit()
+                .frzclk()
+                .clear_bit()
+        });
+        pmc::wait_ready(|| uotghs.sr.read().clkusable().bit_is_set())?;
+
+        uotghs
+            .devctrl
+            .write(|w| w.spdconf().forced_fs().detach().set_bit());
+
+        Ok(UsbBus {
+            uotghs,
+            endpoints: [None; ENDPOINTS],
+            in_pending: Mutex::new(Cell::new(0)),
+        })
+    }
+
+    /// Returns whether the host powers the bus (`UOTGHS_SR.VBUS`)
+    pub fn is_vbus_present(&self) -> bool {
+        self.uotghs.sr.read().vbus().bit_is_set()
+    }
+
+    fn eptisr(&self, ep: usize) -> &sam3x8e::uotghs::DEVEPTISR {
+        unsafe { &self.uotghs.deveptisr.deveptisr[ep] }
+    }
+
+    fn epticr(&self, ep: usize) -> &sam3x8e::uotghs::DEVEPTICR {
+        unsafe { &self.uotghs.devepticr.devepticr[ep] }
+    }
+
+    fn eptimr(&self, ep: usize) -> &sam3x8e::uotghs::DEVEPTIMR {
+        unsafe { &self.uotghs.deveptimr.deveptimr[ep] }
+    }
+
+    fn eptier(&self, ep: usize) -> &sam3x8e::uotghs::DEVEPTIER {
+        unsafe { &self.uotghs.deveptier.deveptier[ep] }
+    }
+
+    fn eptidr(&self, ep: usize) -> &sam3x8e::uotghs::DEVEPTIDR {
+        unsafe { &self.uotghs.deveptidr.deveptidr[ep] }
+    }
+
+    /// Returns the endpoint at `ep_addr`, if it is allocated in that direction
+    fn endpoint(&self, ep_addr: EndpointAddress) -> Result<Endpoint> {
+        match self.endpoints.get(ep_addr.index()).copied().flatten() {
+            Some(ep)
+                if ep.ep_type == EndpointType::Control || ep.direction == ep_addr.direction() =>
+            {
+                Ok(ep)
+            }
+            _ => Err(UsbError::InvalidEndpoint),
+        }
+    }
+
+    /// Allocates the banks of the endpoints and enables them, as a USB reset disables all
+    /// endpoints but endpoint 0
+    fn configure_endpoints(&self) {
+        interrupt::free(|cs| self.in_pending.borrow(cs).set(0));
+
+        for (i, ep) in self.endpoints.iter().enumerate() {
+            let ep = match ep {
+                Some(ep) => ep,
+                None => continue,
+            };
+
+            self.uotghs.deveptcfg[i].write(|w| {
+                w.epsize()
+                    .bits(ep.epsize())
+                    .epbk()
+                    ._1_bank()
+                    .epdir()
+                    .bit(ep.ep_type != EndpointType::Control && ep.direction == UsbDirection::In)
+                    .eptype()
+                    .bits(ep.eptype())
+                    .alloc()
+                    .set_bit()
+            });
+            self.uotghs
+                .devept
+                .modify(|r, w| unsafe { w.bits(r.bits() | 1 << i) });
+
+            if ep.ep_type == EndpointType::Control {
+                self.eptier(i)
+                    .write_with_zero(|w| w.rxstpes().set_bit().rxoutes().set_bit());
+            } else if ep.direction == UsbDirection::Out {
+                self.eptier(i).write_with_zero(|w| w.rxoutes().set_bit());
+            }
+            self.uotghs
+                .devier
+                .write_with_zero(|w| unsafe { w.bits(PEP_0 << i) });
+        }
+    }
+
+    /// Copies the received packet of an endpoint out of its FIFO
+    fn read_fifo(&self, ep: usize, buf: &mut [u8]) -> Result<usize> {
+        let count = usize::from(self.eptisr(ep).read().byct().bits());
+        if count > buf.len() {
+            return Err(UsbError::BufferOverflow);
+        }
+
+        let fifo = (FIFO_BASE + FIFO_SIZE * ep) as *const u8;
+        for (i, byte) in buf[..count].iter_mut().enumerate() {
+            *byte = unsafe { ptr::read_volatile(fifo.add(i)) };
+        }
+        Ok(count)
+    }
+}
+
+impl usb_device::bus::UsbBus for UsbBus {
+    fn alloc_ep(
+        &mut self,
+        ep_dir: UsbDirection,
+        ep_addr: Option<EndpointAddress>,
+        ep_type: EndpointType,
+        max_packet_size: u16,
+        _interval: u8,
+    ) -> Result<EndpointAddress> {
+        if ep_type == EndpointType::Isochronous {
+            return Err(UsbError::Unsupported);
+        }
+
+        let size = max_packet_size.max(8).next_power_of_two();
+        if size > MAX_PACKET || (ep_type == EndpointType::Control && size > MAX_CONTROL_PACKET) {
+            return Err(UsbError::EndpointMemoryOverflow);
+        }
+
+        // Both directions of the control endpoint share endpoint 0
+        if ep_type == EndpointType::Control {
+            if matches!(ep_addr, Some(addr) if addr.index() != 0) {
+                return Err(UsbError::InvalidEndpoint);
+            }
+            return match self.endpoints[0] {
+                Some(ep) if ep.ep_type == EndpointType::Control && ep.size == size => {
+                    Ok(EndpointAddress::from_parts(0, ep_dir))
+                }
+                Some(_) => Err(UsbError::InvalidEndpoint),
+                None => {
+                    self.endpoints[0] = Some(Endpoint {
+                        ep_type,
+                        direction: UsbDirection::In,
+                        size,
+                    });
+                    Ok(EndpointAddress::from_parts(0, ep_dir))
+                }
+            };
+        }
+
+        let index = match ep_addr {
+            Some(addr) if addr.index() == 0 || addr.index() >= ENDPOINTS => {
+                return Err(UsbError::InvalidEndpoint)
+            }
+            Some(addr) if self.endpoints[addr.index()].is_some() => {
+                return Err(UsbError::InvalidEndpoint)
+            }
+            Some(addr) => addr.index(),
+            None => (1..ENDPOINTS)
+                .find(|&i| self.endpoints[i].is_none())
+                .ok_or(UsbError::EndpointOverflow)?,
+        };
+
+        let used: u16 = self.endpoints.iter().flatten().map(|ep| ep.size).sum();
+        if used + size > DPRAM_SIZE {
+            return Err(UsbError::EndpointMemoryOverflow);
+        }
+
+        self.endpoints[index] = Some(Endpoint {
+            ep_type,
+            direction: ep_dir,
+            size,
+        });
+        Ok(EndpointAddress::from_parts(index, ep_dir))
+    }
+
+    fn enable(&mut self) {
+        self.configure_endpoints();
+        self.uotghs
+            .devier
+            .write_with_zero(|w| unsafe { w.bits(EORST | SUSP) });
+        self.uotghs.devctrl.modify(|_, w| w.detach().clear_bit());
+    }
+
+    fn reset(&self) {
+        self.uotghs
+            .devctrl
+            .modify(|_, w| unsafe { w.uadd().bits(0).adden().clear_bit() });
+        self.configure_endpoints();
+    }
+
+    /// Sets the address assigned by the host (`UOTGHS_DEVCTRL.UADD`/`ADDEN`)
+    ///
+    /// `usb-device` calls this once the status stage of SET_ADDRESS is done. UADD and ADDEN
+    /// can't be written at once, so the address is written first and enabled after.
+    fn set_device_address(&self, addr: u8) {
+        self.uotghs
+            .devctrl
+            .modify(|_, w| unsafe { w.uadd().bits(addr & 0x7F).adden().clear_bit() });
+        self.uotghs.devctrl.modify(|_, w| w.adden().set_bit());
+    }
+
+    fn write(&self, ep_addr: EndpointAddress, buf: &[u8]) -> Result<usize> {
+        let ep = self.endpoint(ep_addr)?;
+        if !ep_addr.is_in() {
+            return Err(UsbError::InvalidEndpoint);
+        }
+        if buf.len() > usize::from(ep.size) {
+            return Err(UsbError::BufferOverflow);
+        }
+
+        let i = ep_addr.index();
+        if self.eptisr(i).read().txini().bit_is_clear() {
+            return Err(UsbError::WouldBlock);
+        }
+
+        let fifo = (FIFO_BASE + FIFO_SIZE * i) as *mut u8;
+        for (j, &byte) in buf.iter().enumerate() {
+            unsafe { ptr::write_volatile(fifo.add(j), byte) };
+        }
+
+        interrupt::free(|cs| {
+            let pending = self.in_pending.borrow(cs);
+            pending.set(pending.get() | 1 << i);
+        });
+        // Clearing TXINI sends the bank of a control endpoint, the other endpoints also need
+        // FIFOCON cleared
+        self.epticr(i).write_with_zero(|w| w.txinic().set_bit());
+        if ep.ep_type != EndpointType::Control {
+            self.eptidr(i).write_with_zero(|w| w.fifoconc().set_bit());
+        }
+        self.eptier(i).write_with_zero(|w| w.txines().set_bit());
+
+        Ok(buf.len())
+    }
+
+    fn read(&self, ep_addr: EndpointAddress, buf: &mut [u8]) -> Result<usize> {
+        let ep = self.endpoint(ep_addr)?;
+        if !ep_addr.is_out() {
+            return Err(UsbError::InvalidEndpoint);
+        }
+
+        let i = ep_addr.index();
+        let isr = self.eptisr(i).read();
+        if ep.ep_type == EndpointType::Control && isr.rxstpi().bit_is_set() {
+            let count = self.read_fifo(i, buf)?;
+            self.epticr(i).write_with_zero(|w| w.rxstpic().set_bit());
+            Ok(count)
+        } else if isr.rxouti().bit_is_set() {
+            let count = self.read_fifo(i, buf)?;
+            self.epticr(i).write_with_zero(|w| w.rxoutic().set_bit());
+            if ep.ep_type != EndpointType::Control {
+                self.eptidr(i).write_with_zero(|w| w.fifoconc().set_bit());
+            }
+            Ok(count)
+        } else {
+            Err(UsbError::WouldBlock)
+        }
+    }
+
+    fn set_stalled(&self, ep_addr: EndpointAddress, stalled: bool) {
+        let i = ep_addr.index();
+        if i >= ENDPOINTS {
+            return;
+        }
+
+        if stalled {
+            self.eptier(i).write_with_zero(|w| w.stallrqs().set_bit());
+        } else {
+            self.eptidr(i).write_with_zero(|w| w.stallrqc().set_bit());
+            self.eptier(i).write_with_zero(|w| w.rstdts().set_bit());
+        }
+    }
+
+    fn is_stalled(&self, ep_addr: EndpointAddress) -> bool {
+        let i = ep_addr.index();
+        i < ENDPOINTS && self.eptimr(i).read().stallrq().bit_is_set()
+    }
+
+    /// Freezes the controller clock; only the wake-up detection keeps running
+    fn suspend(&self) {
+        self.uotghs
+            .devidr
+            .write_with_zero(|w| unsafe { w.bits(SUSP) });
+        self.uotghs
+            .devicr
+            .write_with_zero(|w| unsafe { w.bits(WAKEUP) });
+        self.uotghs
+            .devier
+            .write_with_zero(|w| unsafe { w.bits(WAKEUP) });
+        self.uotghs.ctrl.modify(|_, w| w.frzclk().set_bit());
+    }
+
+    fn resume(&self) {
+        self.uotghs.ctrl.modify(|_, w| w.frzclk().clear_bit());
+        // The clock was usable before suspend, so it comes back within a few cycles; on a
+        // timeout the next poll finds the controller frozen and waits for another wake-up
+        let _ = pmc::wait_ready(|| self.uotghs.sr.read().clkusable().bit_is_set());
+        self.uotghs
+            .devicr
+            .write_with_zero(|w| unsafe { w.bits(WAKEUP | SUSP) });
+        self.uotghs
+            .devidr
+            .write_with_zero(|w| unsafe { w.bits(WAKEUP) });
+        self.uotghs
+            .devier
+            .write_with_zero(|w| unsafe { w.bits(SUSP) });
+    }
+
+    fn poll(&self) -> PollResult {
+        let isr = self.uotghs.devisr.read().bits();
+
+        if isr & EORST != 0 {
+            self.uotghs
+                .devicr
+                .write_with_zero(|w| unsafe { w.bits(EORST) });
+            return PollResult::Reset;
+        }
+        if self.uotghs.ctrl.read().frzclk().bit_is_set() {
+            // Suspended: only a wake-up is of interest, the clock is restarted by `resume`
+            return if isr & WAKEUP != 0 {
+                PollResult::Resume
+            } else {
+                PollResult::None
+            };
+        }
+        if isr & SUSP != 0 {
+            self.uotghs
+                .devicr
+                .write_with_zero(|w| unsafe { w.bits(SUSP) });
+            return PollResult::Suspend;
+        }
+
+        let mut ep_out = 0;
+        let mut ep_in_complete = 0;
+        let mut ep_setup = 0;
+        interrupt::free(|cs| {
+            let pending = self.in_pending.borrow(cs);
+            for (i, ep) in self.endpoints.iter().enumerate() {
+                let ep = match ep {
+                    Some(ep) => ep,
+                    None => continue,
+                };
+                let ep_isr = self.eptisr(i).read();
+                let bit = 1 << i;
+
+                if ep.ep_type == EndpointType::Control && ep_isr.rxstpi().bit_is_set() {
+                    ep_setup |= bit;
+                }
+                if ep_isr.rxouti().bit_is_set() {
+                    ep_out |= bit;
+                }
+                if pending.get() & bit != 0 && ep_isr.txini().bit_is_set() {
+                    ep_in_complete |= bit;
+                    pending.set(pending.get() & !bit);
+                    self.eptidr(i).write_with_zero(|w| w.txinec().set_bit());
+                }
+            }
+        });
+
+        if ep_out | ep_in_complete | ep_setup == 0 {
+            PollResult::None
+        } else {
+            PollResult::Data {
+                ep_out,
+                ep_in_complete,
+                ep_setup,
+            }
+        }
+    }
+}
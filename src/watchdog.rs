@@ -0,0 +1,108 @@
+//! Watchdog Timer (WDT)
+//!
+//! The watchdog counts down from a 12-bit value at SLCK / 128 (256 Hz from the 32.768 kHz
+//! crystal), so timeouts go from about 4 ms to 16 s. It is **enabled out of reset** with the
+//! longest timeout: an application that neither feeds nor disables it resets every 16 s.
+//!
+//! `WDT_MR` can only be written once after a reset; later writes are ignored. A `Watchdog`
+//! therefore accepts a single configuration, either `start` or `disable`, and panics if
+//! asked for a second one. During development, call `disable` right at startup to get rid
+//! of the watchdog altogether.
+//!
+//! The watchdog is halted while the core is in debug state or idle, so a debugger sitting
+//! on a breakpoint doesn't trigger a reset.
+
+use crate::hal;
+use sam3x8e::WDT;
+
+/// Watchdog counter clock, SLCK / 128
+const WDT_CLOCK_FREQ: u32 = 32_768 / 128;
+
+/// Largest watchdog counter value
+const MAX_WDV: u32 = 0xFFF;
+
+/// Watchdog timer
+pub struct Watchdog {
+    wdt: WDT,
+    configured: bool,
+}
+
+impl Watchdog {
+    /// Takes the watchdog, which is still running with its reset configuration
+    pub fn new(wdt: WDT) -> Self {
+        Watchdog {
+            wdt,
+            configured: false,
+        }
+    }
+
+    /// Returns whether the watchdog has underflowed since `WDT_SR` was last read
+    pub fn has_underflowed(&self) -> bool {
+        self.wdt.sr.read().wdunf().bit_is_set()
+    }
+
+    /// Releases the WDT peripheral
+    pub fn free(self) -> WDT {
+        self.wdt
+    }
+
+    fn configure(&mut self) {
+        assert!(!self.configured);
+        self.configured = true;
+    }
+}
+
+impl hal::watchdog::Watchdog for Watchdog {
+    /// Restarts the watchdog counter (`WDT_CR.WDRSTT`)
+    fn feed(&mut self) {
+        self.wdt
+            .cr
+            .write_with_zero(|w| w.key().passwd().wdrstt().set_bit());
+    }
+}
+
+impl hal::watchdog::WatchdogEnable for Watchdog {
+    /// Timeout in milliseconds
+    type Time = u32;
+
+    /// Sets the timeout and resets the processor when it expires
+    ///
+    /// The timeout is rounded down to a multiple of 1/256 s and clamped to 4 ms ..= 16 s.
+    /// This writes the write-once `WDT_MR`, so it can only be done once after a reset.
+    fn start<T>(&mut self, period: T)
+    where
+        T: Into<u32>,
+    {
+        self.configure();
+
+        let wdv = (u64::from(period.into()) * u64::from(WDT_CLOCK_FREQ) / 1_000)
+            .max(1)
+            .min(u64::from(MAX_WDV)) as u16;
+
+        // WDD = WDV: the watchdog can be fed at any time
+        self.wdt.mr.write_with_zero(|w| unsafe {
+            w.wdv()
+                .bits(wdv)
+                .wdd()
+                .bits(wdv)
+                .wdrsten()
+                .set_bit()
+                .wddbghlt()
+                .set_bit()
+                .wdidlehlt()
+                .set_bit()
+        });
+    }
+}
+
+impl hal::watchdog::WatchdogDisable for Watchdog {
+    /// Disables the watchdog (`WDT_MR.WDDIS`)
+    ///
+    /// This writes the write-once `WDT_MR`, so the watchdog stays disabled until the next
+    /// reset.
+    fn disable(&mut self) {
+        self.configure();
+
+        self.wdt.mr.write_with_zero(|w| w.wddis().set_bit());
+    }
+}